@@ -1,17 +1,28 @@
 extern crate pod;
 extern crate memmap;
+#[cfg(target_os = "linux")]
+extern crate libc;
 
 use pod::Pod;
 use std::ops::DerefMut;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::collections::LinkedList;
 use std::mem;
+use std::ptr;
+use std::slice;
 use std::cell::UnsafeCell;
 use memmap::MmapMut;
 
+/// The size of the chunks handed out by `Arena::allocator`.
+pub const MAPPING_SIZE: usize = 1 << 21; // 2 MB
+
 pub struct Arena {
     owned_slices: Mutex<LinkedList<Box<[u8]>>>,
     owned_mappings: Mutex<LinkedList<MmapMut>>,
+    free_mappings: Mutex<LinkedList<MmapMut>>,
+    bytes_allocated: AtomicUsize,
+    huge_pages: bool,
 }
 
 impl Arena {
@@ -19,31 +30,70 @@ impl Arena {
         Self {
             owned_slices: Default::default(),
             owned_mappings: Default::default(),
+            free_mappings: Default::default(),
+            bytes_allocated: AtomicUsize::new(0),
+            huge_pages: false,
+        }
+    }
+    /// Like `new`, but asks the kernel to back the 2 MB chunks with transparent huge pages
+    /// (`madvise(MADV_HUGEPAGE)`). This does nothing on platforms other than Linux.
+    pub fn with_huge_pages() -> Self {
+        Self {huge_pages: true, ..Self::new()}
+    }
+    /// The number of bytes handed out to allocators since the arena was created or last reset.
+    /// Allocators take memory in whole chunks, so this includes the unused tail of each chunk.
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated.load(Ordering::Relaxed)
+    }
+    /// Invalidates everything allocated so far. The 2 MB chunks are kept, zeroed,
+    /// and handed out again by later allocations instead of being returned to the OS.
+    pub fn reset(&mut self) {
+        let owned_mappings = self.owned_mappings.get_mut().unwrap();
+        let free_mappings = self.free_mappings.get_mut().unwrap();
+        for mut map in owned_mappings.split_off(0) {
+            unsafe {
+                ptr::write_bytes(map.as_mut_ptr(), 0, map.len());
+            }
+            free_mappings.push_back(map);
         }
+        self.owned_slices.get_mut().unwrap().clear();
+        self.bytes_allocated.store(0, Ordering::Relaxed);
     }
+    // Each chunk is handed out to only one allocator, so the slices returned here never alias.
+    #[allow(clippy::mut_from_ref)]
     fn give_boxed(&self, mut memory: Box<[u8]>) -> &mut [u8] {
         let result = (&mut *memory) as *mut _;
         let mut owned_slices = self.owned_slices.lock().unwrap();
         owned_slices.push_back(memory);
         unsafe {&mut *result}
     }
+    #[allow(clippy::mut_from_ref)]
     fn give_mmap(&self, mut map: MmapMut) -> &mut [u8] {
         let result = map.deref_mut() as *mut _;
         let mut owned_mappings = self.owned_mappings.lock().unwrap();
         owned_mappings.push_back(map);
         unsafe {&mut *result}
     }
+    fn new_mmap(&self) -> MmapMut {
+        let map = MmapMut::map_anon(MAPPING_SIZE).unwrap();
+        if self.huge_pages {
+            advise_huge_pages(&map);
+        }
+        map
+    }
     fn alloc(&self, sz: usize) -> &mut [u8] {
-        if sz == 1 << 21 {
-            self.give_mmap(MmapMut::map_anon(sz).unwrap())
+        self.bytes_allocated.fetch_add(sz, Ordering::Relaxed);
+        if sz == MAPPING_SIZE {
+            let reused = self.free_mappings.lock().unwrap().pop_front();
+            self.give_mmap(reused.unwrap_or_else(|| self.new_mmap()))
         } else {
             self.give_boxed(vec![0; sz].into_boxed_slice())
         }
     }
-    pub fn allocator(&self) -> ArenaAllocator {
-        self.allocator_with_chunk_size(1 << 21) // 2 MB
+    pub fn allocator(&self) -> ArenaAllocator<'_> {
+        self.allocator_with_chunk_size(MAPPING_SIZE)
     }
-    pub fn allocator_with_chunk_size(&self, chunk_size: usize) -> ArenaAllocator {
+    pub fn allocator_with_chunk_size(&self, chunk_size: usize) -> ArenaAllocator<'_> {
         ArenaAllocator {
             arena: self,
             memory: UnsafeCell::new(self.alloc(chunk_size)),
@@ -52,6 +102,23 @@ impl Arena {
     }
 }
 
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn advise_huge_pages(map: &MmapMut) {
+    // This is only a hint, so failure (e.g. on kernels without transparent huge pages) is fine.
+    unsafe {
+        libc::madvise(map.as_ptr() as *mut libc::c_void, map.len(), libc::MADV_HUGEPAGE);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_huge_pages(_: &MmapMut) {}
+
 pub struct ArenaAllocator<'a> {
     arena: &'a Arena,
     chunk_size: usize,
@@ -60,6 +127,10 @@ pub struct ArenaAllocator<'a> {
 
 const ALIGN: usize = 8;
 
+fn round_up_to_align(x: usize) -> usize {
+    x.div_ceil(ALIGN) * ALIGN
+}
+
 impl<'a> ArenaAllocator<'a> {
     fn get_memory(&self, sz: usize) -> &'a mut [u8] {
         let memory = unsafe { &mut *self.memory.get() };
@@ -75,19 +146,100 @@ impl<'a> ArenaAllocator<'a> {
         }
     }
     pub fn alloc_one<T: Pod>(&self) -> &'a mut T {
-        assert!(ALIGN % mem::align_of::<T>() == 0);
-        let x = mem::size_of::<T>();
-        let x = x + ((!x + 1) % ALIGN); // TODO fix panic when x=0
-        let x = self.get_memory(x);
-        let x = T::ref_from_slice_mut(x);
+        assert!(ALIGN.is_multiple_of(mem::align_of::<T>()));
+        if mem::size_of::<T>() == 0 {
+            return unsafe { &mut *ptr::NonNull::dangling().as_ptr() };
+        }
+        let size = mem::size_of::<T>();
+        let x = self.get_memory(round_up_to_align(size));
+        let x = T::ref_from_slice_mut(&mut x[..size]);
         x.unwrap()
     }
     pub fn alloc_slice<T: Pod>(&self, sz: usize) -> &'a mut [T] {
-        assert!(ALIGN % mem::align_of::<T>() == 0);
-        let x = mem::size_of::<T>();
-        let x = x + ((!x + 1) % ALIGN); // TODO fix panic when x=0
-        let x = self.get_memory(x * sz);
-        let x = u8::map_slice_mut(x);
+        assert!(ALIGN.is_multiple_of(mem::align_of::<T>()));
+        if mem::size_of::<T>() == 0 {
+            return unsafe { slice::from_raw_parts_mut(ptr::NonNull::dangling().as_ptr(), sz) };
+        }
+        let size = mem::size_of::<T>() * sz;
+        let x = self.get_memory(round_up_to_align(size));
+        let x = u8::map_slice_mut(&mut x[..size]);
         x.unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Empty;
+    unsafe impl Pod for Empty {}
+
+    #[test]
+    fn zero_sized_types() {
+        let arena = Arena::new();
+        let allocator = arena.allocator();
+        let before = arena.bytes_allocated();
+        let _: &mut Empty = allocator.alloc_one();
+        let slice: &mut [Empty] = allocator.alloc_slice(100);
+        assert_eq!(slice.len(), 100);
+        let _: &mut [()] = allocator.alloc_slice(0);
+        assert_eq!(arena.bytes_allocated(), before);
+    }
+
+    #[test]
+    fn rounds_up_to_alignment() {
+        let arena = Arena::new();
+        let allocator = arena.allocator();
+        let a: &mut u8 = allocator.alloc_one();
+        let b: &mut u64 = allocator.alloc_one();
+        assert_eq!(b as *mut u64 as usize - a as *mut u8 as usize, ALIGN);
+        let c: &mut [u32] = allocator.alloc_slice(3);
+        let d: &mut u8 = allocator.alloc_one();
+        assert_eq!(c.len(), 3);
+        assert_eq!(d as *mut u8 as usize - c.as_ptr() as usize, 2 * ALIGN);
+    }
+
+    #[test]
+    fn counts_bytes() {
+        let arena = Arena::new();
+        assert_eq!(arena.bytes_allocated(), 0);
+        {
+            let allocator = arena.allocator_with_chunk_size(64);
+            assert_eq!(arena.bytes_allocated(), 64);
+            let _: &mut [u64] = allocator.alloc_slice(8);
+            assert_eq!(arena.bytes_allocated(), 64);
+            let _: &mut u64 = allocator.alloc_one();
+            assert_eq!(arena.bytes_allocated(), 128);
+            let _: &mut [u64] = allocator.alloc_slice(100);
+            assert_eq!(arena.bytes_allocated(), 928);
+        }
+    }
+
+    #[test]
+    fn reset_reuses_chunks() {
+        let mut arena = Arena::new();
+        let first = {
+            let allocator = arena.allocator();
+            let x: &mut u64 = allocator.alloc_one();
+            *x = 42;
+            x as *mut u64
+        };
+        assert_eq!(arena.bytes_allocated(), MAPPING_SIZE);
+        arena.reset();
+        assert_eq!(arena.bytes_allocated(), 0);
+        let allocator = arena.allocator();
+        let x: &mut u64 = allocator.alloc_one();
+        assert_eq!(x as *mut u64, first);
+        assert_eq!(*x, 0);
+    }
+
+    #[test]
+    fn huge_pages() {
+        let arena = Arena::with_huge_pages();
+        let allocator = arena.allocator();
+        let x: &mut [u64] = allocator.alloc_slice(1000);
+        x[999] = 1;
+        assert_eq!(x.iter().sum::<u64>(), 1);
+    }
+}
@@ -0,0 +1,97 @@
+// Plays Sashimi limited to an Elo against Sashimi at a fixed number of playouts per move, and
+// prints the limited side's score and the Elo that it implies, which is how the levels in
+// `strength.rs` are calibrated:
+//
+//     cargo run --release --example strength_match -- <elo> [games] [reference playouts]
+//
+// The first few plies of every game are sampled from the policy so that the games differ, and each
+// opening is played twice, with the colours swapped. Both sides search with one thread and a
+// small tree, since a full-sized transposition table for every move takes longer than the search.
+
+extern crate mcts;
+extern crate sashimi;
+extern crate shakmaty;
+
+use mcts::GameState;
+use sashimi::{BoardStatus, Config, Engine, Limits, State, MIN_ELO, REFERENCE_ELO, REFERENCE_PLAYOUTS};
+use shakmaty::Color;
+use std::env;
+
+const OPENING_PLIES: usize = 6;
+// Games this long are adjudicated as draws, since the 50-move rule isn't tracked.
+const MAX_PLIES: usize = 300;
+const MEMORY_LIMIT_MB: usize = 64;
+
+fn config(seed: u64, elo: Option<u32>) -> Config {
+    Config {num_threads: 1, memory_limit_mb: Some(MEMORY_LIMIT_MB), seed, elo, ..Config::default()}
+}
+
+enum Outcome {
+    Win(Color),
+    Draw,
+}
+
+fn play_game(white: &Engine, black: &Engine, opening_seed: u64, reference_playouts: u32) -> Outcome {
+    let opening = Engine::new(config(opening_seed, Some(MIN_ELO)));
+    let mut state = State::default();
+    for ply in 0..MAX_PLIES {
+        match state.outcome() {
+            BoardStatus::Checkmate => return Outcome::Win(!state.turn()),
+            BoardStatus::Stalemate => return Outcome::Draw,
+            BoardStatus::Ongoing => (),
+        }
+        // Only the kings are left.
+        if state.board().occupied().count() == 2 {
+            return Outcome::Draw;
+        }
+        let player = if ply < OPENING_PLIES {
+            &opening
+        } else if state.turn() == Color::White {
+            white
+        } else {
+            black
+        };
+        let mov = player.play(&state, Limits::Playouts(reference_playouts)).unwrap();
+        state.make_move(&mov);
+    }
+    Outcome::Draw
+}
+
+fn main() {
+    let args: Vec<u32> = env::args().skip(1).map(|x| x.parse().expect("expected a number")).collect();
+    let elo = *args.first().expect("usage: strength_match <elo> [games] [reference playouts]");
+    let games = args.get(1).cloned().unwrap_or(100);
+    let reference_playouts = args.get(2).cloned().unwrap_or(REFERENCE_PLAYOUTS);
+    let reference = Engine::new(config(0, None));
+    let (mut wins, mut draws, mut losses) = (0, 0, 0);
+    for game in 0..games {
+        let limited = Engine::new(config(game as u64, Some(elo)));
+        let limited_color = if game % 2 == 0 { Color::White } else { Color::Black };
+        let (white, black) = match limited_color {
+            Color::White => (&limited, &reference),
+            Color::Black => (&reference, &limited),
+        };
+        match play_game(white, black, (game / 2) as u64, reference_playouts) {
+            Outcome::Win(color) if color == limited_color => wins += 1,
+            Outcome::Win(_) => losses += 1,
+            Outcome::Draw => draws += 1,
+        }
+        println!("game {}: +{} ={} -{}", game + 1, wins, draws, losses);
+    }
+    let n = games as f64;
+    let score = (wins as f64 + draws as f64 / 2.0) / n;
+    // The standard error of the score, from the spread of the game results.
+    let variance = (wins as f64 * (1.0 - score).powi(2)
+        + draws as f64 * (0.5 - score).powi(2)
+        + losses as f64 * score.powi(2)) / n;
+    let error = (variance / n).sqrt();
+    let to_elo = |s: f64| {
+        let s = s.max(0.5 / n).min(1.0 - 0.5 / n);
+        -400.0 * (1.0 / s - 1.0).log10()
+    };
+    let difference = to_elo(score);
+    println!("Elo {} against {} playouts: score {:.3}, difference {:.0} ({:.0} to {:.0}), so about {:.0}",
+        elo, reference_playouts, score, difference,
+        to_elo(score - 1.96 * error), to_elo(score + 1.96 * error),
+        REFERENCE_ELO as f64 + difference);
+}
@@ -0,0 +1,329 @@
+//! `sashimi annotate`, which searches every position of the games in a PGN and writes them out again
+//! with what the engine thinks of each move.
+//!
+//! Each move is followed by a comment on the position it was played in: the expected result of the
+//! best move, from -1 to 1, the value model's win/draw/loss probabilities at the end of the
+//! principal variation, the best move and the principal variation, all from White's point of view.
+//! A move is marked `?!`, `?` or `??` when it loses more than `INACCURACY`, `MISTAKE` or `BLUNDER`
+//! in expected score (1 for a win, 1/2 for a draw) against the best move, and the engine's line
+//! follows it as a variation. The value of the move played comes from the search of the next position.
+//!
+//! The games are annotated in parallel, one search thread per game, and written in their order in
+//! the input. Variations and comments in the input are dropped.
+
+extern crate pgn_reader;
+extern crate memmap;
+
+use self::pgn_reader::{Reader, Visitor, San, Outcome, Skip, Nag};
+use self::memmap::Mmap;
+
+use shakmaty::{Color, Setup};
+use shakmaty::san::san_plus;
+use mcts::GameState;
+use config::Config;
+use engine::{Engine, Limits, Wdl};
+use state::{State, StateBuilder, Move, BoardStatus};
+use training::StartingPosition;
+use uci::{ENGINE_NAME, VERSION};
+
+use std::cmp::max;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Write, BufWriter};
+use std::iter;
+use std::mem;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+
+/// Losses of expected score above these are marked `?!`, `?` and `??`.
+const INACCURACY: f64 = 0.05;
+const MISTAKE: f64 = 0.1;
+const BLUNDER: f64 = 0.15;
+/// The size of each search tree unless `--memory-limit` says otherwise, since allocating a
+/// full-sized one for every position takes longer than a short search.
+const MEMORY_LIMIT_MB: usize = 64;
+const LINE_LENGTH: usize = 80;
+
+/// A game as read from the PGN, up to its first illegal move.
+struct Game {
+    headers: Vec<(String, String)>,
+    /// `None` if the starting position is invalid.
+    state: Option<State>,
+    moves: Vec<Move>,
+    result: String,
+}
+
+#[derive(Default)]
+struct GameReader {
+    headers: Vec<(String, String)>,
+    start: StartingPosition,
+    state: Option<StateBuilder>,
+    illegal_move: bool,
+    outcome: Option<Outcome>,
+}
+
+impl<'pgn> Visitor<'pgn> for GameReader {
+    type Result = Game;
+
+    fn begin_game(&mut self) {
+        *self = GameReader::default();
+    }
+
+    fn header(&mut self, key: &'pgn [u8], value: &'pgn [u8]) {
+        self.start.header(key, value);
+        self.headers.push((String::from_utf8_lossy(key).into_owned(), String::from_utf8_lossy(value).into_owned()));
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        self.state = self.start.state();
+        Skip(self.state.is_none())
+    }
+
+    fn san(&mut self, san: San) {
+        let state = match self.state {
+            Some(ref mut state) if !self.illegal_move => state,
+            _ => return,
+        };
+        match san.to_move(state.chess()) {
+            Ok(m) => state.make_move(m),
+            Err(_) => self.illegal_move = true,
+        }
+    }
+
+    fn outcome(&mut self, outcome: Outcome) {
+        self.outcome = Some(outcome);
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true) // stay in the mainline
+    }
+
+    fn end_game(&mut self, _game: &'pgn [u8]) -> Self::Result {
+        let headers = mem::take(&mut self.headers);
+        let result = match self.outcome.take() {
+            Some(outcome) => outcome.to_string(),
+            None => headers.iter()
+                .find(|header| header.0 == "Result")
+                .map(|header| header.1.clone())
+                .unwrap_or_else(|| "*".into()),
+        };
+        let (state, moves) = match self.state {
+            Some(ref state) => {
+                let (state, moves) = state.extract();
+                (Some(state), moves)
+            },
+            None => {
+                warn!("Not annotating a game with an invalid starting position.");
+                (None, Vec::new())
+            },
+        };
+        if self.illegal_move {
+            warn!("Annotating a game only up to its first illegal move.");
+        }
+        Game {headers, state, moves, result}
+    }
+}
+
+/// The search of a position, from White's point of view.
+struct Analysis {
+    /// The expected result of the best move, from -1 to 1.
+    score: f64,
+    wdl: Wdl,
+    pv: Vec<Move>,
+}
+
+impl Analysis {
+    fn new(engine: &Engine, state: &State, nodes: u32) -> Self {
+        let (score, wdl, pv) = if state.outcome() == BoardStatus::Ongoing {
+            let result = engine.search(state, Limits::Playouts(nodes));
+            (result.score, result.wdl, result.pv)
+        } else {
+            let wdl = engine.evaluate(state);
+            ((wdl.win - wdl.loss) as f64, wdl, Vec::new())
+        };
+        match state.turn() {
+            Color::White => Analysis {score, wdl, pv},
+            Color::Black => Analysis {score: -score, wdl: Wdl {win: wdl.loss, draw: wdl.draw, loss: wdl.win}, pv},
+        }
+    }
+
+    /// The expected score of `color`, from 0 to 1.
+    fn expected_score(&self, color: Color) -> f64 {
+        let score = if color == Color::White { self.score } else { -self.score };
+        (score + 1.) / 2.
+    }
+
+    fn comment(&self, state: &State) -> String {
+        let Wdl {win, draw, loss} = self.wdl;
+        let mut comment = format!("{:+.2} W {:.0}% D {:.0}% L {:.0}%", self.score, win * 100., draw * 100., loss * 100.);
+        if !self.pv.is_empty() {
+            comment += &format!(", best {}, pv {}", line(state, &self.pv[..1], false), line(state, &self.pv, false));
+        }
+        comment
+    }
+}
+
+/// `moves`, played from `state`, in SAN. If `numbered`, with move numbers as they're written after
+/// a comment.
+fn line(state: &State, moves: &[Move], numbered: bool) -> String {
+    let mut state = state.clone();
+    let mut words = Vec::new();
+    for (i, mov) in moves.iter().enumerate() {
+        if numbered && state.turn() == Color::White {
+            words.push(format!("{}.", state.chess().fullmoves()));
+        } else if numbered && i == 0 {
+            words.push(format!("{}...", state.chess().fullmoves()));
+        }
+        words.push(san_plus(state.chess().clone(), mov).to_string());
+        state.make_move(mov);
+    }
+    words.join(" ")
+}
+
+fn nag(loss: f64) -> Option<Nag> {
+    if loss > BLUNDER {
+        Some(Nag(4))
+    } else if loss > MISTAKE {
+        Some(Nag(2))
+    } else if loss > INACCURACY {
+        Some(Nag(6))
+    } else {
+        None
+    }
+}
+
+/// Appends `text` to `out`, broken into lines of at most `LINE_LENGTH` where it can be.
+fn wrap(text: &str, out: &mut String) {
+    let mut length = 0;
+    for word in text.split(' ') {
+        if length > 0 && length + 1 + word.len() > LINE_LENGTH {
+            out.push('\n');
+            length = 0;
+        } else if length > 0 {
+            out.push(' ');
+            length += 1;
+        }
+        out.push_str(word);
+        length += word.len();
+    }
+    out.push('\n');
+}
+
+fn annotate_game(engine: &Engine, game: &Game, nodes: u32) -> String {
+    let mut out = String::new();
+    for (key, value) in &game.headers {
+        out += &format!("[{} \"{}\"]\n", key, value);
+    }
+    if !game.headers.iter().any(|header| header.0 == "Annotator") {
+        out += &format!("[Annotator \"{} {}\"]\n", ENGINE_NAME, VERSION.unwrap_or("unknown"));
+    }
+    out.push('\n');
+    let mut movetext = Vec::new();
+    if let Some(ref state) = game.state {
+        let mut states = vec![state.clone()];
+        for mov in &game.moves {
+            let mut state = states[states.len() - 1].clone();
+            state.make_move(mov);
+            states.push(state);
+        }
+        let analyses: Vec<_> = states.iter().map(|state| Analysis::new(engine, state, nodes)).collect();
+        for (i, mov) in game.moves.iter().enumerate() {
+            let (state, analysis) = (&states[i], &analyses[i]);
+            let loss = if analysis.pv.first() == Some(mov) {
+                0.
+            } else {
+                let color = state.turn();
+                analysis.expected_score(color) - analyses[i + 1].expected_score(color)
+            };
+            movetext.push(line(state, slice::from_ref(mov), true));
+            if let Some(nag) = nag(loss) {
+                movetext.push(nag.to_string());
+            }
+            movetext.push(format!("{{ {} }}", analysis.comment(state)));
+            if nag(loss).is_some() {
+                movetext.push(format!("({})", line(state, &analysis.pv, true)));
+            }
+        }
+    }
+    movetext.push(game.result.clone());
+    wrap(&movetext.join(" "), &mut out);
+    out.push('\n');
+    out
+}
+
+/// Annotates the games in `pgn` with `nodes` playouts per position, on `config.num_threads` games
+/// at a time.
+fn annotate_pgn<W: Write>(pgn: &[u8], out: &mut W, nodes: u32, config: &Config) {
+    let mut game_reader = GameReader::default();
+    let games: Vec<Game> = {
+        let mut reader = Reader::new(&mut game_reader, pgn);
+        iter::from_fn(|| reader.read_game()).collect()
+    };
+    info!("Annotating {} games.", games.len());
+    let num_threads = max(1, config.num_threads);
+    let config = Config {
+        num_threads: 1,
+        memory_limit_mb: Some(config.memory_limit_mb.unwrap_or(MEMORY_LIMIT_MB)),
+        ..config.clone()
+    };
+    let next_game = AtomicUsize::new(0);
+    let (sender, receiver) = channel();
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            let (games, next_game, config, sender) = (&games, &next_game, &config, sender.clone());
+            scope.spawn(move || {
+                let engine = Engine::new(config.clone());
+                loop {
+                    let i = next_game.fetch_add(1, Ordering::Relaxed);
+                    if i >= games.len() {
+                        break;
+                    }
+                    sender.send((i, annotate_game(&engine, &games[i], nodes))).unwrap();
+                }
+            });
+        }
+        drop(sender);
+        // Each game is written as soon as it and all the games before it are done.
+        let mut finished = BTreeMap::new();
+        let mut written = 0;
+        for (i, annotated) in receiver {
+            finished.insert(i, annotated);
+            while let Some(annotated) = finished.remove(&written) {
+                out.write_all(annotated.as_bytes()).expect("write");
+                written += 1;
+                info!("Annotated {} of {} games.", written, games.len());
+            }
+        }
+    });
+}
+
+pub fn annotate(in_path: &str, out_path: &str, nodes: u32, config: &Config) {
+    let file = File::open(in_path).expect("fopen");
+    let pgn = unsafe { Mmap::map(&file).expect("mmap") };
+    let mut out_file = BufWriter::new(File::create(out_path).expect("create"));
+    annotate_pgn(&pgn[..], &mut out_file, nodes, config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotate_blunder() {
+        let pgn = b"[Event \"Test\"]\n[Result \"1-0\"]\n\n1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0\n\n\
+            [Event \"Illegal\"]\n\n1. e4 e5 2. Ke3 *\n";
+        let mut out = Vec::new();
+        annotate_pgn(pgn, &mut out, 500, &Config {num_threads: 2, memory_limit_mb: Some(16), ..Config::default()});
+        let out = String::from_utf8(out).unwrap().replace('\n', " ");
+        assert!(out.starts_with("[Event \"Test\"] [Result \"1-0\"] [Annotator \"Sashimi"));
+        assert!(out.contains(" 3... Nf6 $4 { "));
+        assert!(out.contains(" 4. Qxf7# { "));
+        assert!(out.contains("1-0  [Event \"Illegal\"]"));
+        // Only the moves up to the illegal one are kept.
+        assert!(out.contains(" 1... e5 "));
+        assert!(!out.contains("Ke3"));
+        assert!(out.trim_end().ends_with('*'));
+    }
+}
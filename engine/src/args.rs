@@ -1,57 +1,100 @@
 extern crate argparse;
 use self::argparse::*;
+use config::Config;
+use training::TrainingConfig;
 
 pub struct Options {
     pub log_file_path: String,
     pub train_pgn: Option<String>,
     pub train_output_path: String,
     pub policy: bool,
+    pub xboard: bool,
+    pub nodes: u32,
     pub extra: Vec<String>,
-    pub num_threads: usize,
+    pub config: Config,
+    pub training: TrainingConfig,
 }
 
 impl Default for Options {
     fn default() -> Self {
-        extern crate num_cpus;
-        let num_threads = num_cpus::get();
         Options {
             log_file_path: "sashimi.log".into(),
             train_pgn: None,
             train_output_path: "train_data.libsvm".into(),
             policy: false,
+            xboard: false,
+            nodes: 10_000,
             extra: Vec::new(),
-            num_threads,
+            config: Config::default(),
+            training: TrainingConfig::default(),
         }
     }
 }
 
-pub fn init() {
+pub fn parse() -> Options {
     let mut options = Options::default();
     {
+        let config = &mut options.config;
+        let training = &mut options.training;
         let mut ap = ArgumentParser::new();
         ap.refer(&mut options.train_pgn)
             .add_option(&["-t", "--train"], StoreOption, "path to .pgn for training");
         ap.refer(&mut options.train_output_path)
-            .add_option(&["-o", "--output"], Store, "train output path");
+            .add_option(&["-o", "--output"], Store, "train output path, within --output-dir");
         ap.refer(&mut options.policy)
             .add_option(&["-p", "--policy"], StoreTrue, "output policy data instead of value data");
+        ap.refer(&mut training.output_dir)
+            .add_option(&["--output-dir"], Store, "directory for the training data and its side files");
+        ap.refer(&mut training.min_elo)
+            .add_option(&["--min-elo"], Store, "skip games with a player rated below this for value data");
+        ap.refer(&mut training.min_elo_policy)
+            .add_option(&["--min-elo-policy"], Store, "skip games with a player rated below this for policy data");
+        ap.refer(&mut training.samples_per_game)
+            .add_option(&["--samples-per-game"], Store, "average number of positions sampled per game for value data");
+        ap.refer(&mut training.min_feature_frequency)
+            .add_option(&["--min-feature-frequency"], Store,
+                "leave out value features found in fewer sampled positions than this");
+        ap.refer(&mut training.skip_plies)
+            .add_option(&["--skip-plies"], Store, "never sample this many plies at the start of a game for value data");
+        ap.refer(&mut training.time_controls)
+            .add_option(&["--time-control"], Collect, "only train on games with this TimeControl header (repeatable)");
+        ap.refer(&mut training.terminations)
+            .add_option(&["--termination"], Collect, "only train on games with this Termination header (repeatable)");
+        ap.refer(&mut training.rated)
+            .add_option(&["--rated"], StoreOption, "only train on rated games (true) or casual ones (false)");
+        ap.refer(&mut training.seed)
+            .add_option(&["--sample-seed"], Store, "seed for sampling the positions of value data");
+        ap.refer(&mut options.xboard)
+            .add_option(&["--xboard"], StoreTrue, "speak the Xboard protocol (CECP) instead of UCI");
+        ap.refer(&mut options.nodes)
+            .add_option(&["--nodes"], Store, "playouts per position for annotate");
         ap.refer(&mut options.log_file_path)
             .add_option(&["--log"], Store, "log file path");
-        ap.refer(&mut options.num_threads)
+        ap.refer(&mut config.num_threads)
             .add_option(&["--threads"], Store, "number of threads");
+        ap.refer(&mut config.graph_search)
+            .add_option(&["--graph-search"], StoreTrue, "back up transposed nodes as a graph instead of a tree");
+        ap.refer(&mut config.memory_limit_mb)
+            .add_option(&["--memory-limit"], StoreOption,
+                "prune the least-visited subtrees to keep the tree under this many MB, instead of stopping at the node limit");
+        ap.refer(&mut config.huge_pages)
+            .add_option(&["--huge-pages"], StoreTrue, "back the search tree with transparent huge pages (Linux only)");
+        ap.refer(&mut config.tree_policy)
+            .add_option(&["--tree-policy"], Store, "alphago (default), variance-puct or ucb1-tuned");
+        ap.refer(&mut config.cpuct_factor)
+            .add_option(&["--cpuct-factor"], Store, "grow the alphago exploration constant by this times ln(visits)");
+        ap.refer(&mut config.fpu_reduction)
+            .add_option(&["--fpu-reduction"], StoreOption,
+                "value unvisited moves at the parent's value minus this times sqrt(visited policy), instead of 0");
+        ap.refer(&mut config.seed)
+            .add_option(&["--seed"], Store, "seed for the random choices of the search (also the Seed UCI option)");
+        ap.refer(&mut config.gumbel_playouts)
+            .add_option(&["--gumbel"], StoreOption,
+                "choose root moves by Gumbel sampling and sequential halving, planned for this many playouts");
         ap.refer(&mut options.extra)
-            .add_argument("uci_commands", Collect, "additional arguments are interpreted as UCI commands");
+            .add_argument("uci_commands", Collect, "additional arguments are interpreted as UCI commands, or `annotate <in.pgn> <out.pgn>`");
         ap.parse_args_or_exit();
     }
-    unsafe {
-        G_OPTIONS = Some(options);
-    }
+    options.training.num_threads = options.config.num_threads;
+    options
 }
-
-pub fn options() -> &'static Options {
-    unsafe {
-        G_OPTIONS.as_ref().unwrap()
-    }
-}
-
-static mut G_OPTIONS: Option<Options> = None;
@@ -0,0 +1,48 @@
+//! The settings of the engine.
+
+use search::PolicyKind;
+
+/// How the engine searches. The binary fills this in from the command line; programs that embed
+/// the engine construct it themselves, usually starting from `Config::default()`.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The number of search threads. Defaults to the number of CPUs.
+    pub num_threads: usize,
+    /// Back up transposed nodes as a graph instead of a tree.
+    pub graph_search: bool,
+    /// If `Some`, the least-visited subtrees are pruned to keep the tree under this many MB,
+    /// instead of stopping the search at the node limit.
+    pub memory_limit_mb: Option<usize>,
+    /// Back the search tree with transparent huge pages (Linux only).
+    pub huge_pages: bool,
+    pub tree_policy: PolicyKind,
+    /// Grow the AlphaGo exploration constant by this times ln(visits).
+    pub cpuct_factor: f32,
+    /// If `Some(r)`, unvisited moves are valued at their parent's value minus `r` times the square root
+    /// of the policy of the visited moves, instead of 0.
+    pub fpu_reduction: Option<f32>,
+    /// The seed for the random choices of the search.
+    pub seed: u64,
+    /// If `Some(n)`, root moves are chosen by Gumbel sampling and sequential halving, planned for `n` playouts.
+    pub gumbel_playouts: Option<u64>,
+    /// If `Some`, play at about this Elo instead of at full strength (see `strength`).
+    pub elo: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        extern crate num_cpus;
+        Config {
+            num_threads: num_cpus::get(),
+            graph_search: false,
+            memory_limit_mb: None,
+            huge_pages: false,
+            tree_policy: PolicyKind::default(),
+            cpuct_factor: 0.0,
+            fpu_reduction: None,
+            seed: 0,
+            gumbel_playouts: None,
+            elo: None,
+        }
+    }
+}
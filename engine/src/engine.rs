@@ -0,0 +1,149 @@
+//! Running the engine in-process, without going through UCI.
+
+use mcts::GameState;
+use std::cmp::max;
+use std::time::Duration;
+use config::Config;
+use features::{GameResult, Model};
+use policy_features::evaluate_moves;
+use search::Search;
+use strength;
+use state::{State, Move, BoardStatus};
+use shakmaty::Color;
+
+/// When a search stops.
+#[derive(Clone, Copy, Debug)]
+pub enum Limits {
+    /// After this many playouts in all.
+    Playouts(u32),
+    Time(Duration),
+}
+
+/// The probabilities of a win, a draw and a loss for the side to move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wdl {
+    pub win: f32,
+    pub draw: f32,
+    pub loss: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    /// `None` if the game is over.
+    pub best_move: Option<Move>,
+    /// The principal variation, starting with `best_move`.
+    pub pv: Vec<Move>,
+    /// The expected result of `best_move` for the side to move, from -1 (a loss) to 1 (a win).
+    pub score: f64,
+    /// The static evaluation of the position at the end of the principal variation, for the side to
+    /// move at the root.
+    pub wdl: Wdl,
+    /// The number of nodes in the search tree.
+    pub nodes: usize,
+}
+
+/// The engine, set up with a `Config`. It is cheap to create, and every search starts a new tree.
+pub struct Engine {
+    config: Config,
+    model: Model,
+}
+
+impl Engine {
+    pub fn new(config: Config) -> Self {
+        Self {config, model: Model::new()}
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Searches `state` until `limits` are reached, with `config().num_threads` threads.
+    pub fn search(&self, state: &State, limits: Limits) -> SearchResult {
+        let mut manager = Search::create_manager_from_config(state.clone(), &self.config);
+        let num_threads = max(1, self.config.num_threads);
+        match limits {
+            Limits::Playouts(n) => manager.playout_n_parallel(n, num_threads),
+            Limits::Time(duration) => manager.playout_parallel_for(duration, num_threads),
+        }
+        let pv = manager.principal_variation(PV_LENGTH);
+        let score = manager.principal_variation_info(1).first()
+            .map(|x| x.sum_rewards() / x.visits() as f64)
+            .unwrap_or(0.0);
+        let leaf = manager.principal_variation_states(PV_LENGTH).pop().unwrap();
+        let mut wdl = self.evaluate(&leaf);
+        if leaf.turn() != state.turn() {
+            wdl = Wdl {win: wdl.loss, draw: wdl.draw, loss: wdl.win};
+        }
+        SearchResult {
+            best_move: pv.first().cloned(),
+            pv,
+            score,
+            wdl,
+            nodes: manager.tree().num_nodes(),
+        }
+    }
+
+    /// The move to play in `state`, or `None` if the game is over. If `config().elo` is set, the move
+    /// is chosen as `strength` says for that Elo and `limits` are ignored; otherwise it's the best
+    /// move of `search`.
+    pub fn play(&self, state: &State, limits: Limits) -> Option<Move> {
+        match self.config.elo {
+            Some(elo) => strength::choose_move(state, &self.config, elo),
+            None => self.search(state, limits).best_move,
+        }
+    }
+
+    /// The static evaluation of `state` by the value model, without searching. If the game is over,
+    /// this is its result instead.
+    pub fn evaluate(&self, state: &State) -> Wdl {
+        match state.outcome() {
+            BoardStatus::Checkmate => return Wdl {win: 0.0, draw: 0.0, loss: 1.0},
+            BoardStatus::Stalemate => return Wdl {win: 0.0, draw: 1.0, loss: 0.0},
+            BoardStatus::Ongoing => (),
+        }
+        let moves = state.available_moves();
+        let probabilities = self.model.predict(state, moves.as_slice());
+        let (win, loss) = match state.turn() {
+            Color::White => (GameResult::WhiteWin, GameResult::BlackWin),
+            Color::Black => (GameResult::BlackWin, GameResult::WhiteWin),
+        };
+        Wdl {
+            win: probabilities[win as usize],
+            draw: probabilities[GameResult::Draw as usize],
+            loss: probabilities[loss as usize],
+        }
+    }
+
+    /// The policy model's probability of each legal move, which the search uses as its prior.
+    pub fn policy(&self, state: &State) -> Vec<(Move, f32)> {
+        let moves = state.available_moves();
+        let priors = evaluate_moves(state, moves.as_slice());
+        moves.as_slice().iter().cloned().zip(priors).collect()
+    }
+}
+
+const PV_LENGTH: usize = 10;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use search::to_uci;
+
+    #[test]
+    fn engine_api() {
+        let engine = Engine::new(Config {num_threads: 1, ..Config::default()});
+        let state = State::from_moves(Some("6k1/8/6K1/8/8/8/8/R7 w - - 0 1"), &[]).unwrap();
+        let result = engine.search(&state, Limits::Playouts(20_000));
+        assert_eq!(result.best_move.as_ref().map(|mov| to_uci(mov, false)), Some("a1a8".into()));
+        assert_eq!(Some(&result.pv[0]), result.best_move.as_ref());
+        assert!(result.score > 0.9);
+        let policy = engine.policy(&state);
+        assert_eq!(policy.len(), state.available_moves().len());
+        assert!((policy.iter().map(|x| x.1).sum::<f32>() - 1.0).abs() < 1e-3);
+        let wdl = engine.evaluate(&state);
+        assert!((wdl.win + wdl.draw + wdl.loss - 1.0).abs() < 1e-3);
+        assert!(wdl.win > wdl.loss);
+        // The principal variation ends in mate.
+        assert_eq!(result.wdl, Wdl {win: 1.0, draw: 0.0, loss: 0.0});
+    }
+}
@@ -1,38 +1,37 @@
 use mcts::{Evaluator, SearchHandle};
-use search::{GooseMCTS, SCALE};
-use state::{State, Player, MoveList};
+use search::GooseMCTS;
+use state::{State, Player, MoveList, BoardStatus};
 use features::Model;
 use policy_features::evaluate_moves;
-use chess::*;
+use shakmaty::Color;
 
 pub struct GooseEval {
     model: Model
 }
 
 impl Evaluator<GooseMCTS> for GooseEval {
-    type StateEvaluation = i64;
+    type StateEvaluation = f64;
 
     fn evaluate_new_state(&self, state: &State, moves: &MoveList,
-                          _: Option<SearchHandle<GooseMCTS>>) -> (Vec<f32>, i64) {
+                          _: Option<SearchHandle<GooseMCTS>>) -> (Vec<f32>, f64) {
         let move_evaluations = evaluate_moves(state, moves.as_slice());
-        let state_evaluation = if moves.len() == 0 {
-            let x = SCALE as i64;
+        let state_evaluation = if moves.is_empty() {
             match state.outcome() {
-                BoardStatus::Stalemate => 0,
+                BoardStatus::Stalemate => 0.,
                 BoardStatus::Checkmate =>
-                    if state.board().side_to_move() == Color::White {-x} else {x},
+                    if state.turn() == Color::White {-1.} else {1.},
                 BoardStatus::Ongoing => unreachable!(),
             }
         } else {
-            (self.model.score(state, moves.as_slice()) * SCALE as f32) as i64
+            self.model.score(state, moves.as_slice()) as f64
         };
         (move_evaluations, state_evaluation)
     }
-    fn evaluate_existing_state(&self, _: &State, evaln: &i64,
-                               _: SearchHandle<GooseMCTS>) -> i64 {
+    fn evaluate_existing_state(&self, _: &State, evaln: &f64,
+                               _: SearchHandle<GooseMCTS>) -> f64 {
         *evaln
     }
-    fn interpret_evaluation_for_player(&self, evaln: &i64, player: &Player) -> i64 {
+    fn interpret_evaluation_for_player(&self, evaln: &f64, player: &Player) -> f64 {
         match *player {
             Color::White => *evaln,
             Color::Black => -*evaln,
@@ -48,7 +47,7 @@ impl From<Model> for GooseEval {
 
 #[cfg(test)]
 mod tests {
-    use search::Search;
+    use search::{Search, to_uci};
     use mcts::GameState;
     use super::*;
     use float_ord::FloatOrd;
@@ -58,11 +57,11 @@ mod tests {
         let state = State::from_fen(fen).unwrap();
         let moves = state.available_moves();
         let moves = moves.as_slice();
-        let evalns = evaluate_moves(&state, &moves);
+        let evalns = evaluate_moves(&state, moves);
         let mut paired: Vec<_> = moves.iter().zip(evalns.iter()).collect();
         paired.sort_by_key(|x| FloatOrd(*x.1));
         for (a, b) in paired {
-            println!("policy: {} {}", a, b);
+            println!("policy: {} {}", to_uci(a, false), b);
         }
         let mut manager = Search::create_manager(state);
         // for _ in 0..5 {
@@ -79,16 +78,51 @@ mod tests {
             println!("{}", info);
         }
         println!("{}", manager.tree().diagnose());
-        assert!(format!("{}", mov).starts_with(desired),
+        assert!(to_uci(&mov, false).starts_with(desired),
                 "expected {}, got {}",
                 desired,
-                mov);
+                to_uci(&mov, false));
         manager.principal_variation_states(pv_len)
     }
 
+    /// Counts the nodes whose outgoing edges claim more visits than the node itself received.
+    fn count_inconsistent_nodes(fen: &str, graph_search: bool) -> usize {
+        use mcts::NodeHandle;
+        use std::collections::HashSet;
+        let state = State::from_fen(fen).unwrap();
+        let mcts = GooseMCTS {graph_search, ..Default::default()};
+        let mut manager = Search::create_manager_with(state, mcts);
+        manager.playout_n(10_000);
+        let mut seen = HashSet::new();
+        let root = manager.tree().root_node();
+        let mut stack: Vec<NodeHandle<GooseMCTS>> = root.moves()
+            .filter_map(|x| x.child())
+            .collect();
+        let mut inconsistent = 0;
+        while let Some(node) = stack.pop() {
+            if !seen.insert(node.into_raw()) {
+                continue;
+            }
+            let edge_visits: u64 = node.moves().map(|x| x.visits()).sum();
+            if edge_visits > node.visits() {
+                inconsistent += 1;
+            }
+            stack.extend(node.moves().filter_map(|x| x.child()));
+        }
+        inconsistent
+    }
+
+    #[test]
+    fn graph_search_keeps_edge_visits_consistent() {
+        // Rooks and kings shuffling around produce a lot of transpositions.
+        let fen = "4k3/8/8/8/8/8/8/R3K2R w - - 0 1";
+        assert!(count_inconsistent_nodes(fen, false) > 0);
+        assert_eq!(count_inconsistent_nodes(fen, true), 0);
+    }
+
     #[test]
     fn mate_in_one() {
-        assert_find_move("6k1/8/6K1/8/8/8/8/R7 w - - 0 0", "a1a8");;
+        assert_find_move("6k1/8/6K1/8/8/8/8/R7 w - - 0 0", "a1a8");
     }
 
     #[test]
@@ -0,0 +1,102 @@
+//! The `eval` and `policy` commands, which show why the models like a position or a move.
+
+use mcts::GameState;
+use engine::Engine;
+use features::{self, Model};
+use policy_features::{self, evaluate_moves};
+use search::to_uci;
+use state::State;
+use float_ord::FloatOrd;
+use std::fmt::Write;
+
+/// The number of features that `eval` shows.
+const TOP_FEATURES: usize = 12;
+
+/// The value model's win, draw and loss probabilities for the side to move, and the features that
+/// change the balance between a win and a loss the most, with their share of the logits.
+pub fn eval(engine: &Engine, state: &State, json: bool) -> String {
+    let wdl = engine.evaluate(state);
+    let model = Model::new();
+    let moves = state.available_moves();
+    let mut contributions: Vec<_> = features::active_features(state, moves.as_slice())
+        .into_iter()
+        .filter_map(|(fidx, count)| {
+            let coef = model.coefficients(fidx)?;
+            let [win, loss, draw] = coef.map(|x| x * count as f32);
+            Some((fidx, count, win, draw, loss))
+        })
+        .collect();
+    contributions.sort_by_key(|x| FloatOrd(-(x.2 - x.4).abs()));
+    contributions.truncate(TOP_FEATURES);
+    let mut out = String::new();
+    if json {
+        let entries: Vec<_> = contributions.iter()
+            .map(|&(fidx, count, win, draw, loss)| format!(
+                "{{\"name\":\"{}\",\"count\":{},\"win\":{},\"draw\":{},\"loss\":{}}}",
+                features::name_feature(fidx), count, win, draw, loss))
+            .collect();
+        write!(out, "{{\"win\":{},\"draw\":{},\"loss\":{},\"features\":[{}]}}",
+            wdl.win, wdl.draw, wdl.loss, entries.join(",")).unwrap();
+    } else {
+        writeln!(out, "win {:.4} draw {:.4} loss {:.4}", wdl.win, wdl.draw, wdl.loss).unwrap();
+        writeln!(out, "{:>8} {:>8} {:>8}  feature", "win", "draw", "loss").unwrap();
+        for &(fidx, count, win, draw, loss) in &contributions {
+            write!(out, "{:>+8.4} {:>+8.4} {:>+8.4}  {}", win, draw, loss, features::name_feature(fidx)).unwrap();
+            if count != 1 {
+                write!(out, " x{}", count).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+    }
+    out
+}
+
+/// Every legal move with its probability under the policy, most likely first, and the weights of the
+/// policy features that are active for it.
+pub fn policy(state: &State, json: bool) -> String {
+    let moves = state.available_moves();
+    let moves = moves.as_slice();
+    let priors = evaluate_moves(state, moves);
+    let mut order: Vec<usize> = (0..moves.len()).collect();
+    order.sort_by_key(|&i| FloatOrd(-priors[i]));
+    let mut out = String::new();
+    let mut entries = Vec::new();
+    for i in order {
+        let active = policy_features::active_features(state, &moves[i]);
+        if json {
+            let active: Vec<_> = active.iter()
+                .map(|&(fidx, weight)| format!(
+                    "{{\"name\":\"{}\",\"weight\":{}}}", policy_features::name_feature(fidx), weight))
+                .collect();
+            entries.push(format!("{{\"move\":\"{}\",\"probability\":{},\"features\":[{}]}}",
+                to_uci(&moves[i], state.is_chess960()), priors[i], active.join(",")));
+        } else {
+            let active: Vec<_> = active.iter()
+                .map(|&(fidx, weight)| format!("{}={:+.3}", policy_features::name_feature(fidx), weight))
+                .collect();
+            writeln!(out, "{:<6} {:.4}  {}", to_uci(&moves[i], state.is_chess960()), priors[i], active.join(" ")).unwrap();
+        }
+    }
+    if json {
+        write!(out, "{{\"moves\":[{}]}}", entries.join(",")).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+
+    #[test]
+    fn explain_json() {
+        let engine = Engine::new(Config {num_threads: 1, ..Config::default()});
+        let state = State::default();
+        let eval = eval(&engine, &state, true);
+        assert!(eval.starts_with("{\"win\":"));
+        assert_eq!(eval.matches("\"name\"").count(), TOP_FEATURES);
+        let policy = policy(&state, true);
+        assert_eq!(policy.matches("\"move\"").count(), 20);
+        assert!(policy.contains("\"move\":\"e2e4\""));
+    }
+}
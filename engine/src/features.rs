@@ -1,7 +1,8 @@
-use chess::*;
-use state::State;
+use shakmaty::{Board, Color, Piece, Role, Square};
+use state::{State, Move, destination};
 use std::io::Write;
 use std::cmp::min;
+use std::collections::BTreeMap;
 
 use features_common::*;
 
@@ -9,37 +10,31 @@ include!(concat!(env!("OUT_DIR"), "/feature_const.rs"));
 const MAX_PATTERNS_IN_POSITION: usize = 64 + 7*7;
 const NUM_RANKS: usize = 8;
 const NUM_FILES: usize = 8;
+const NUM_SQUARES: usize = NUM_RANKS * NUM_FILES;
 const NUM_PIECES: usize = NUM_COLORS * NUM_ROLES;
 const NUM_PATTERNS: usize = NUM_PIECES * (NUM_RANKS + NUM_FILES) * NUM_PHASES;
 const NUM_2X2_ELTS: usize = NUM_PIECES + 1;
 const NUM_2X2_PATTERNS: usize = NUM_2X2_ELTS * NUM_2X2_ELTS * NUM_2X2_ELTS * NUM_2X2_ELTS;
 pub const NUM_FEATURES: usize = NUM_DENSE_FEATURES + NUM_PATTERNS + NUM_2X2_PATTERNS;
 
-struct FullPiece {
-    role: Piece,
-    color: Color,
-}
-
 fn feature_index(c: usize, p: Phase, idx: usize) -> usize {
     let x = 0;
     let x = x * NUM_PHASES + p as usize;
     let x = x * NUM_COLORS + c;
-    let x = x * NUM_NAMES + idx;
-    x
+    x * NUM_NAMES + idx
 }
 
-fn encode_piece(pc: FullPiece) -> usize {
+fn encode_piece(pc: Piece) -> usize {
     let x = 0;
-    let x = x * NUM_COLORS + pc.color as usize;
-    let x = x * NUM_ROLES + pc.role as usize;
-    x
+    let x = x * NUM_COLORS + encode_color(pc.color);
+    x * NUM_ROLES + pc.role as usize
 }
 
-fn pattern_index(ph: Phase, pc: FullPiece, sq: Square, is_rank: bool) -> usize {
+fn pattern_index(ph: Phase, pc: Piece, sq: Square, is_rank: bool) -> usize {
     let rr = if is_rank {
-        sq.get_rank() as usize
+        sq.rank() as usize
     } else {
-        sq.get_file() as usize + NUM_RANKS
+        sq.file() as usize + NUM_RANKS
     };
     let x = 0;
     let x = x * NUM_PHASES + ph as usize;
@@ -99,7 +94,7 @@ impl FeatureVec {
                 cnt = 0;
             }
         }
-        write!(f, "\n").unwrap();
+        writeln!(f).unwrap();
     }
     pub fn write_frequency(&self, freq: &mut [u64; NUM_FEATURES]) {
         for (index, &value) in self.arr.iter().enumerate() {
@@ -111,87 +106,80 @@ impl FeatureVec {
     }
 }
 
-fn flip_side(s: Square) -> Square {
-    let r = s.get_rank();
-    let f = s.get_file();
-    let r = r.to_index();
-    let r = 7 - r;
-    let r = Rank::from_index(r);
-    Square::make_square(r, f)
-}
-
-fn encode_move(c: Color, p: Piece, to: Square) -> usize {
+fn encode_move(c: Color, p: Role, to: Square) -> usize {
     let to = if c == Color::Black {
-        flip_side(to)
+        to.flip_vertical()
     } else {
         to
     };
     let x = 0;
-    let x = x * NUM_PIECES + p.to_index();
-    let x = x * NUM_SQUARES + to.to_index();
+    let x = x * NUM_PIECES + p as usize;
+    let x = x * NUM_SQUARES + usize::from(to);
     x + CAN_DO_PAWN_TO_A1
 }
 
-fn foreach_feature<F>(state: &State, _: &[ChessMove], mut f: F) where F: FnMut(usize, u8) {
+fn foreach_feature<F>(state: &State, _: &[Move], mut f: F) where F: FnMut(usize, u8) {
     let board = state.board();
-    let colors = &[board.side_to_move(), !board.side_to_move()];
+    let colors = &[state.turn(), !state.turn()];
     let non_king_pieces = &[
-        Piece::Pawn,
-        Piece::Knight,
-        Piece::Bishop,
-        Piece::Rook,
-        Piece::Queen];
-    let all_pieces = &[
-        Piece::Pawn,
-        Piece::Knight,
-        Piece::Bishop,
-        Piece::Rook,
-        Piece::Queen,
-        Piece::King];
+        Role::Pawn,
+        Role::Knight,
+        Role::Bishop,
+        Role::Rook,
+        Role::Queen];
     let p = phase(state);
     for (color_index, &c) in colors.iter().enumerate() {
         let moves = &state.move_lists()[color_index];
-        let color_board = board.color_combined(c);
+        let color_board = board.by_color(c);
         for &piece in non_king_pieces {
-            let cnt = (board.pieces(piece) & color_board).popcnt();
+            let cnt = (board.by_role(piece) & color_board).count();
             let feat = match (piece, cnt) {
-                (Piece::Pawn, 0) => PAWN_NUM_0,
-                (Piece::Pawn, 1) => PAWN_NUM_1,
-                (Piece::Pawn, 2) => PAWN_NUM_2,
-                (Piece::Pawn, 3) => PAWN_NUM_3,
-                (Piece::Pawn, 4) => PAWN_NUM_4,
-                (Piece::Pawn, 5) => PAWN_NUM_5,
-                (Piece::Pawn, 6) => PAWN_NUM_6,
-                (Piece::Pawn, 7) => PAWN_NUM_7,
-                (Piece::Pawn, _) => PAWN_NUM_8,
-                (Piece::Knight, 0) => KNIGHT_NUM_0,
-                (Piece::Knight, 1) => KNIGHT_NUM_1,
-                (Piece::Knight, _) => KNIGHT_NUM_2,
-                (Piece::Bishop, 0) => BISHOP_NUM_0,
-                (Piece::Bishop, 1) => BISHOP_NUM_1,
-                (Piece::Bishop, _) => BISHOP_NUM_2,
-                (Piece::Rook, 0) => ROOK_NUM_0,
-                (Piece::Rook, 1) => ROOK_NUM_1,
-                (Piece::Rook, _) => ROOK_NUM_2,
-                (Piece::Queen, 0) => QUEEN_NUM_0,
-                (Piece::Queen, _) => QUEEN_NUM_1,
-                (Piece::King, _) => unreachable!(),
+                (Role::Pawn, 0) => PAWN_NUM_0,
+                (Role::Pawn, 1) => PAWN_NUM_1,
+                (Role::Pawn, 2) => PAWN_NUM_2,
+                (Role::Pawn, 3) => PAWN_NUM_3,
+                (Role::Pawn, 4) => PAWN_NUM_4,
+                (Role::Pawn, 5) => PAWN_NUM_5,
+                (Role::Pawn, 6) => PAWN_NUM_6,
+                (Role::Pawn, 7) => PAWN_NUM_7,
+                (Role::Pawn, _) => PAWN_NUM_8,
+                (Role::Knight, 0) => KNIGHT_NUM_0,
+                (Role::Knight, 1) => KNIGHT_NUM_1,
+                (Role::Knight, _) => KNIGHT_NUM_2,
+                (Role::Bishop, 0) => BISHOP_NUM_0,
+                (Role::Bishop, 1) => BISHOP_NUM_1,
+                (Role::Bishop, _) => BISHOP_NUM_2,
+                (Role::Rook, 0) => ROOK_NUM_0,
+                (Role::Rook, 1) => ROOK_NUM_1,
+                (Role::Rook, _) => ROOK_NUM_2,
+                (Role::Queen, 0) => QUEEN_NUM_0,
+                (Role::Queen, _) => QUEEN_NUM_1,
+                (Role::King, _) => unreachable!(),
             };
             f(feature_index(color_index, p, feat), 1);
         }
         let prev_dest = state.prev_move()
-            .map(|x| x.get_dest())
-            .unwrap_or(Square::make_square(Rank::First, File::A));
-        let mut best_recapture = Piece::King;
+            .map(destination)
+            .unwrap_or(Square::A1);
+        let mut best_recapture = Role::King;
+        // The opponent's moves are from the position before the last move, so the pieces are
+        // looked up on the board as it is now.
         for mov in moves {
-            if let Some(a) = board.piece_on(mov.get_source()) {
-                let b = board.piece_on(mov.get_dest()).unwrap_or(Piece::King);
-                f(feature_index(color_index, p, CAN_DO_PAWN_TAKES_PAWN + encode_pair(a, b)), 1);
-                if mov.get_dest() == prev_dest {
-                    best_recapture = min(best_recapture, a);
-                }
-                f(feature_index(color_index, p, encode_move(c, a, mov.get_dest())), 1);
+            let dest = destination(mov);
+            let a = match board.role_at(mov.from().unwrap()) {
+                Some(a) => a,
+                None => continue,
+            };
+            let b = if mov.is_castle() {
+                Role::King
+            } else {
+                board.role_at(dest).unwrap_or(Role::King)
+            };
+            f(feature_index(color_index, p, CAN_DO_PAWN_TAKES_PAWN + encode_pair(a, b)), 1);
+            if dest == prev_dest {
+                best_recapture = min(best_recapture, a);
             }
+            f(feature_index(color_index, p, encode_move(c, a, dest)), 1);
         }
         if let Some(captured) = state.prev_capture() {
             f(feature_index(
@@ -200,15 +188,16 @@ fn foreach_feature<F>(state: &State, _: &[ChessMove], mut f: F) where F: FnMut(u
                 JUST_CAPTURED_PAWN_RECAPTURE_WITH_PAWN + encode_pair(captured, best_recapture)), 1);
         }
         if color_index == 0 {
-            if board.checkers().0 != 0 {
+            let checkers = state.checkers();
+            if checkers.any() {
                 f(feature_index(color_index, p, IS_CHECK), 1);
-                if board.checkers().popcnt() != 1 {
+                if checkers.more_than_one() {
                     f(feature_index(color_index, p, IS_DOUBLE_CHECK), 1);
                 }
             }
-            let pinned = board.pinned();
-            for &piece in all_pieces {
-                if (board.pieces(piece) & pinned).0 != 0 {
+            let pinned = state.pinned();
+            for &piece in &ROLES {
+                if (board.by_role(piece) & pinned).any() {
                     f(feature_index(color_index, p, PAWN_PINNED + piece as usize), 1);
                 }
             }
@@ -216,18 +205,18 @@ fn foreach_feature<F>(state: &State, _: &[ChessMove], mut f: F) where F: FnMut(u
         f(feature_index(color_index, p, ONE), 1);
     }
     for &color in colors {
-        let color_board = board.color_combined(color);
-        for &piece in all_pieces {
-            for sq in board.pieces(piece) & color_board {
+        let color_board = board.by_color(color);
+        for &piece in &ROLES {
+            for sq in board.by_role(piece) & color_board {
                 for &is_rank in &[false, true] {
-                    if piece != Piece::Pawn && piece != Piece::Rook && piece != Piece::King && 
-                            is_rank && sq.get_rank() != Rank::First && sq.get_rank() != Rank::Eighth  {
+                    if piece != Role::Pawn && piece != Role::Rook && piece != Role::King &&
+                            is_rank && sq.rank() != 0 && sq.rank() != 7 {
                         continue;
                     }
-                    if piece != Piece::Pawn && piece != Piece::King && piece != Piece::Knight && !is_rank {
+                    if piece != Role::Pawn && piece != Role::King && piece != Role::Knight && !is_rank {
                         continue;
                     }
-                    f(pattern_index(p, FullPiece {color, role: piece}, sq, is_rank), 1);
+                    f(pattern_index(p, Piece {color, role: piece}, sq, is_rank), 1);
                 }
             }
         }
@@ -235,17 +224,13 @@ fn foreach_feature<F>(state: &State, _: &[ChessMove], mut f: F) where F: FnMut(u
     if p != Phase::Endgame {
         for file in 0..7 {
             for rank in 0..7 {
-                let pattern = extract_2x2_pattern(
-                    board,
-                    File::from_index(file),
-                    Rank::from_index(rank));
+                let pattern = extract_2x2_pattern(board, file, rank);
                 f(index_2x2_pattern(&pattern), 1);
             }
         }
     }
 }
-
-pub fn featurize(state: &State, moves: &[ChessMove]) -> FeatureVec {
+pub fn featurize(state: &State, moves: &[Move]) -> FeatureVec {
     let mut arr = [0u8; NUM_DENSE_FEATURES];
     let mut patterns = Vec::with_capacity(MAX_PATTERNS_IN_POSITION);
     foreach_feature(state, moves, |i, v| {
@@ -254,7 +239,7 @@ pub fn featurize(state: &State, moves: &[ChessMove]) -> FeatureVec {
         if i < NUM_DENSE_FEATURES {
             arr[i] += v;
         } else {
-            patterns.push(i as usize);
+            patterns.push(i);
         }
     });
     assert!(patterns.len() <= MAX_PATTERNS_IN_POSITION);
@@ -264,13 +249,22 @@ pub fn featurize(state: &State, moves: &[ChessMove]) -> FeatureVec {
     }
 }
 
+/// The features that are active in `state`, with how many times each occurs, in increasing order.
+pub fn active_features(state: &State, moves: &[Move]) -> Vec<(usize, u32)> {
+    let mut counts = BTreeMap::new();
+    foreach_feature(state, moves, |i, v| {
+        *counts.entry(i).or_insert(0) += v as u32;
+    });
+    counts.into_iter().collect()
+}
+
 pub struct Model;
 
 impl Model {
     pub fn new() -> Self {
         Model
     }
-    pub fn predict(&self, state: &State, moves: &[ChessMove]) -> [f32; NUM_OUTCOMES] {
+    pub fn predict(&self, state: &State, moves: &[Move]) -> [f32; NUM_OUTCOMES] {
         let mut result = [0f32; NUM_OUTCOMES];
         foreach_feature(state, moves, |i, _| {
             if i < NUM_MODEL_FEATURES {
@@ -287,12 +281,17 @@ impl Model {
         for x in &mut result {
             *x *= s;
         }
-        if state.board().side_to_move() == Color::Black {
+        if state.turn() == Color::Black {
             result.swap(0, 1);
         }
         result
     }
-    pub fn score(&self, state: &State, moves: &[ChessMove]) -> f32 {
+    /// What feature `fidx` adds to the logits of a win, a loss and a draw for the side to move, or
+    /// `None` if the model doesn't use it.
+    pub fn coefficients(&self, fidx: usize) -> Option<[f32; NUM_OUTCOMES]> {
+        COEF.get(fidx).cloned()
+    }
+    pub fn score(&self, state: &State, moves: &[Move]) -> f32 {
         let probs = self.predict(state, moves);
           probs[GameResult::WhiteWin as usize]
         - probs[GameResult::BlackWin as usize]
@@ -301,20 +300,26 @@ impl Model {
 
 fn phase(s: &State) -> Phase {
     if s.queens_off() {
-        return Phase::Endgame;
+        Phase::Endgame
     } else {
-        return Phase::Midgame;
+        Phase::Midgame
     }
 }
 
 pub fn name_feature(fidx: usize) -> String {
-    assert!(fidx < NUM_DENSE_FEATURES);
+    assert!(fidx < NUM_FEATURES);
+    if fidx >= NUM_DENSE_FEATURES + NUM_PATTERNS {
+        return name_2x2_pattern(fidx - NUM_DENSE_FEATURES - NUM_PATTERNS);
+    }
+    if fidx >= NUM_DENSE_FEATURES {
+        return name_pattern(fidx - NUM_DENSE_FEATURES);
+    }
     let side_names = &["OUR", "OPPONENT"];
-    for c in 0..2 {
+    for (c, side_name) in side_names.iter().enumerate() {
         for p in &[Phase::Midgame, Phase::Endgame] {
             for (idx, name) in INDEX_NAMES.iter().enumerate() {
                 if feature_index(c, *p, idx) == fidx {
-                    return format!("{}_{:?}_{}", side_names[c], p, name).to_lowercase();
+                    return format!("{}_{:?}_{}", side_name, p, name).to_lowercase();
                 }
             }
         }
@@ -322,21 +327,129 @@ pub fn name_feature(fidx: usize) -> String {
     unreachable!()
 }
 
-fn full_piece_on(board: &Board, sq: Square) -> Option<FullPiece> {
-    let role = board.piece_on(sq)?;
-    let color = if (board.color_combined(Color::White) & BitBoard::from_square(sq)).0 != 0 {
-        Color::White
+fn name_piece(pc: usize) -> String {
+    let color = [Color::White, Color::Black][pc / NUM_ROLES];
+    let role = ROLES[pc % NUM_ROLES];
+    format!("{:?}_{:?}", color, role).to_lowercase()
+}
+
+// The inverse of `pattern_index`.
+fn name_pattern(x: usize) -> String {
+    let rr = x % (NUM_RANKS + NUM_FILES);
+    let x = x / (NUM_RANKS + NUM_FILES);
+    let pc = x % NUM_PIECES;
+    let ph = if x / NUM_PIECES == Phase::Midgame as usize { Phase::Midgame } else { Phase::Endgame };
+    let line = if rr < NUM_RANKS {
+        format!("rank_{}", rr + 1)
     } else {
-        Color::Black
+        format!("file_{}", (b'a' + (rr - NUM_RANKS) as u8) as char)
     };
-    Some(FullPiece {color, role})
+    format!("{:?}_{}_{}", ph, name_piece(pc), line).to_lowercase()
+}
+
+// The inverse of `index_2x2_pattern`: the squares from the lower left, along the rank first.
+fn name_2x2_pattern(x: usize) -> String {
+    let mut x = x;
+    let mut names = Vec::new();
+    for _ in 0..4 {
+        let elt = x % NUM_2X2_ELTS;
+        x /= NUM_2X2_ELTS;
+        names.push(if elt == NUM_PIECES { "empty".into() } else { name_piece(elt) });
+    }
+    names.reverse();
+    format!("2x2_{}", names.join("_"))
+}
+
+fn encode_square(board: &Board, file: i8, rank: i8) -> usize {
+    let sq = Square::from_coords(file, rank).unwrap();
+    board.piece_at(sq).map(encode_piece).unwrap_or(NUM_PIECES)
 }
 
-fn extract_2x2_pattern(board: &Board, file: File, rank: Rank) -> [usize; 4] {
+fn extract_2x2_pattern(board: &Board, file: i8, rank: i8) -> [usize; 4] {
     [
-        full_piece_on(board, Square::make_square(rank, file)).map(|pc| encode_piece(pc)).unwrap_or(NUM_PIECES),
-        full_piece_on(board, Square::make_square(rank, file.right())).map(|pc| encode_piece(pc)).unwrap_or(NUM_PIECES),
-        full_piece_on(board, Square::make_square(rank.up(), file)).map(|pc| encode_piece(pc)).unwrap_or(NUM_PIECES),
-        full_piece_on(board, Square::make_square(rank.up(), file.right())).map(|pc| encode_piece(pc)).unwrap_or(NUM_PIECES),
+        encode_square(board, file, rank),
+        encode_square(board, file + 1, rank),
+        encode_square(board, file, rank + 1),
+        encode_square(board, file + 1, rank + 1),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mcts::GameState;
+    use policy_features;
+    use search::to_uci;
+    use std::fs::File;
+
+    // Every prefix of these is a position in the corpus.
+    const GAMES: &[&str] = &[
+        "startpos moves d2d4 g8f6 c2c4 c7c5 d4c5 e7e5 b1c3 f8c5 e2e4 b8c6 h2h3 d8b6 d1e2 c6d4 e2d2 d7d6 c3a4 f6e4 a4b6 a7b6 d2d1 c5b4 c1d2 b4d2 d1d2 e4d2 e1d2 d4b3 a2b3 a8a1 g1f3 c8d7 g2g4 h7h5 h1g1 h5g4 h3g4 d7c6 f1e2 a1a2 d2c3 h8h3 g1g3 h3h1 b3b4 h1b1 b4b5 c6d7 e2d3 b1b2 d3f5 b2f2 f5d7 e8d7 f3g5 a2a3 c3b4 a3g3 g5e4 f2b2 b4a4 g3g1 e4c3 g1a1 c3a2 a1a2",
+        "startpos moves g1f3 g8f6 d2d4 b8c6 b1c3 e7e6 c1g5 h7h6 g5h4 g7g5 h4g3 f8b4 d1d3 g5g4 f3d2 e8g8 e1c1 d7d6 a2a3 b4a5 d2c4 a5c3 d3c3 c8d7 c3b3 b7b6 g3h4 a7a5 a3a4 d8e7 c4e3 h6h5 g2g3 d6d5 f1g2 f8b8 c2c4 b6b5 c4b5 c6b4 c1b1 c7c6 b5c6 b4c6 b3c3 b8b4 c3a3 a8b8 d1d3 b4b2 a3b2 b8b2 b1b2 e7b4 d3b3 b4d4 b2b1 c6b4 h1d1 d4e5 d1c1 d7a4 b3a3 a4b5 f2f4 g4f3",
+        "fen r3k2r/ppp1q1pp/2n1b3/8/3p4/6p1/PPPNQPP1/2K1RB1R w kq - 0 16 moves e2e6 e7e6 e1e6 e8f7 f2g3",
+        "fen 5q2/6Pk/8/6K1/8/8/8/8 w - - 0 1 moves g7f8n h7g8 f8e6",
+        "fen 8/P6k/8/8/8/8/1p4K1/8 w - - 0 1 moves a7a8q b2b1r a8b8 b1b8",
+        "fen 6k1/8/6K1/8/8/8/8/R7 w - - 0 1 moves a1a8",
+    ];
+
+    fn fnv(s: &str) -> u64 {
+        s.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3))
+    }
+
+    /// A digest of the value features and of the policy features of every legal move.
+    fn feature_digests(state: &State) -> (u64, u64) {
+        let moves = state.available_moves();
+        let value: Vec<_> = active_features(state, moves.as_slice()).into_iter()
+            .map(|(i, count)| format!("{}:{}", i, count))
+            .collect();
+        let mut policy: Vec<_> = moves.as_slice().iter()
+            .map(|mov| {
+                let indices: Vec<_> = policy_features::active_features(state, mov).into_iter()
+                    .map(|(i, _)| i.to_string())
+                    .collect();
+                format!("{}:{}", to_uci(mov, false), indices.join(","))
+            })
+            .collect();
+        policy.sort();
+        (fnv(&value.join(" ")), fnv(&policy.join(" ")))
+    }
+
+    fn corpus() -> Vec<String> {
+        let mut result = Vec::new();
+        for game in GAMES {
+            let (setup, moves) = match game.find(" moves ") {
+                Some(i) => (&game[..i], game[i + " moves ".len()..].split(' ').collect()),
+                None => (&game[..], Vec::new()),
+            };
+            for n in 0..moves.len() + 1 {
+                let position = format!("{} moves {}", setup, moves[..n].join(" "));
+                let state = State::from_tokens(position.split_whitespace(), false).expect(&position);
+                let (value, policy) = feature_digests(&state);
+                result.push(format!("{:016x} {:016x} {}", value, policy, position.trim_end()));
+            }
+        }
+        result
+    }
+
+    const CORPUS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/feature_corpus.txt");
+
+    /// The features of the corpus positions are the same as when `feature_corpus.txt` was written.
+    /// If they change on purpose, rewrite it with `cargo test -- --ignored write_feature_corpus`.
+    #[test]
+    fn features_match_corpus() {
+        let expected = include_str!("../testdata/feature_corpus.txt");
+        for (expected, actual) in expected.lines().zip(corpus()) {
+            assert_eq!(expected, actual);
+        }
+        assert_eq!(expected.lines().count(), corpus().len());
+    }
+
+    #[test]
+    #[ignore]
+    fn write_feature_corpus() {
+        let mut file = File::create(CORPUS_PATH).unwrap();
+        for line in corpus() {
+            writeln!(file, "{}", line).unwrap();
+        }
+    }
+}
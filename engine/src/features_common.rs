@@ -1,10 +1,24 @@
-use chess::*;
+use shakmaty::{Color, Role};
 
 pub const NUM_ROLES: usize = 6;
+pub const ROLES: [Role; NUM_ROLES] = [
+    Role::Pawn,
+    Role::Knight,
+    Role::Bishop,
+    Role::Rook,
+    Role::Queen,
+    Role::King];
 
-pub fn encode_pair(a: Piece, b: Piece) -> usize {
+pub fn encode_pair(a: Role, b: Role) -> usize {
     let x = 0;
     let x = x * NUM_ROLES + a as usize;
-    let x = x * NUM_ROLES + b as usize;
-    x
+    x * NUM_ROLES + b as usize
+}
+
+/// White first, which is the opposite of `Color as usize`.
+pub fn encode_color(c: Color) -> usize {
+    match c {
+        Color::White => 0,
+        Color::Black => 1,
+    }
 }
@@ -0,0 +1,54 @@
+//! Sashimi, a chess engine that searches with Monte Carlo tree search.
+//!
+//! Besides the `sashimi` binary, which speaks UCI, the engine can be embedded in-process:
+//!
+//! ```no_run
+//! extern crate sashimi;
+//! use sashimi::{Config, Engine, Limits, State};
+//! use std::time::Duration;
+//!
+//! let engine = Engine::new(Config::default());
+//! let state = State::from_moves(None, &["e2e4", "e7e5"]).unwrap();
+//! let result = engine.search(&state, Limits::Time(Duration::from_secs(1)));
+//! let pv: Vec<_> = result.pv.iter().map(|mov| sashimi::to_uci(mov, false)).collect();
+//! println!("{} ({:?})", pv.join(" "), result.wdl);
+//! for (mov, prior) in engine.policy(&state) {
+//!     println!("{} {}", sashimi::to_uci(&mov, false), prior);
+//! }
+//! ```
+
+#[macro_use]
+extern crate log;
+extern crate float_ord;
+extern crate smallvec;
+extern crate shakmaty;
+extern crate mcts;
+
+mod search;
+mod state;
+mod evaluation;
+mod features;
+mod policy_features;
+mod features_common;
+mod config;
+mod engine;
+mod explain;
+mod strength;
+
+// Used by the binary; not part of the API.
+#[doc(hidden)]
+pub mod annotate;
+#[doc(hidden)]
+pub mod args;
+#[doc(hidden)]
+pub mod training;
+#[doc(hidden)]
+pub mod uci;
+#[doc(hidden)]
+pub mod xboard;
+
+pub use config::Config;
+pub use engine::{Engine, Limits, SearchResult, Wdl};
+pub use search::{PolicyKind, to_uci};
+pub use state::{State, Move, BoardStatus};
+pub use strength::{Strength, MIN_ELO, MAX_ELO, REFERENCE_ELO, REFERENCE_PLAYOUTS};
@@ -1,28 +1,14 @@
 #[macro_use]
 extern crate log;
-extern crate float_ord;
-extern crate smallvec;
 extern crate simplelog;
-extern crate shakmaty;
-extern crate chess;
-extern crate mcts;
+extern crate sashimi;
 
 use simplelog::{WriteLogger, CombinedLogger, LevelFilter, Config, TermLogger};
 use std::fs::OpenOptions;
-
-mod uci;
-mod search;
-mod state;
-mod evaluation;
-mod features;
-mod policy_features;
-mod features_common;
-mod args;
-mod training;
+use sashimi::{annotate, args, training, uci, xboard};
 
 fn main() {
-    args::init();
-    let options = args::options();
+    let options = args::parse();
     let log_file = OpenOptions::new()
         .create(true)
         .append(true)
@@ -38,10 +24,20 @@ fn main() {
             Config::default()).unwrap()
         ]).unwrap();
     if let Some(ref train_pgn) = options.train_pgn {
-        training::train(&train_pgn, &options.train_output_path, options.policy);
+        training::train(train_pgn, &options.train_output_path, options.policy, &options.training);
+    } else if options.extra.first().map(|x| x.as_str()) == Some("annotate") {
+        if options.extra.len() != 3 {
+            eprintln!("usage: sashimi annotate <in.pgn> <out.pgn> [--nodes N]");
+            std::process::exit(2);
+        }
+        annotate::annotate(&options.extra[1], &options.extra[2], options.nodes, &options.config);
     } else {
         info!("Init.");
-        uci::main(options.extra.clone());
+        if options.xboard {
+            xboard::main(options.extra, options.config);
+        } else {
+            uci::main(options.extra, options.config);
+        }
         info!("Exit.");
     }
 }
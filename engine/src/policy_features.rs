@@ -1,21 +1,13 @@
 use features::FeatureVec;
-use state::{State, Move};
-use chess::*;
+use state::{State, Move, captured, destination};
+use shakmaty::{attacks, Bitboard, Color, Role, Square};
 use features_common::*;
 
 include!(concat!(env!("OUT_DIR"), "/policy_feature_const.rs"));
 const NUM_ADVS: usize = 5;
 const NUM_ENCODED: usize = NUM_ROLES * NUM_ROLES * NUM_ADVS;
-const ROLES: [Piece; NUM_ROLES] = [
-    Piece::Pawn,
-    Piece::Knight,
-    Piece::Bishop,
-    Piece::Rook,
-    Piece::Queen,
-    Piece::King];
 
 fn encode_advantage(adv: i32) -> usize {
-    assert_eq!(PAWN_P2 + 0, PAWN_P2);
     assert_eq!(PAWN_P2 + 1, PAWN_P1);
     assert_eq!(PAWN_P2 + 2, PAWN_P0);
     assert_eq!(PAWN_P2 + 3, PAWN_N1);
@@ -29,33 +21,32 @@ fn encode_advantage(adv: i32) -> usize {
     }
 }
 
-fn encode_axba(a: Piece, b: Piece, adv: i32) -> usize {
+fn encode_axba(a: Role, b: Role, adv: i32) -> usize {
     let adv_i = encode_advantage(adv);
     let x = encode_pair(a, b);
     assert!(adv_i < NUM_ADVS);
-    let x = x * NUM_ADVS + adv_i;
-    x
+    x * NUM_ADVS + adv_i
 }
 
-fn attacks(sq: Square, piece: Piece, color: Color, occ: BitBoard) -> BitBoard {
+fn attacks(sq: Square, piece: Role, color: Color, occ: Bitboard) -> Bitboard {
     match piece {
-        Piece::Rook => get_rook_moves(sq, occ),
-        Piece::Bishop => get_bishop_moves(sq, occ),
-        Piece::Queen => get_rook_moves(sq, occ) ^ get_bishop_moves(sq, occ),
-        Piece::King => get_king_moves(sq),
-        Piece::Knight => get_knight_moves(sq),
-        Piece::Pawn => get_pawn_attacks(sq, color, occ),
+        Role::Rook => attacks::rook_attacks(sq, occ),
+        Role::Bishop => attacks::bishop_attacks(sq, occ),
+        Role::Queen => attacks::queen_attacks(sq, occ),
+        Role::King => attacks::king_attacks(sq),
+        Role::Knight => attacks::knight_attacks(sq),
+        Role::Pawn => attacks::pawn_attacks(color, sq) & occ,
     }
 }
 
 fn rank_from_side(side: Color, sq: Square) -> usize {
     match side {
-        Color::White => sq.get_rank().to_index(),
-        Color::Black => 7 - sq.get_rank().to_index(),
+        Color::White => sq.rank() as usize,
+        Color::Black => 7 - sq.rank() as usize,
     }
 }
 
-fn encode_urgency(piece: Piece, adv: i32) -> usize {
+fn encode_urgency(piece: Role, adv: i32) -> usize {
     let x = 0;
     let x = x * NUM_ROLES + piece as usize;
     let x = x * NUM_ADVS + encode_advantage(adv);
@@ -65,88 +56,87 @@ fn encode_urgency(piece: Piece, adv: i32) -> usize {
 fn foreach_feature<F>(state: &State, mov: &Move, mut f: F) where F: FnMut(usize, u8) {
     let mut f = |x| f(x, 1);
     let board = state.board();
-    let our_role = board.piece_on(mov.get_source()).unwrap();
-    let src_bb = BitBoard::from_square(mov.get_source());
-    let dst_bb = BitBoard::from_square(mov.get_dest());
-    let without_src = board.combined() & !src_bb;
+    let turn = state.turn();
+    let our_role = mov.role();
+    let src = mov.from().unwrap();
+    let dst = destination(mov);
+    let src_bb = Bitboard::from_square(src);
+    let dst_bb = Bitboard::from_square(dst);
+    let without_src = board.occupied() & !src_bb;
     let occ = without_src & !dst_bb;
-    let src_adv = get_advantage(state, without_src, mov.get_source());
-    let dst_adv = get_advantage(state, occ, mov.get_dest());
-    let taken = board.piece_on(mov.get_dest());
+    let src_adv = get_advantage(state, without_src, src);
+    let dst_adv = get_advantage(state, occ, dst);
+    let taken = captured(mov);
     f(encode_axba(
         our_role,
-        taken.unwrap_or(Piece::King),
+        taken.unwrap_or(Role::King),
         dst_adv));
     f(encode_urgency(our_role, src_adv));
-    let follow_ups = attacks(mov.get_dest(), our_role, board.side_to_move(), occ);
-    let enemies = board.color_combined(!board.side_to_move());
+    let follow_ups = attacks(dst, our_role, turn, occ);
+    let enemies = board.by_color(!turn);
     for &hit_role in &ROLES {
-        if (follow_ups & enemies & board.pieces(hit_role)).0 != 0 {
+        if (follow_ups & enemies & board.by_role(hit_role)).any() {
             f(PAWN_HITS_PAWN + encode_pair(our_role, hit_role));
         }
     }
-    if let Some(prev_move) = state.prev_move().as_ref() {
-        if mov.get_dest() == prev_move.get_dest() {
+    if let Some(prev_move) = state.prev_move() {
+        if dst == destination(prev_move) {
             if let Some(captured) = state.prev_capture() {
                 f(PAWN_RECAPTURES_PAWN + encode_pair(our_role, captured));
             } else {
                 f(CAPTURES_LAST_MOVE);
             }
         }
-        if mov.get_dest() == prev_move.get_source() {
+        if Some(dst) == prev_move.from() {
             f(TAKES_OLD_PLACE);
         }
     }
     {
-        let betw = between(mov.get_source(), mov.get_dest());
+        let betw = attacks::between(src, dst);
         let former = state.formerly_occupied();
-        if (betw & former[0]).0 != 0 { f(CROSSES_FORMERLY_OCCUPIED_0); }
-        if (betw & former[1]).0 != 0 { f(CROSSES_FORMERLY_OCCUPIED_1); }
-        if (betw & former[2]).0 != 0 { f(CROSSES_FORMERLY_OCCUPIED_2); }
-        if (betw & former[3]).0 != 0 { f(CROSSES_FORMERLY_OCCUPIED_3); }
-        if (dst_bb & former[0]).0 != 0 { f(TAKES_FORMERLY_OCCUPIED_0); }
-        if (dst_bb & former[1]).0 != 0 { f(TAKES_FORMERLY_OCCUPIED_1); }
-        if (dst_bb & former[2]).0 != 0 { f(TAKES_FORMERLY_OCCUPIED_2); }
-        if (dst_bb & former[3]).0 != 0 { f(TAKES_FORMERLY_OCCUPIED_3); }
-        if (src_bb & former[0]).0 != 0 { f(FROM_FORMERLY_OCCUPIED_0); }
-        if (src_bb & former[1]).0 != 0 { f(FROM_FORMERLY_OCCUPIED_1); }
-        if (src_bb & former[2]).0 != 0 { f(FROM_FORMERLY_OCCUPIED_2); }
-        if (src_bb & former[3]).0 != 0 { f(FROM_FORMERLY_OCCUPIED_3); }
+        if (betw & former[0]).any() { f(CROSSES_FORMERLY_OCCUPIED_0); }
+        if (betw & former[1]).any() { f(CROSSES_FORMERLY_OCCUPIED_1); }
+        if (betw & former[2]).any() { f(CROSSES_FORMERLY_OCCUPIED_2); }
+        if (betw & former[3]).any() { f(CROSSES_FORMERLY_OCCUPIED_3); }
+        if (dst_bb & former[0]).any() { f(TAKES_FORMERLY_OCCUPIED_0); }
+        if (dst_bb & former[1]).any() { f(TAKES_FORMERLY_OCCUPIED_1); }
+        if (dst_bb & former[2]).any() { f(TAKES_FORMERLY_OCCUPIED_2); }
+        if (dst_bb & former[3]).any() { f(TAKES_FORMERLY_OCCUPIED_3); }
+        if (src_bb & former[0]).any() { f(FROM_FORMERLY_OCCUPIED_0); }
+        if (src_bb & former[1]).any() { f(FROM_FORMERLY_OCCUPIED_1); }
+        if (src_bb & former[2]).any() { f(FROM_FORMERLY_OCCUPIED_2); }
+        if (src_bb & former[3]).any() { f(FROM_FORMERLY_OCCUPIED_3); }
     }
-    let f_src = mov.get_source().get_file();
-    let f_dst = mov.get_dest().get_file();
-    if our_role == Piece::King &&
-            f_src != f_dst.left() &&
-            f_src != f_dst &&
-            f_src != f_dst.right() {
-        if f_src.to_index() < f_dst.to_index() {
+    if let Move::Castle {king, rook} = *mov {
+        // The names are the wrong way round: CASTLE_LONG is castling towards the h file.
+        if king.file() < rook.file() {
             f(CASTLE_LONG);
         } else {
             f(CASTLE_SHORT);
         }
     }
-    if our_role == Piece::Pawn {
-        let src_rank = rank_from_side(board.side_to_move(), mov.get_source());
-        let dst_rank = rank_from_side(board.side_to_move(), mov.get_dest());
+    if our_role == Role::Pawn {
+        let src_rank = rank_from_side(turn, src);
+        let dst_rank = rank_from_side(turn, dst);
         f(PAWN_TO_RANK_1 + dst_rank);
         if dst_rank == src_rank + 2 {
             f(PAWN_DOUBLE_MOVE);
         }
-        if mov.get_source().get_file() != mov.get_dest().get_file() && taken == None {
+        if src.file() != dst.file() && taken.is_none() {
             f(EN_PASSANT);
         }
     }
-    match mov.get_promotion() {
-        Some(Piece::Queen) => f(PROMOTE_QUEEN),
-        Some(Piece::Rook) => f(PROMOTE_ROOK),
-        Some(Piece::Knight) => f(PROMOTE_KNIGHT),
-        Some(Piece::Bishop) => f(PROMOTE_BISHOP),
+    match mov.promotion() {
+        Some(Role::Queen) => f(PROMOTE_QUEEN),
+        Some(Role::Rook) => f(PROMOTE_ROOK),
+        Some(Role::Knight) => f(PROMOTE_KNIGHT),
+        Some(Role::Bishop) => f(PROMOTE_BISHOP),
         _ => (),
     };
-    if (dst_bb & board.checkers()).0 != 0 {
+    if (dst_bb & state.checkers()).any() {
         f(TAKES_CHECKER);
     }
-    if (src_bb & board.pinned()).0 != 0 {
+    if (src_bb & state.pinned()).any() {
         f(MOVES_PINNED);
     }
 }
@@ -174,10 +164,23 @@ fn evaluate_single(state: &State, mov: &Move) -> f32 {
     result
 }
 
-pub fn evaluate_moves(state: &State, moves: &[Move]) -> Vec<f32> {
-    let mut evalns: Vec<_> = moves.iter()
+/// The features that are active for `mov`, with their weights, in increasing order of index.
+pub fn active_features(state: &State, mov: &Move) -> Vec<(usize, f32)> {
+    let mut result = Vec::new();
+    foreach_feature(state, mov, |i, _| result.push((i, COEF[i])));
+    result.sort_by_key(|x| x.0);
+    result
+}
+
+/// The logits of the policy, before they are turned into probabilities by `evaluate_moves`.
+pub fn evaluate_move_logits(state: &State, moves: &[Move]) -> Vec<f32> {
+    moves.iter()
         .map(|x| evaluate_single(state, x))
-        .collect();
+        .collect()
+}
+
+pub fn evaluate_moves(state: &State, moves: &[Move]) -> Vec<f32> {
+    let mut evalns = evaluate_move_logits(state, moves);
     softmax(&mut evalns);
     evalns
 }
@@ -200,7 +203,7 @@ fn name_feature_uc(idx: usize) -> String {
             for &r2 in &ROLES {
                 for adv in (-2)..3 {
                     if encode_axba(r1, r2, adv) == idx {
-                        return format!("{}_x_{}_{}", r1, r2, adv);
+                        return format!("{}_x_{}_{}", r1.char(), r2.char(), adv);
                     }
                 }
             }
@@ -213,54 +216,50 @@ pub fn name_feature(idx: usize) -> String {
     name_feature_uc(idx).to_lowercase()
 }
 
-fn get_advantage(state: &State, occ: BitBoard, to: Square) -> i32 {
+fn get_advantage(state: &State, occ: Bitboard, to: Square) -> i32 {
     let board = state.board();
-    
-    let b = get_bishop_moves(to, occ);
-    let r = get_rook_moves(to, occ);
-    let n = get_knight_moves(to);
-    let k = get_king_moves(to);
+    let b = attacks::bishop_attacks(to, occ);
+    let r = attacks::rook_attacks(to, occ);
+    let n = attacks::knight_attacks(to);
+    let k = attacks::king_attacks(to);
     let q = b ^ r;
 
-    let b = b & board.pieces(Piece::Bishop);
-    let r = r & board.pieces(Piece::Rook);
-    let n = n & board.pieces(Piece::Knight);
-    let k = k & board.pieces(Piece::King);
-    let q = q & board.pieces(Piece::Queen);
+    let b = b & board.bishops();
+    let r = r & board.rooks();
+    let n = n & board.knights();
+    let k = k & board.kings();
+    let q = q & board.queens();
 
     let atk = b ^ r ^ n ^ k ^ q;
 
-    let turn = board.side_to_move();
-    let fre = board.color_combined(turn);
-    let ene = board.color_combined(!turn);
+    let turn = state.turn();
+    let fre = board.by_color(turn);
+    let ene = board.by_color(!turn);
 
-    let fp = get_pawn_attacks(to, !turn, occ) & board.pieces(Piece::Pawn) & fre;
-    let ep = get_pawn_attacks(to, turn, occ) & board.pieces(Piece::Pawn) & ene;
+    let fp = attacks::pawn_attacks(!turn, to) & occ & board.pawns() & fre;
+    let ep = attacks::pawn_attacks(turn, to) & occ & board.pawns() & ene;
 
     let fre_atk = (atk & fre) ^ fp;
     let ene_atk = (atk & ene) ^ ep;
 
-    fre_atk.popcnt() as i32 - ene_atk.popcnt() as i32
+    fre_atk.count() as i32 - ene_atk.count() as i32
 }
 
 #[cfg(test)]
 #[test]
 fn test_advantage() {
     let state = State::default();
-    let occ = state.board().combined();
+    let occ = state.board().occupied();
     {
-        let sq = Square::make_square(Rank::Sixth, File::C);
-        let adv = get_advantage(&state, occ, sq);
+        let adv = get_advantage(&state, occ, Square::C6);
         assert_eq!(adv, -3);
     }
     {
-        let sq = Square::make_square(Rank::Third, File::C);
-        let adv = get_advantage(&state, occ, sq);
+        let adv = get_advantage(&state, occ, Square::C3);
         assert_eq!(adv, 3);
     }
     {
-        let sq = Square::make_square(Rank::Second, File::E);
-        let adv = get_advantage(&state, occ, sq);
+        let adv = get_advantage(&state, occ, Square::E2);
         assert_eq!(adv, 4);
     }
 }
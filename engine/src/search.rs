@@ -1,30 +1,132 @@
-use mcts::{MCTS, MCTSManager, AsyncSearchOwned, CycleBehaviour};
-use mcts::tree_policy::AlphaGoPolicy;
+use mcts::{MCTS, MCTSManager, AsyncSearchOwned, CycleBehaviour, GameState, Moves, MoveInfoHandle, SearchHandle};
+use mcts::tree_policy::{TreePolicy, AlphaGoPolicy, PUCTParameters, UCB1TunedPolicy, VariancePUCTPolicy,
+                        PolicyRng, validate_probabilities};
 use mcts::transposition_table::ApproxTable;
-use state::{State, Move};
+use mcts::gumbel::GumbelRoot;
+use state::{State, Move, destination};
 use std::sync::mpsc::Sender;
-use std::thread;
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
 use std::cmp::max;
+use std::str::FromStr;
 use uci::{TIMEUP, Tokens};
 use evaluation::GooseEval;
 use features::Model;
-use args::options;
-use chess::Piece;
+use policy_features::evaluate_move_logits;
+use config::Config;
+use strength;
 
 const DEFAULT_MOVE_TIME_SECS: u64 = 10;
+const NODE_LIMIT: usize = 4_000_000;
+const PV_LENGTH: usize = 10;
+// Rough size of a node with its move list, used to size the transposition table.
+const BYTES_PER_NODE: usize = 1 << 10;
 
-pub const SCALE: f32 = 1e9;
+const EXPLORATION_CONSTANT: f32 = 5.0;
 
-fn policy() -> AlphaGoPolicy {
-    AlphaGoPolicy::new(5.0 * SCALE)
+/// The tree policies that can be selected with `--tree-policy`, for A/B testing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PolicyKind {
+    #[default]
+    AlphaGo,
+    VariancePUCT,
+    UCB1Tuned,
 }
 
-fn num_threads() -> usize {
-    max(1, options().num_threads)
+impl FromStr for PolicyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "alphago" => Ok(PolicyKind::AlphaGo),
+            "variance-puct" => Ok(PolicyKind::VariancePUCT),
+            "ucb1-tuned" => Ok(PolicyKind::UCB1Tuned),
+            _ => Err(format!("unknown tree policy {} (expected alphago, variance-puct or ucb1-tuned)", s)),
+        }
+    }
+}
+
+pub enum GoosePolicy {
+    AlphaGo(AlphaGoPolicy),
+    VariancePUCT(VariancePUCTPolicy),
+    // Ignores the move evaluations.
+    UCB1Tuned(UCB1TunedPolicy),
+}
+
+impl GoosePolicy {
+    fn new(mcts: &GooseMCTS) -> Self {
+        match mcts.tree_policy {
+            PolicyKind::AlphaGo => {
+                let params = PUCTParameters {
+                    cpuct_factor: mcts.cpuct_factor,
+                    fpu_reduction: mcts.fpu_reduction,
+                    ..PUCTParameters::new(EXPLORATION_CONSTANT)
+                };
+                GoosePolicy::AlphaGo(AlphaGoPolicy::with_parameters(params.clone(), params))
+            }
+            // Rewards are in [-1, 1], so a variance of 1 is the worst case.
+            PolicyKind::VariancePUCT => GoosePolicy::VariancePUCT(VariancePUCTPolicy::new(EXPLORATION_CONSTANT, 1.0)),
+            PolicyKind::UCB1Tuned => GoosePolicy::UCB1Tuned(UCB1TunedPolicy::new(1.0, 2.0)),
+        }
+    }
 }
 
-pub struct GooseMCTS;
+impl TreePolicy<GooseMCTS> for GoosePolicy {
+    type MoveEvaluation = f32;
+    type ThreadLocalData = PolicyRng;
+
+    fn choose_child<'a>(&self, _: &State, moves: Moves<'a, GooseMCTS>, mut handle: SearchHandle<GooseMCTS>)
+        -> MoveInfoHandle<'a, GooseMCTS>
+    {
+        let is_root = handle.depth() == 0;
+        let rng = &mut handle.thread_data().policy_data;
+        match *self {
+            GoosePolicy::AlphaGo(ref policy) => policy.select_child(moves, rng, is_root),
+            GoosePolicy::VariancePUCT(ref policy) => policy.select_child(moves, rng),
+            GoosePolicy::UCB1Tuned(ref policy) => policy.select_child(moves, rng),
+        }
+    }
+    fn validate_evaluations(&self, evalns: &[f32]) {
+        validate_probabilities(evalns);
+    }
+    fn needs_sum_of_squares(&self) -> bool {
+        match *self {
+            GoosePolicy::AlphaGo(_) => false,
+            GoosePolicy::VariancePUCT(_) | GoosePolicy::UCB1Tuned(_) => true,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct GooseMCTS {
+    pub graph_search: bool,
+    pub memory_limit: Option<usize>,
+    pub huge_pages: bool,
+    pub tree_policy: PolicyKind,
+    pub cpuct_factor: f32,
+    pub fpu_reduction: Option<f32>,
+    pub gumbel_root: Option<GumbelRoot>,
+}
+
+impl GooseMCTS {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            graph_search: config.graph_search,
+            memory_limit: config.memory_limit_mb.map(|mb| mb << 20),
+            huge_pages: config.huge_pages,
+            tree_policy: config.tree_policy,
+            cpuct_factor: config.cpuct_factor,
+            fpu_reduction: config.fpu_reduction,
+            gumbel_root: None,
+        }
+    }
+    fn table_capacity(&self) -> usize {
+        match self.memory_limit {
+            Some(bytes) => bytes / BYTES_PER_NODE,
+            None => NODE_LIMIT,
+        }
+    }
+}
 pub struct ThreadSentinel;
 
 impl Default for ThreadSentinel {
@@ -41,81 +143,215 @@ impl Drop for ThreadSentinel {
 
 impl MCTS for GooseMCTS {
     type State = State;
+    type Reward = f64;
     type Eval = GooseEval;
-    type TreePolicy = AlphaGoPolicy;
+    type TreePolicy = GoosePolicy;
     type NodeData = ();
     type ExtraThreadData = ThreadSentinel;
     type TranspositionTable = ApproxTable<Self>;
     type PlayoutData = ();
 
     fn node_limit(&self) -> usize {
-        4_000_000
+        if self.memory_limit.is_some() {
+            usize::MAX
+        } else {
+            NODE_LIMIT
+        }
+    }
+    fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
     }
-    fn virtual_loss(&self) -> i64 {
-        SCALE as i64
+    fn virtual_loss(&self) -> f64 {
+        1.
     }
     fn cycle_behaviour(&self) -> CycleBehaviour<Self> {
-        CycleBehaviour::UseThisEvalWhenCycleDetected(0)
+        CycleBehaviour::UseThisEvalWhenCycleDetected(0.)
     }
+    fn graph_search(&self) -> bool {
+        self.graph_search
+    }
+    fn huge_pages(&self) -> bool {
+        self.huge_pages
+    }
+    fn gumbel_root(&self) -> Option<&GumbelRoot> {
+        self.gumbel_root.as_ref()
+    }
+}
+
+/// A Gumbel root search of `budget` playouts from `state`, which needs the policy's logits
+/// rather than its probabilities.
+fn gumbel_root(state: &State, budget: u64, seed: u64) -> GumbelRoot {
+    let moves = state.available_moves();
+    GumbelRoot::new(&evaluate_move_logits(state, moves.as_slice()), budget, seed)
 }
 
 pub struct Search {
     search: AsyncSearchOwned<GooseMCTS>,
+    /// The move being chosen in the background at a limited strength, which is printed when the
+    /// search is stopped. It is `None` if the game is over.
+    limited: Option<JoinHandle<Option<Move>>>,
+    config: Config,
+}
+
+/// What a search has found so far, for the front ends to print.
+pub struct Report {
+    pub best_move: Move,
+    pub pv: Vec<Move>,
+    /// The expected result of `best_move` for the side to move, from -100 to 100.
+    pub score_cp: i64,
+    pub nodes: usize,
+    pub chess960: bool,
+}
+
+impl Report {
+    fn new(manager: &MCTSManager<GooseMCTS>) -> Option<Self> {
+        let best_move = manager.best_move()?;
+        Some(Report {
+            best_move,
+            pv: manager.principal_variation(PV_LENGTH),
+            score_cp: manager.principal_variation_info(1).first()
+                .map(|x| (x.sum_rewards() / x.visits() as f64 * 100.) as i64)
+                .unwrap_or(0),
+            nodes: manager.tree().num_nodes(),
+            chess960: manager.tree().root_state().is_chess960(),
+        })
+    }
+    /// The principal variation in UCI notation, with a space before every move.
+    pub fn pv_string(&self) -> String {
+        self.pv.iter()
+            .map(|x| format!(" {}", to_uci(x, self.chess960)))
+            .collect()
+    }
 }
 
 impl Search {
+    #[cfg(test)]
     pub fn create_manager(state: State) -> MCTSManager<GooseMCTS> {
+        Self::create_manager_with(state, GooseMCTS::default())
+    }
+    pub fn create_manager_with(state: State, mcts: GooseMCTS) -> MCTSManager<GooseMCTS> {
+        let table = ApproxTable::enough_to_hold(mcts.table_capacity());
+        let policy = GoosePolicy::new(&mcts);
         MCTSManager::new(
             state.freeze(),
-            GooseMCTS,
+            mcts,
             GooseEval::from(Model::new()),
-            policy(),
-            ApproxTable::enough_to_hold(GooseMCTS.node_limit()))
+            policy,
+            table)
     }
-    pub fn new(state: State) -> Self {
-        let search = Self::create_manager(state).into();
-        Self {search}
+    /// A manager that searches `state` as `config` says.
+    pub fn create_manager_from_config(state: State, config: &Config) -> MCTSManager<GooseMCTS> {
+        let mut mcts = GooseMCTS::from_config(config);
+        mcts.gumbel_root = config.gumbel_playouts.map(|budget| gumbel_root(&state, budget, config.seed));
+        let mut manager = Self::create_manager_with(state, mcts);
+        manager.seed(config.seed);
+        manager
     }
-    fn stop_and_print_m(self) -> MCTSManager<GooseMCTS> {
-        if self.search.num_threads() == 0 {
-            return self.search.halt();
+    pub fn new(state: State, config: &Config) -> Self {
+        Self {
+            search: Self::create_manager_from_config(state, config).into(),
+            limited: None,
+            config: config.clone(),
         }
-        let manager = self.search.halt();
-        if let Some(mov) = manager.best_move() {
+    }
+    fn stop_and_print_m(self) -> (MCTSManager<GooseMCTS>, Config) {
+        let Search {search, limited, config} = self;
+        if let Some(limited) = limited {
+            let manager = search.halt();
+            let chess960 = manager.tree().root_state().is_chess960();
+            let best_move = limited.join().expect("the limited strength search panicked");
+            println!("bestmove {}", best_move.map_or("0000".into(), |mov| to_uci(&mov, chess960)));
+            return (manager, config);
+        }
+        if search.num_threads() == 0 {
+            return (search.halt(), config);
+        }
+        let manager = search.halt();
+        if let Some(report) = Report::new(&manager) {
             let info_str = format!("info depth {} score cp {} pv{}",
-                                   manager.tree().num_nodes(),
-                                   manager.principal_variation_info(1)
-                                   .get(0)
-                                   .map(|x| x.sum_rewards() / x.visits() as i64 / (SCALE / 100.) as i64)
-                                   .unwrap_or(0),
-                                   get_pv(&manager));
+                                   report.nodes, report.score_cp, report.pv_string());
             info!("{}", info_str);
             println!("{}", info_str);
-            println!("bestmove {}", to_uci(mov));
+            println!("bestmove {}", to_uci(&report.best_move, report.chess960));
+        }
+        (manager.reset(), config)
+    }
+    /// Stops searching without printing anything, and returns what the search found. At a limited
+    /// strength, that is only the move, and it waits for the move to be chosen.
+    pub fn stop(self) -> Option<Report> {
+        let manager = self.search.halt();
+        match self.limited {
+            Some(limited) => {
+                let best_move = limited.join().expect("the limited strength search panicked")?;
+                Some(Report {
+                    pv: vec![best_move.clone()],
+                    best_move,
+                    score_cp: 0,
+                    nodes: 0,
+                    chess960: manager.tree().root_state().is_chess960(),
+                })
+            }
+            None => Report::new(&manager),
         }
-        let manager = manager.reset();
-        manager
+    }
+    /// What the search has found so far. It goes on searching afterwards, without a time limit.
+    pub fn peek(self) -> (Self, Option<Report>) {
+        let Search {search, limited, config} = self;
+        let num_threads = search.num_threads();
+        let manager = search.halt();
+        let report = Report::new(&manager);
+        let search = if num_threads == 0 {
+            manager.into()
+        } else {
+            manager.into_playout_parallel_async(num_threads)
+        };
+        (Self {search, limited, config}, report)
     }
     pub fn stop_and_print(self) -> Self {
+        let (manager, config) = self.stop_and_print_m();
         Self {
-            search: self.stop_and_print_m().into()
+            search: manager.into(),
+            limited: None,
+            config,
         }
     }
     pub fn go(self, mut tokens: Tokens, position_num: u64, sender: &Sender<String>) -> Self {
-        let manager = self.stop_and_print_m();
+        let (manager, config) = self.stop_and_print_m();
         let mut think_time = Some(Duration::from_secs(DEFAULT_MOVE_TIME_SECS));
         while let Some(s) = tokens.next() {
             match s {
                 "movetime" => {
-                    let t = tokens.next().unwrap_or("".into());
+                    let t = tokens.next().unwrap_or("");
                     if let Ok(t) = t.parse() {
                         think_time = Some(Duration::from_millis(t));
                     }
                 }
-                "infinite" => think_time = None,
+                "infinite" | "ponder" => think_time = None,
                 _ => (),
             }
         }
+        if let Some(elo) = config.elo {
+            let info_str = format!("info string playing at Elo {} ({:?})", elo, strength::Strength::for_elo(elo));
+            info!("{}", info_str);
+            println!("{}", info_str);
+            return Self::start_limited(manager, config, elo, think_time.is_some(), position_num, sender);
+        }
+        Self::start(manager, config, think_time, position_num, sender)
+    }
+    /// Searches in the background until stopped, or for `think_time` if there is one, after which
+    /// `TIMEUP` and `position_num` are sent to `sender`. Nothing is printed.
+    pub fn think(self, think_time: Option<Duration>, position_num: u64, sender: &Sender<String>) -> Self {
+        let Search {search, config, ..} = self;
+        Self::start(search.halt(), config, think_time, position_num, sender)
+    }
+    /// Chooses a move at `elo` in the background, after which `TIMEUP` and `position_num` are sent
+    /// to `sender`. Nothing is printed.
+    pub fn think_limited(self, elo: u32, position_num: u64, sender: &Sender<String>) -> Self {
+        let Search {search, config, ..} = self;
+        Self::start_limited(search.halt(), config, elo, true, position_num, sender)
+    }
+    fn start(manager: MCTSManager<GooseMCTS>, config: Config, think_time: Option<Duration>,
+             position_num: u64, sender: &Sender<String>) -> Self {
         if let Some(t) = think_time {
             let sender = sender.clone();
             thread::spawn(move || {
@@ -124,32 +360,72 @@ impl Search {
             });
         }
         Self {
-            search: manager.into_playout_parallel_async(num_threads())
+            search: manager.into_playout_parallel_async(max(1, config.num_threads)),
+            limited: None,
+            config,
+        }
+    }
+    /// Chooses a move at `elo` in the background. Limited strength searches a fixed number of
+    /// playouts, so it ignores the clock: `TIMEUP` is sent as soon as the move is chosen, unless
+    /// `timed` is false, in which case the move waits for the search to be stopped.
+    fn start_limited(manager: MCTSManager<GooseMCTS>, config: Config, elo: u32, timed: bool,
+                     position_num: u64, sender: &Sender<String>) -> Self {
+        let state = manager.tree().root_state().clone();
+        let limited_config = config.clone();
+        let sender = sender.clone();
+        let limited = thread::spawn(move || {
+            let best_move = strength::choose_move(&state, &limited_config, elo);
+            if timed {
+                let _ = sender.send(format!("{} {}", TIMEUP, position_num));
+            }
+            best_move
+        });
+        Self {
+            search: manager.into(),
+            limited: Some(limited),
+            config,
+        }
+    }
+    pub fn set_seed(self, seed: u64) -> Self {
+        let (mut manager, mut config) = self.stop_and_print_m();
+        manager.seed(seed);
+        config.seed = seed;
+        Self {
+            search: manager.into(),
+            limited: None,
+            config,
+        }
+    }
+    /// Limits the strength to `elo`, or plays at full strength if `None`.
+    pub fn set_elo(self, elo: Option<u32>) -> Self {
+        let (manager, mut config) = self.stop_and_print_m();
+        config.elo = elo;
+        Self {
+            search: manager.into(),
+            limited: None,
+            config,
         }
     }
     pub fn nodes_per_sec(self) -> Self {
-        let mut manager = self.stop_and_print_m().reset();
-        manager.perf_test_to_stderr(num_threads());
+        let (manager, config) = self.stop_and_print_m();
+        let mut manager = manager.reset();
+        manager.perf_test_to_stderr(max(1, config.num_threads));
         Self {
-            search: manager.into()
+            search: manager.into(),
+            limited: None,
+            config,
         }
     }
 }
 
-fn to_uci(mov: Move) -> String {
-    let promo = match mov.get_promotion() {
-        Some(Piece::Queen) => "q",
-        Some(Piece::Rook) => "r",
-        Some(Piece::Knight) => "n",
-        Some(Piece::Bishop) => "b",
-        Some(_) => unreachable!(),
-        None => "",
-    };
-    format!("{}{}{}", mov.get_source(), mov.get_dest(), promo)
-}
-
-fn get_pv(m: &MCTSManager<GooseMCTS>) -> String {
-    m.principal_variation(10).into_iter()
-        .map(|x| format!(" {}", to_uci(x)))
-        .collect()
+/// A move in the notation of the UCI protocol, like `e2e4` or `e7e8q`. Castling is written as the
+/// king's move, like `e1g1`, or in Chess960 as the king taking its own rook, like `e1h1`.
+pub fn to_uci(mov: &Move, chess960: bool) -> String {
+    match *mov {
+        Move::Castle {king, rook} if chess960 => format!("{}{}", king, rook),
+        ref mov => {
+            let promo = mov.promotion().map(|role| role.char().to_string()).unwrap_or_default();
+            format!("{}{}{}", mov.from().unwrap(), destination(mov), promo)
+        }
+    }
 }
@@ -3,39 +3,51 @@ use mcts::GameState;
 use mcts::transposition_table::TranspositionHash;
 use uci::Tokens;
 use shakmaty;
-use shakmaty::Position;
-use chess;
-use std::iter::IntoIterator;
+use shakmaty::{attacks, Bitboard, Chess, Color, Position, Role, Setup, Square};
 use std::cmp::max;
-use std;
 
-pub type Player = chess::Color;
-pub type Move = chess::ChessMove;
+pub type Player = Color;
+pub type Move = shakmaty::Move;
+pub type MoveList = Vec<Move>;
 
 pub const NUM_OCCUPIED_KEPT: usize = 4;
 
+/// How the game stands. A draw by repetition counts as a stalemate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoardStatus {
+    Ongoing,
+    Stalemate,
+    Checkmate,
+}
+
 pub struct StateBuilder {
-    initial_state: shakmaty::Chess,
-    crnt_state: shakmaty::Chess,
-    moves: Vec<shakmaty::Move>,
+    initial_state: Chess,
+    crnt_state: Chess,
+    moves: Vec<Move>,
+    chess960: bool,
 }
 
 impl StateBuilder {
-    pub fn chess(&self) -> &shakmaty::Chess {
+    pub fn chess(&self) -> &Chess {
         &self.crnt_state
     }
-    pub fn make_move(&mut self, mov: shakmaty::Move) {
+    pub fn make_move(&mut self, mov: Move) {
         self.crnt_state = self.crnt_state.clone().play(&mov).unwrap();
         self.moves.push(mov);
     }
     pub fn from_fen(fen: &str) -> Option<Self> {
         Some(fen
             .parse::<shakmaty::fen::Fen>().ok()?
-            .position::<shakmaty::Chess>().ok()?
+            .position::<Chess>().ok()?
             .into())
     }
-    pub fn from_tokens(mut tokens: Tokens) -> Option<Self> {
-        let mut result = match tokens.next()? {
+    /// Plays Chess960, where castling moves are written as the king taking its own rook.
+    pub fn chess960(mut self, chess960: bool) -> Self {
+        self.chess960 = chess960;
+        self
+    }
+    pub fn from_tokens(mut tokens: Tokens, chess960: bool) -> Option<Self> {
+        let result = match tokens.next()? {
             "startpos" => Self::default(),
             "fen" => {
                 let mut s = String::new();
@@ -49,67 +61,126 @@ impl StateBuilder {
             },
             _ => return None,
         };
+        let mut result = result.chess960(chess960);
         match tokens.next() {
             Some("moves") => (),
             Some(_) => return None,
             None => (),
         };
         for mov_str in tokens {
-            let uci = mov_str.parse::<shakmaty::uci::Uci>().ok()?;
-            let mov = uci.to_move(result.chess()).ok()?;
-            result.make_move(mov);
+            result.make_uci_move(mov_str)?;
         }
         Some(result)
     }
+    /// Plays a move in UCI notation, like `e2e4`. Returns `None` if it isn't legal.
+    pub fn make_uci_move(&mut self, mov_str: &str) -> Option<()> {
+        let uci = mov_str.parse::<shakmaty::uci::Uci>().ok()?;
+        let mov = uci.to_move(self.chess()).ok()?;
+        self.make_move(mov);
+        Some(())
+    }
     pub fn extract(&self) -> (State, Vec<Move>) {
-        let state = StateBuilder::from(self.initial_state.clone()).into();
-        let moves = self.moves.iter().map(|m| convert_move(m)).collect();
-        (state, moves)
+        let state = StateBuilder::from(self.initial_state.clone()).chess960(self.chess960).into();
+        (state, self.moves.clone())
     }
 }
 
 #[derive(Clone)]
 pub struct State {
-    board: chess::Board,
-    prev_move: Option<chess::ChessMove>,
-    prev_capture: Option<chess::Piece>,
+    chess: Chess,
+    hash: u64,
+    // Kept for the features, which ask for them once per move.
+    checkers: Bitboard,
+    pinned: Bitboard,
+    prev_move: Option<Move>,
+    prev_capture: Option<Role>,
     prev_state_hashes: SmallVec<[u64; 64]>,
     repetitions: usize,
-    formerly_occupied: [chess::BitBoard; NUM_OCCUPIED_KEPT],
+    formerly_occupied: [Bitboard; NUM_OCCUPIED_KEPT],
     frozen: bool,
     queens_off: bool,
-    move_lists: [Vec<chess::ChessMove>; 2],
+    move_lists: [Vec<Move>; 2],
+    chess960: bool,
 }
 
 impl State {
-    pub fn from_tokens(tokens: Tokens) -> Option<Self> {
-        StateBuilder::from_tokens(tokens).map(|x| x.into())
+    pub fn from_tokens(tokens: Tokens, chess960: bool) -> Option<Self> {
+        StateBuilder::from_tokens(tokens, chess960).map(|x| x.into())
     }
-    #[cfg(test)]
     pub fn from_fen(fen: &str) -> Option<Self> {
         StateBuilder::from_fen(fen).map(|x| x.into())
     }
-    pub fn prev_move(&self) -> Option<chess::ChessMove> {
-        self.prev_move
+    /// The position after `moves`, in UCI notation, from `fen` (or the starting position if `None`).
+    /// The moves are replayed rather than skipped, so repetitions among them count towards a draw.
+    pub fn from_moves(fen: Option<&str>, moves: &[&str]) -> Option<Self> {
+        let mut builder = match fen {
+            Some(fen) => StateBuilder::from_fen(fen)?,
+            None => StateBuilder::default(),
+        };
+        for mov in moves {
+            builder.make_uci_move(mov)?;
+        }
+        Some(builder.into())
+    }
+    /// Like `from_moves`, but for Chess960. `fen` may be an X-FEN or a Shredder-FEN, and castling
+    /// moves are written as the king taking its own rook, like `e1h1`.
+    pub fn chess960_from_moves(fen: &str, moves: &[&str]) -> Option<Self> {
+        let mut builder = StateBuilder::from_fen(fen)?.chess960(true);
+        for mov in moves {
+            builder.make_uci_move(mov)?;
+        }
+        Some(builder.into())
+    }
+    /// `mov_str`, in UCI notation, as a legal move in this position.
+    pub fn parse_uci(&self, mov_str: &str) -> Option<Move> {
+        mov_str.parse::<shakmaty::uci::Uci>().ok()?.to_move(&self.chess).ok()
+    }
+    pub fn prev_move(&self) -> Option<&Move> {
+        self.prev_move.as_ref()
     }
-    pub fn prev_capture(&self) -> Option<chess::Piece> {
+    pub fn prev_capture(&self) -> Option<Role> {
         self.prev_capture
     }
-    pub fn board(&self) -> &chess::Board {
-        &self.board
+    pub fn chess(&self) -> &Chess {
+        &self.chess
+    }
+    pub fn board(&self) -> &shakmaty::Board {
+        self.chess.board()
+    }
+    pub fn turn(&self) -> Color {
+        self.chess.turn()
     }
-    pub fn outcome(&self) -> chess::BoardStatus {
+    pub fn checkers(&self) -> Bitboard {
+        self.checkers
+    }
+    /// The pieces, of either colour, that stand alone between the king of the side to move and an
+    /// enemy slider.
+    pub fn pinned(&self) -> Bitboard {
+        self.pinned
+    }
+    pub fn outcome(&self) -> BoardStatus {
         if self.drawn_by_repetition() {
-            chess::BoardStatus::Stalemate // close enough
+            BoardStatus::Stalemate // close enough
+        } else if self.chess.is_checkmate() {
+            BoardStatus::Checkmate
+        } else if self.chess.is_stalemate() {
+            BoardStatus::Stalemate
         } else {
-            self.board.status()
+            BoardStatus::Ongoing
         }
     }
-    pub fn formerly_occupied(&self) -> &[chess::BitBoard; NUM_OCCUPIED_KEPT] {
+    /// A Zobrist hash of the position, which leaves out the history.
+    pub fn position_hash(&self) -> u64 {
+        self.hash
+    }
+    pub fn is_chess960(&self) -> bool {
+        self.chess960
+    }
+    pub fn formerly_occupied(&self) -> &[Bitboard; NUM_OCCUPIED_KEPT] {
         &self.formerly_occupied
     }
     fn check_for_repetition(&mut self) {
-        let crnt_hash = self.board.get_hash();
+        let crnt_hash = self.hash;
         self.repetitions = max(self.repetitions,
             self.prev_state_hashes.iter()
                 .filter(|h| **h == crnt_hash)
@@ -124,24 +195,110 @@ impl State {
     pub fn queens_off(&self) -> bool {
         self.queens_off
     }
-    pub fn move_lists(&self) -> &[Vec<chess::ChessMove>; 2] {
+    pub fn move_lists(&self) -> &[Vec<Move>; 2] {
         &self.move_lists
     }
 }
 
+/// The square that `mov` takes the moving piece to. For castling, that's where the king ends up,
+/// on the g or the c file, even in Chess960 where the move is written with the rook's square.
+pub fn destination(mov: &Move) -> Square {
+    match *mov {
+        Move::Castle {king, rook} => {
+            let file = if rook.file() > king.file() { 6 } else { 2 };
+            Square::from_coords(file, king.rank()).unwrap()
+        }
+        ref mov => mov.to(),
+    }
+}
+
+/// The piece that `mov` takes from its destination, which leaves out en passant.
+pub fn captured(mov: &Move) -> Option<Role> {
+    match *mov {
+        Move::Normal {capture, ..} => capture,
+        _ => None,
+    }
+}
+
+const NUM_ZOBRIST_KEYS: usize = 12 * 64 + 64 + 8 + 1;
+const ZOBRIST_CASTLING: usize = 12 * 64;
+const ZOBRIST_EN_PASSANT: usize = ZOBRIST_CASTLING + 64;
+const ZOBRIST_BLACK: usize = ZOBRIST_EN_PASSANT + 8;
+static ZOBRIST: [u64; NUM_ZOBRIST_KEYS] = zobrist_keys(0x5A5A_5A5A_5A5A_5A5A);
+// An independent set of keys, so that positions whose hashes collide are still told apart.
+static VERIFICATION_KEYS: [u64; NUM_ZOBRIST_KEYS] = zobrist_keys(0xC3A5_C85C_97CB_3127);
+
+// SplitMix64.
+const fn zobrist_keys(seed: u64) -> [u64; NUM_ZOBRIST_KEYS] {
+    let mut keys = [0; NUM_ZOBRIST_KEYS];
+    let mut x = seed;
+    let mut i = 0;
+    while i < NUM_ZOBRIST_KEYS {
+        x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = x;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+fn pinned(chess: &Chess) -> Bitboard {
+    let board = chess.board();
+    let king = match board.king_of(chess.turn()) {
+        Some(king) => king,
+        None => return Bitboard(0),
+    };
+    let snipers = board.by_color(!chess.turn())
+        & ((attacks::rook_attacks(king, Bitboard(0)) & board.rooks_and_queens())
+         | (attacks::bishop_attacks(king, Bitboard(0)) & board.bishops_and_queens()));
+    let mut pinned = Bitboard(0);
+    for sniper in snipers {
+        if let Some(sq) = (attacks::between(king, sniper) & board.occupied()).single_square() {
+            pinned.add(sq);
+        }
+    }
+    pinned
+}
+
+/// A Zobrist hash of the pieces, the side to move, the castling rights and the en passant square.
+fn zobrist_hash(chess: &Chess, keys: &[u64; NUM_ZOBRIST_KEYS]) -> u64 {
+    let board = chess.board();
+    let mut hash = 0;
+    for sq in board.occupied() {
+        let piece = board.piece_at(sq).unwrap();
+        let color = if piece.color == Color::White { 0 } else { 1 };
+        hash ^= keys[(color * 6 + piece.role as usize) * 64 + usize::from(sq)];
+    }
+    for sq in chess.castling_rights() {
+        hash ^= keys[ZOBRIST_CASTLING + usize::from(sq)];
+    }
+    if let Some(sq) = chess.ep_square() {
+        hash ^= keys[ZOBRIST_EN_PASSANT + sq.file() as usize];
+    }
+    if chess.turn() == Color::Black {
+        hash ^= keys[ZOBRIST_BLACK];
+    }
+    hash
+}
+
 impl TranspositionHash for State {
     fn hash(&self) -> u64 {
         match self.repetitions {
-            0 => self.board().get_hash(),
-            1 => self.board().get_hash() ^ 0xDEADBEEF,
+            0 => self.hash,
+            1 => self.hash ^ 0xDEADBEEF,
             _ => 1,
         }
     }
+    fn verification_key(&self) -> u64 {
+        zobrist_hash(&self.chess, &VERIFICATION_KEYS)
+    }
 }
 
 impl Default for StateBuilder {
     fn default() -> Self {
-        shakmaty::Chess::default().into()
+        Chess::default().into()
     }
 }
 
@@ -151,75 +308,43 @@ impl Default for State {
     }
 }
 
-impl From<shakmaty::Chess> for StateBuilder {
-    fn from(chess: shakmaty::Chess) -> Self {
+impl From<Chess> for StateBuilder {
+    fn from(chess: Chess) -> Self {
         Self {
             initial_state: chess.clone(),
             crnt_state: chess,
             moves: Vec::new(),
+            chess960: false,
         }
     }
 }
 
-fn convert_square(sq: shakmaty::Square) -> chess::Square {
-    chess::Square::make_square(
-        chess::Rank::from_index(sq.rank() as usize),
-        chess::File::from_index(sq.file() as usize))
-}
-
-fn convert_role(role: shakmaty::Role) -> chess::Piece {
-    match role {
-        shakmaty::Role::Pawn => chess::Piece::Pawn,
-        shakmaty::Role::Knight => chess::Piece::Knight,
-        shakmaty::Role::Bishop => chess::Piece::Bishop,
-        shakmaty::Role::Rook => chess::Piece::Rook,
-        shakmaty::Role::Queen => chess::Piece::Queen,
-        shakmaty::Role::King => chess::Piece::King,
-    }
-}
-
-fn convert_move(mov: &shakmaty::Move) -> chess::ChessMove {
-    match mov {
-        &shakmaty::Move::Castle {ref king, ref rook} => {
-            let from = convert_square(mov.from().unwrap());
-            let to = if king.file() < rook.file() {
-                from.right().unwrap().right().unwrap()
-            } else {
-                from.left().unwrap().left().unwrap()
-            };
-            chess::ChessMove::new(from, to, None)
-        }
-        mov => chess::ChessMove::new(
-            convert_square(mov.from().unwrap()),
-            convert_square(mov.to()),
-            mov.promotion().map(|x| convert_role(x)))
-    }
-}
-
-impl From<chess::Board> for State {
-    fn from(board: chess::Board) -> Self {
+impl From<Chess> for State {
+    fn from(chess: Chess) -> Self {
         Self {
-            board,
+            hash: zobrist_hash(&chess, &ZOBRIST),
+            checkers: chess.checkers(),
+            pinned: pinned(&chess),
             prev_move: None,
             prev_capture: None,
             prev_state_hashes: SmallVec::new(),
             repetitions: 0,
-            formerly_occupied: [board.combined(); NUM_OCCUPIED_KEPT],
+            formerly_occupied: [chess.board().occupied(); NUM_OCCUPIED_KEPT],
             frozen: false,
             queens_off: false,
             move_lists: [Vec::new(), Vec::new()],
+            chess960: false,
+            chess,
         }
     }
 }
 
 impl From<StateBuilder> for State {
     fn from(sb: StateBuilder) -> Self {
-        let fen = shakmaty::fen::fen(&sb.initial_state, &shakmaty::fen::FenOpts::default());
-        let board = chess::Board::from_fen(fen).unwrap();
-        let mut state = State::from(board);
+        let mut state = State::from(sb.initial_state);
+        state.chess960 = sb.chess960;
         for mov in sb.moves {
-            let mov = convert_move(&mov);
-            assert!(state.board().legal(mov),
+            assert!(state.available_moves().contains(&mov),
                 "{} is illegal on the following board:\n{}",
                 mov, state.board());
             state.make_move(&mov);
@@ -228,85 +353,42 @@ impl From<StateBuilder> for State {
     }
 }
 
-pub struct MoveList {
-    arr: [chess::ChessMove; 256],
-    len: usize,
-}
-
-impl MoveList {
-    pub fn as_slice(&self) -> &[chess::ChessMove] {
-        &self.arr[..self.len]
-    }
-    pub fn len(&self) -> usize {
-        self.len
-    }
-}
-
-pub struct MoveListIterator {
-    arr: [chess::ChessMove; 256],
-    len: usize,
-    pos: usize,
-}
-
-impl Iterator for MoveListIterator {
-    type Item = chess::ChessMove;
-    fn next(&mut self) -> Option<chess::ChessMove> {
-        self.pos += 1;
-        if self.pos <= self.len {
-            unsafe { Some(*self.arr.get_unchecked(self.pos - 1)) }
-        } else {
-            None
-        }
-    }
-}
-
-impl IntoIterator for MoveList {
-    type Item = chess::ChessMove;
-    type IntoIter = MoveListIterator;
-    fn into_iter(self) -> MoveListIterator {
-        MoveListIterator {
-            pos: 0,
-            arr: self.arr,
-            len: self.len,
-        }
-    }
-}
-
 impl GameState for State {
     type Move = Move;
     type Player = Player;
     type MoveList = MoveList;
 
     fn current_player(&self) -> Player {
-        self.board.side_to_move()
+        self.turn()
     }
     fn available_moves(&self) -> MoveList {
-        let mut arr = unsafe { std::mem::uninitialized() };
-        let len = if self.drawn_by_repetition() {
-            0
+        if self.drawn_by_repetition() {
+            Vec::new()
         } else {
-            self.board.enumerate_moves(&mut arr)
-        };
-        MoveList {arr, len}
+            self.chess.legals().to_vec()
+        }
     }
-    fn make_move(&mut self, mov: &chess::ChessMove) {
-        if (self.board.pieces(chess::Piece::Pawn) & chess::BitBoard::from_square(mov.get_source())).0 != 0 {
+    fn make_move(&mut self, mov: &Move) {
+        if mov.role() == Role::Pawn {
             self.prev_state_hashes.clear();
         } else if !self.frozen {
-            self.prev_state_hashes.push(self.board.get_hash());
+            self.prev_state_hashes.push(self.hash);
         }
-        self.prev_capture = self.board.piece_on(mov.get_dest());
-        self.prev_move = Some(*mov);
+        self.prev_capture = captured(mov);
+        self.prev_move = Some(mov.clone());
         for i in (0..(NUM_OCCUPIED_KEPT - 1)).rev() {
             self.formerly_occupied[i+1] = self.formerly_occupied[i];
         }
-        self.formerly_occupied[0] = self.board.combined();
-        self.board = self.board.make_move(*mov);
+        self.formerly_occupied[0] = self.board().occupied();
+        self.chess.play_unchecked(mov);
+        self.hash = zobrist_hash(&self.chess, &ZOBRIST);
+        self.checkers = self.chess.checkers();
+        self.pinned = pinned(&self.chess);
         self.check_for_repetition();
-        self.queens_off = self.queens_off || self.board.pieces(chess::Piece::Queen).0 == 0;
+        self.queens_off = self.queens_off || self.board().queens().is_empty();
         self.move_lists.swap(0, 1);
-        if self.board.checkers().0 == 0 {
-            self.move_lists[0] = self.available_moves().as_slice().to_vec();
+        if self.checkers.is_empty() {
+            self.move_lists[0] = self.available_moves();
         }
     }
 }
@@ -329,6 +411,79 @@ mod tests {
             state.make_move(m);
         }
         let state = State::from(state);
-        assert!(state.outcome() == chess::BoardStatus::Stalemate);
+        assert!(state.outcome() == BoardStatus::Stalemate);
+    }
+
+    #[test]
+    fn verification_key_depends_on_more_than_occupancy() {
+        let keys: Vec<u64> = [
+            "4k3/8/8/8/8/8/8/R3K3 w Q - 0 1",
+            "4k3/8/8/8/8/8/8/R3K3 b Q - 0 1",
+            "4k3/8/8/8/8/8/8/R3K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/Q3K3 w - - 0 1",
+            "4K3/8/8/8/8/8/8/r3k3 w - - 0 1",
+        ].iter().map(|fen| State::from_fen(fen).unwrap().verification_key()).collect();
+        for (i, a) in keys.iter().enumerate() {
+            assert!(keys[i + 1..].iter().all(|b| a != b));
+        }
+    }
+
+    fn perft(state: &State, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        state.available_moves().into_iter()
+            .map(|mov| {
+                let mut child = state.clone();
+                child.make_move(&mov);
+                perft(&child, depth - 1)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn standard_perft() {
+        // The starting position, "Kiwipete" and two more from the Chess Programming Wiki.
+        let positions = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 8902),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 97862),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 2812),
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 9467),
+        ];
+        for &(fen, expected) in &positions {
+            assert_eq!(perft(&State::from_fen(fen).unwrap(), 3), expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn chess960_perft() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+            "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+            "1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9",
+            "rbbqn1kr/pp2p1pp/6n1/2pp1p2/2P4P/P7/BP1PPPP1/R1BQNNKR w HAha - 0 9",
+        ];
+        for fen in &fens {
+            let builder = StateBuilder::from_fen(fen).unwrap().chess960(true);
+            let expected = shakmaty::perft(builder.chess(), 3);
+            assert_eq!(perft(&builder.into(), 3), expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn chess960_castling() {
+        // Castling is written as the king taking its own rook.
+        let fen = "rk5r/8/8/8/8/8/8/RK5R w HAha - 0 1";
+        let state = State::chess960_from_moves(fen, &["b1h1", "b8a8"]).unwrap();
+        let board = state.board();
+        for &(sq, role) in &[("g1", Role::King), ("f1", Role::Rook), ("c8", Role::King), ("d8", Role::Rook)] {
+            assert_eq!(board.role_at(sq.parse().unwrap()), Some(role), "{}", sq);
+        }
+        // A rook that has moved can't castle, even once it's back.
+        let moves = ["a1a2", "a8a7", "a2a1", "a7a8"];
+        assert!(State::chess960_from_moves(fen, &[&moves[..], &["b1a1"]].concat()).is_none());
+        assert!(State::chess960_from_moves(fen, &[&moves[..], &["b1h1"]].concat()).is_some());
     }
 }
@@ -0,0 +1,174 @@
+//! Playing below full strength, for `UCI_LimitStrength` and `UCI_Elo`.
+//!
+//! A target Elo is mapped to a number of playouts and a temperature. The move is sampled from the
+//! root visit counts raised to the power 1 / temperature, so that a temperature of 0 plays the most
+//! visited move. At the lowest levels there is no search at all, and the move is sampled from the
+//! policy's priors instead; since the policy was trained on games of strong humans, its mistakes
+//! look like theirs.
+//!
+//! The levels were calibrated with `examples/strength_match.rs`, by playing each of them against
+//! Sashimi at a fixed number of playouts per move; the results are next to `LEVELS`. Matches only
+//! measure differences, so the scale is anchored by defining Sashimi at `REFERENCE_PLAYOUTS` to be
+//! `REFERENCE_ELO`.
+
+extern crate rand;
+use self::rand::Rng;
+
+use mcts::GameState;
+use mcts::tree_policy::PolicyRng;
+use config::Config;
+use policy_features::evaluate_moves;
+use search::Search;
+use state::{State, Move};
+use std::cmp::max;
+
+pub const REFERENCE_PLAYOUTS: u32 = 1000;
+pub const REFERENCE_ELO: u32 = 2000;
+
+/// (Elo, playouts, temperature), in increasing order of Elo. 0 playouts means that the move is
+/// sampled from the priors. Only the levels without search use a temperature: the ones that search
+/// play the most visited move, so that more playouts never make a level weaker.
+///
+/// Each level played 200 games against Sashimi at 16, 100 and 1000 playouts per move, with these
+/// wins, draws and losses:
+///
+/// ```text
+/// playouts  temperature  against 16    against 100   against 1000  Elo
+///        0          4.0  +0 =38 -162   +0 =10 -190   +0 =0 -200     990 ± 70
+///        0          2.0  +0 =41 -159   +0 =18 -182   +0 =1 -199    1030 ± 65
+///        0          1.0  +3 =86 -111   +0 =31 -169   +0 =3 -197    1180 ± 47
+///        0          0.5  +8 =114 -78   +0 =59 -141   +0 =6 -194    1280 ± 40
+///        0          0.0  +14 =147 -39  +2 =92 -106   +2 =7 -191    1370 ± 36
+///       32          0.0  +71 =116 -13  +15 =92 -93   +2 =5 -193    1470 ± 34
+///       64          0.0  +99 =89 -12   +32 =107 -61  +4 =18 -178   1550 ± 33
+///      256          0.0  +144 =52 -4   +99 =88 -13   +12 =50 -138  1740 ± 35
+///     1000          0.0  +190 =8 -2    +162 =31 -7   +77 =47 -76   2000
+///     4000          0.0  +197 =2 -1    +194 =5 -1    +169 =20 -11  2350 ± 70
+/// ```
+///
+/// The Elo ratings are the maximum-likelihood fit of the Elo model to all the games, with Sashimi
+/// at `REFERENCE_PLAYOUTS` fixed at `REFERENCE_ELO`, rounded to 10; the margins are 95% intervals.
+/// Sashimi at 16 and 100 playouts came out at 1400 and 1600. The level at `REFERENCE_PLAYOUTS`
+/// plays like the reference, so it is given `REFERENCE_ELO` rather than its fitted 1990.
+const LEVELS: [(u32, u32, f32); 10] = [
+    (990, 0, 4.0),
+    (1030, 0, 2.0),
+    (1180, 0, 1.0),
+    (1280, 0, 0.5),
+    (1370, 0, 0.0),
+    (1470, 32, 0.0),
+    (1550, 64, 0.0),
+    (1740, 256, 0.0),
+    (2000, 1000, 0.0),
+    (2350, 4000, 0.0),
+];
+
+pub const MIN_ELO: u32 = LEVELS[0].0;
+pub const MAX_ELO: u32 = LEVELS[LEVELS.len() - 1].0;
+
+/// How the engine plays at a given Elo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Strength {
+    /// 0 means no search.
+    pub playouts: u32,
+    pub temperature: f32,
+}
+
+impl Strength {
+    /// Interpolates between the levels, geometrically in the number of playouts.
+    /// Between the strongest level without search and the weakest one with it, there is nothing
+    /// in between, so the nearer of the two is used.
+    pub fn for_elo(elo: u32) -> Self {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        let i = LEVELS.iter().position(|level| level.0 >= elo).unwrap();
+        let (hi_elo, hi_playouts, hi_temperature) = LEVELS[i];
+        if hi_elo == elo {
+            return Strength {playouts: hi_playouts, temperature: hi_temperature};
+        }
+        let (lo_elo, lo_playouts, lo_temperature) = LEVELS[i - 1];
+        let t = (elo - lo_elo) as f32 / (hi_elo - lo_elo) as f32;
+        if lo_playouts == 0 && hi_playouts != 0 {
+            let (playouts, temperature) = if t < 0.5 {
+                (lo_playouts, lo_temperature)
+            } else {
+                (hi_playouts, hi_temperature)
+            };
+            return Strength {playouts, temperature};
+        }
+        let playouts = if lo_playouts == 0 {
+            0
+        } else {
+            let ln = (lo_playouts as f32).ln() * (1.0 - t) + (hi_playouts as f32).ln() * t;
+            ln.exp().round() as u32
+        };
+        Strength {
+            playouts,
+            temperature: lo_temperature * (1.0 - t) + hi_temperature * t,
+        }
+    }
+}
+
+/// The move to play in `state` at `elo`, or `None` if the game is over. The random choices depend
+/// only on `config.seed` and the position.
+pub fn choose_move(state: &State, config: &Config, elo: u32) -> Option<Move> {
+    let strength = Strength::for_elo(elo);
+    let (moves, weights): (Vec<Move>, Vec<f32>) = if strength.playouts == 0 {
+        let moves = state.available_moves();
+        let priors = evaluate_moves(state, moves.as_slice());
+        (moves, priors)
+    } else {
+        let mut manager = Search::create_manager_from_config(state.clone(), config);
+        manager.playout_n_parallel(strength.playouts, max(1, config.num_threads));
+        let root = manager.tree().root_node();
+        root.moves().map(|mov| (mov.get_move().clone(), mov.visits() as f32)).unzip()
+    };
+    if moves.is_empty() {
+        return None;
+    }
+    let mut rng = PolicyRng::with_seed(config.seed ^ state.position_hash()).rng;
+    Some(moves[sample(&weights, strength.temperature, &mut rng)].clone())
+}
+
+/// An index sampled with probability proportional to `weights[i]` to the power 1 / `temperature`.
+/// A temperature of 0 gives the largest weight.
+fn sample<R: Rng>(weights: &[f32], temperature: f32, rng: &mut R) -> usize {
+    let max_weight = weights.iter().cloned().fold(0.0, f32::max);
+    let best = weights.iter().position(|&w| w == max_weight).unwrap();
+    if temperature <= 0.0 || max_weight <= 0.0 {
+        return best;
+    }
+    // Dividing by the largest weight first keeps small temperatures from overflowing.
+    let scaled: Vec<f64> = weights.iter()
+        .map(|&w| (w as f64 / max_weight as f64).powf(1.0 / temperature as f64))
+        .collect();
+    let mut x = rng.gen::<f64>() * scaled.iter().sum::<f64>();
+    for (i, w) in scaled.iter().enumerate() {
+        if x < *w {
+            return i;
+        }
+        x -= w;
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elo_levels() {
+        assert_eq!(Strength::for_elo(0), Strength {playouts: 0, temperature: 4.0});
+        assert_eq!(Strength::for_elo(MAX_ELO + 100), Strength::for_elo(MAX_ELO));
+        assert_eq!(Strength::for_elo(REFERENCE_ELO).playouts, REFERENCE_PLAYOUTS);
+        let mut previous = Strength::for_elo(MIN_ELO);
+        for elo in MIN_ELO..MAX_ELO + 1 {
+            let strength = Strength::for_elo(elo);
+            assert!(strength.playouts >= previous.playouts);
+            assert!(strength.temperature <= previous.temperature);
+            previous = strength;
+        }
+        let mut rng = PolicyRng::with_seed(1).rng;
+        assert_eq!(sample(&[1.0, 3.0, 2.0], 0.0, &mut rng), 1);
+        assert_eq!(sample(&[0.0, 0.0, 2.0], 1.0, &mut rng), 2);
+    }
+}
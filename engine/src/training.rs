@@ -3,45 +3,311 @@ extern crate memmap;
 extern crate madvise;
 extern crate rand;
 
-use self::rand::{Rng, XorShiftRng, SeedableRng};
+use self::rand::{Rng, XorShiftRng};
 use self::pgn_reader::{Reader, Visitor, San, Outcome, Skip};
 use self::memmap::Mmap;
 use self::madvise::{AccessPattern, AdviseMemory};
 
 use state::StateBuilder;
 use shakmaty;
-use chess;
 use mcts::GameState;
+use mcts::tree_policy::PolicyRng;
 use features::{GameResult, featurize, NUM_DENSE_FEATURES, NUM_FEATURES, name_feature};
 use policy_features;
 use policy_features::NUM_POLICY_FEATURES;
 
+use std::cmp::{min, max};
+use std::collections::BTreeMap;
+use std::fs;
 use std::fs::File;
 use std::io::{Write, BufWriter};
+use std::path::{Path, PathBuf};
 use std::str;
-use std;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
 
-const NUM_ROWS: usize = std::usize::MAX;
-const MIN_ELO: i32 = 1700;
-const MIN_ELO_POLICY: i32 = 2200;
-const NUM_SAMPLES: usize = 1;
+/// The games are handed to the threads in chunks of about this many bytes of PGN. The chunks don't
+/// depend on the number of threads, and neither does the output.
+const CHUNK_SIZE: usize = 1 << 18;
+/// The threads read at most this many chunks per thread ahead of the first one that hasn't been
+/// written, so that's how much output is kept in memory.
+const CHUNKS_PER_THREAD: usize = 2;
 
-struct ValueDataGenerator {
-    out_file: Option<BufWriter<File>>,
+/// The settings of the training data generator. The binary fills this in from the command line.
+#[derive(Clone, Debug)]
+pub struct TrainingConfig {
+    /// The directory that the data and the side files are written to.
+    pub output_dir: String,
+    /// Games with a player rated below this are left out of the value data.
+    pub min_elo: i32,
+    /// Games with a player rated below this are left out of the policy data.
+    pub min_elo_policy: i32,
+    /// The average number of positions sampled from each game for the value data.
+    pub samples_per_game: usize,
+    /// Features found in fewer sampled positions than this are left out of the value data.
+    pub min_feature_frequency: u64,
+    /// The number of plies at the start of each game that are never sampled for the value data.
+    pub skip_plies: usize,
+    /// If not empty, only games with one of these `TimeControl` headers, like `180+2`, are used.
+    pub time_controls: Vec<String>,
+    /// If not empty, only games with one of these `Termination` headers, like `Normal`, are used.
+    pub terminations: Vec<String>,
+    /// If `Some(true)`, only rated games are used, and if `Some(false)`, only casual ones. Lichess
+    /// says which in the `Event` header, like `Rated Blitz game`.
+    pub rated: Option<bool>,
+    /// The seed for sampling the positions of the value data. Each chunk of the PGN samples with
+    /// its own generator, seeded with this plus the chunk's index.
+    pub seed: u64,
+    pub num_threads: usize,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        extern crate num_cpus;
+        TrainingConfig {
+            output_dir: ".".into(),
+            min_elo: 1700,
+            min_elo_policy: 2200,
+            samples_per_game: 1,
+            min_feature_frequency: 500,
+            skip_plies: 2,
+            time_controls: Vec::new(),
+            terminations: Vec::new(),
+            rated: None,
+            seed: 0,
+            num_threads: num_cpus::get(),
+        }
+    }
+}
+
+impl TrainingConfig {
+    fn path(&self, file_name: &str) -> PathBuf {
+        Path::new(&self.output_dir).join(file_name)
+    }
+
+    /// Whether a game with these headers is used, if its players must be rated at least `min_elo`.
+    fn accepts(&self, headers: &GameHeaders, min_elo: i32) -> bool {
+        let one_of = |allowed: &[String], value: &Option<String>| {
+            allowed.is_empty() || value.as_ref().is_some_and(|x| allowed.contains(x))
+        };
+        let rated = headers.event.as_ref().is_some_and(|x| x.to_lowercase().starts_with("rated"));
+        headers.lowest_elo.is_none_or(|elo| elo >= min_elo)
+            && one_of(&self.time_controls, &headers.time_control)
+            && one_of(&self.terminations, &headers.termination)
+            && self.rated.is_none_or(|x| x == rated)
+    }
+}
+
+/// The headers that decide whether a game is used.
+#[derive(Default)]
+struct GameHeaders {
+    lowest_elo: Option<i32>,
+    time_control: Option<String>,
+    termination: Option<String>,
+    event: Option<String>,
+}
+
+impl GameHeaders {
+    fn header(&mut self, key: &[u8], value: &[u8]) {
+        let value = str::from_utf8(value).unwrap_or("");
+        match key {
+            b"WhiteElo" | b"BlackElo" => {
+                // An unknown rating, like `?`, counts as 0.
+                let elo = value.parse().unwrap_or(0);
+                self.lowest_elo = Some(self.lowest_elo.map_or(elo, |x| min(x, elo)));
+            },
+            b"TimeControl" => self.time_control = Some(value.into()),
+            b"Termination" => self.termination = Some(value.into()),
+            b"Event" => self.event = Some(value.into()),
+            _ => (),
+        }
+    }
+}
+
+/// The headers that say where a game starts: `FEN`, and `Variant` for Chess960 games.
+#[derive(Default)]
+pub struct StartingPosition {
+    fen: Option<String>,
+    chess960: bool,
+}
+
+impl StartingPosition {
+    pub fn header(&mut self, key: &[u8], value: &[u8]) {
+        let value = str::from_utf8(value).unwrap_or("");
+        if key == b"FEN" {
+            self.fen = Some(value.into());
+        } else if key == b"Variant" {
+            let variant = value.to_lowercase();
+            self.chess960 = variant.starts_with("chess960") || variant.starts_with("fischerandom");
+        }
+    }
+
+    /// `None` if the FEN is invalid.
+    pub fn state(&self) -> Option<StateBuilder> {
+        let state = match self.fen {
+            Some(ref fen) => StateBuilder::from_fen(fen)?,
+            None => StateBuilder::default(),
+        };
+        Some(state.chess960(self.chess960))
+    }
+}
+
+/// Makes the training data for one chunk of the PGN, for `for_each_chunk`.
+trait ChunkGenerator: for<'pgn> Visitor<'pgn, Result = ()> + Send {
+    /// The number of games read.
+    fn games(&self) -> usize;
+}
+
+/// Whether `game`, as passed to `Visitor::end_game`, is a game rather than the space at the end.
+fn is_game(game: &[u8]) -> bool {
+    !game.iter().all(u8::is_ascii_whitespace)
+}
+
+/// Splits `pgn` into chunks of whole games, of about `size` bytes each.
+fn split_games(pgn: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = pgn;
+    while let Some(i) = next_game_start(rest, size) {
+        let (chunk, tail) = rest.split_at(i);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+/// The position of the first game that starts at `from` or later: a `[` at the start of a line,
+/// after a blank one.
+fn next_game_start(pgn: &[u8], from: usize) -> Option<usize> {
+    let strip_newline = |s: &[u8]| -> Option<usize> {
+        let s = s.strip_suffix(b"\n")?;
+        Some(s.strip_suffix(b"\r").unwrap_or(s).len())
+    };
+    let mut i = max(from, 1);
+    while i < pgn.len() {
+        i += pgn[i..].iter().position(|&c| c == b'[')?;
+        if strip_newline(&pgn[..i]).and_then(|j| strip_newline(&pgn[..j])).is_some() {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Runs a generator made by `new_generator(chunk index)` over every chunk of the games in
+/// `in_path`, on `config.num_threads` threads, and passes the generators to `finish` in the order
+/// of their chunks.
+fn for_each_chunk<G, N, F>(in_path: &str, config: &TrainingConfig, new_generator: N, finish: F)
+    where G: ChunkGenerator, N: Fn(usize) -> G + Sync, F: FnMut(G)
+{
+    let file = File::open(in_path).expect("fopen");
+    let pgn = unsafe { Mmap::map(&file).expect("mmap") };
+    pgn.advise_memory_access(AccessPattern::Sequential).expect("madvise");
+    run_chunks(&split_games(&pgn[..], CHUNK_SIZE), config, new_generator, finish);
+}
+
+/// `for_each_chunk` over `chunks` of PGN. Each thread takes the next chunk as soon as it's done
+/// with the last one, and each generator is passed to `finish` as soon as it and all the ones
+/// before it are done.
+fn run_chunks<G, N, F>(chunks: &[&[u8]], config: &TrainingConfig, new_generator: N, mut finish: F)
+    where G: ChunkGenerator, N: Fn(usize) -> G + Sync, F: FnMut(G)
+{
+    let num_threads = max(1, config.num_threads);
+    let read_ahead = num_threads * CHUNKS_PER_THREAD;
+    let started = Instant::now();
+    let mut games = 0;
+    let next_chunk = AtomicUsize::new(0);
+    // The number of chunks passed to `finish`, which the threads wait on to stay within `read_ahead`.
+    let chunks_done = (Mutex::new(0), Condvar::new());
+    let (sender, receiver) = channel();
+    thread::scope(|scope| {
+        for _ in 0..min(num_threads, chunks.len()) {
+            let (next_chunk, chunks_done, new_generator, sender) =
+                (&next_chunk, &chunks_done, &new_generator, sender.clone());
+            scope.spawn(move || loop {
+                let i = next_chunk.fetch_add(1, Ordering::Relaxed);
+                if i >= chunks.len() {
+                    break;
+                }
+                let (ref done, ref read_on) = *chunks_done;
+                drop(read_on.wait_while(done.lock().unwrap(), |done| i >= *done + read_ahead).unwrap());
+                // Boxed, since a generator can be too big to move around on the stack.
+                let mut generator = Box::new(new_generator(i));
+                Reader::new(&mut *generator, chunks[i]).read_all();
+                sender.send((i, generator)).unwrap();
+            });
+        }
+        drop(sender);
+        let mut finished = BTreeMap::new();
+        let mut written = 0;
+        for (i, generator) in receiver {
+            finished.insert(i, generator);
+            while let Some(generator) = finished.remove(&written) {
+                games += generator.games();
+                finish(*generator);
+                written += 1;
+                *chunks_done.0.lock().unwrap() = written;
+                chunks_done.1.notify_all();
+                if written % read_ahead == 0 || written == chunks.len() {
+                    info!("{:.1}%, {} games, {:.0} games/s",
+                          100. * written as f64 / chunks.len() as f64, games,
+                          games as f64 / started.elapsed().as_secs_f64());
+                }
+            }
+        }
+    });
+}
+
+struct ValueDataGenerator<'a> {
+    config: &'a TrainingConfig,
+    /// `None` in the pass that only counts the features.
+    whitelist: Option<&'a [bool; NUM_FEATURES]>,
+    out: Vec<u8>,
     state: StateBuilder,
+    headers: StartingPosition,
+    game_headers: GameHeaders,
     skip: bool,
-    rows_written: usize,
+    games: usize,
     rng: XorShiftRng,
     freq: [u64; NUM_FEATURES],
-    whitelist: [bool; NUM_FEATURES],
 }
 
-impl<'pgn> Visitor<'pgn> for ValueDataGenerator {
+impl<'a> ValueDataGenerator<'a> {
+    fn new(config: &'a TrainingConfig, chunk: usize, whitelist: Option<&'a [bool; NUM_FEATURES]>) -> Self {
+        ValueDataGenerator {
+            config,
+            whitelist,
+            out: Vec::new(),
+            state: StateBuilder::default(),
+            headers: StartingPosition::default(),
+            game_headers: GameHeaders::default(),
+            skip: true,
+            games: 0,
+            rng: PolicyRng::with_seed(config.seed.wrapping_add(chunk as u64)).rng,
+            freq: [0; NUM_FEATURES],
+        }
+    }
+}
+
+impl<'a> ChunkGenerator for ValueDataGenerator<'a> {
+    fn games(&self) -> usize {
+        self.games
+    }
+}
+
+impl<'a, 'pgn> Visitor<'pgn> for ValueDataGenerator<'a> {
     type Result = ();
 
     fn begin_game(&mut self) {
         self.state = StateBuilder::default();
-        self.skip = self.rows_written >= NUM_ROWS;
+        self.headers = StartingPosition::default();
+        self.game_headers = GameHeaders::default();
+        self.skip = false;
     }
 
     fn san(&mut self, san: San) {
@@ -51,16 +317,19 @@ impl<'pgn> Visitor<'pgn> for ValueDataGenerator {
     }
 
     fn end_headers(&mut self) -> Skip {
+        match self.headers.state() {
+            Some(state) => self.state = state,
+            None => self.skip = true,
+        }
+        if !self.config.accepts(&self.game_headers, self.config.min_elo) {
+            self.skip = true;
+        }
         Skip(self.skip)
     }
 
     fn header(&mut self, key: &[u8], value: &[u8]) {
-        if key == b"WhiteElo" || key == b"BlackElo" {
-            let elo: i32 = str::from_utf8(value).unwrap().parse().unwrap();
-            if elo < MIN_ELO {
-                self.skip = true;
-            }
-        }
+        self.headers.header(key, value);
+        self.game_headers.header(key, value);
     }
 
     fn outcome(&mut self, outcome: Outcome) {
@@ -75,20 +344,18 @@ impl<'pgn> Visitor<'pgn> for ValueDataGenerator {
             },
         };
         let (mut state, moves) = self.state.extract();
-        let freq = NUM_SAMPLES as f64 / moves.len() as f64;
+        let freq = self.config.samples_per_game as f64 / moves.len() as f64;
         for (i, m) in moves.into_iter().enumerate() {
-            if i >= 2 && self.rng.gen_range(0., 1.) < freq {
+            if i >= self.config.skip_plies && self.rng.gen_range(0., 1.) < freq {
                 let moves = state.available_moves();
                 let mut f = featurize(&state, moves.as_slice());
-                self.rows_written += 1;
-                if let Some(out_file) = self.out_file.as_mut() {
-                    let whitelist = &self.whitelist;
-                    let crnt_result = if state.board().side_to_move() == chess::Color::White {
+                if let Some(whitelist) = self.whitelist {
+                    let crnt_result = if state.turn() == shakmaty::Color::White {
                         game_result
                     } else {
                         game_result.flip()
                     };
-                    f.write_libsvm(out_file, crnt_result as usize, |x| whitelist[x]);
+                    f.write_libsvm(&mut self.out, crnt_result as usize, |x| whitelist[x]);
                 }
                 f.write_frequency(&mut self.freq);
             }
@@ -100,104 +367,130 @@ impl<'pgn> Visitor<'pgn> for ValueDataGenerator {
         Skip(true) // stay in the mainline
     }
 
-    fn end_game(&mut self, _game: &'pgn [u8]) -> Self::Result {}
+    fn end_game(&mut self, game: &'pgn [u8]) -> Self::Result {
+        if is_game(game) {
+            self.games += 1;
+        }
+    }
 }
 
-fn write_feature_names() {
-    let mut out_file = File::create("train_data_features.txt").expect("create");
+fn write_feature_names(config: &TrainingConfig) {
+    let mut out_file = File::create(config.path("train_data_features.txt")).expect("create");
     for i in 0..NUM_DENSE_FEATURES {
-        write!(out_file, "{}\n", name_feature(i)).unwrap();
+        writeln!(out_file, "{}", name_feature(i)).unwrap();
     }
 }
 
-fn write_policy_feature_names() {
-    let mut out_file = File::create("policy_train_data_features.txt").expect("create");
+fn write_policy_feature_names(config: &TrainingConfig) {
+    let mut out_file = File::create(config.path("policy_train_data_features.txt")).expect("create");
     for i in 0..NUM_POLICY_FEATURES {
-        write!(out_file, "{}\n", policy_features::name_feature(i)).unwrap();
+        writeln!(out_file, "{}", policy_features::name_feature(i)).unwrap();
     }
 }
 
-fn run_value_gen(in_path: &str, out_file: Option<BufWriter<File>>, whitelist: [bool; NUM_FEATURES]) -> ValueDataGenerator {
-    let mut generator = ValueDataGenerator {
-        freq: [0; NUM_FEATURES],
-        whitelist,
-        out_file,
-        state: StateBuilder::default(),
-        skip: true,
-        rows_written: 0,
-        rng: SeedableRng::from_seed([1, 2, 3, 4]),
-    };
-
-    let file = File::open(in_path).expect("fopen");
-    let pgn = unsafe { Mmap::map(&file).expect("mmap") };
-    pgn.advise_memory_access(AccessPattern::Sequential).expect("madvise");
-    Reader::new(&mut generator, &pgn[..]).read_all();
-
-    generator
+/// Samples positions from the games in `in_path`, and returns how often each feature is found in
+/// them. If `output` is given, the positions are also written to it, with the whitelisted features.
+/// Both passes seed the chunks alike, so that they sample the same positions.
+fn run_value_gen(config: &TrainingConfig, in_path: &str,
+                 mut output: Option<(&mut BufWriter<File>, &[bool; NUM_FEATURES])>) -> [u64; NUM_FEATURES] {
+    let whitelist = output.as_ref().map(|x| x.1);
+    let mut freq = [0; NUM_FEATURES];
+    for_each_chunk(in_path, config, |chunk| ValueDataGenerator::new(config, chunk, whitelist), |generator| {
+        for (total, x) in freq.iter_mut().zip(generator.freq.iter()) {
+            *total += x;
+        }
+        if let Some((ref mut out_file, _)) = output {
+            out_file.write_all(&generator.out).expect("write");
+        }
+    });
+    freq
 }
 
-pub fn train_value(in_path: &str, out_path: &str) {
-    let freq = run_value_gen(in_path, None, [true; NUM_FEATURES]).freq;
-    let out_file = BufWriter::new(File::create(out_path).expect("create"));
+pub fn train_value(in_path: &str, out_path: &str, config: &TrainingConfig) {
+    let freq = run_value_gen(config, in_path, None);
+    let mut out_file = BufWriter::new(File::create(config.path(out_path)).expect("create"));
     let mut whitelist = [false; NUM_FEATURES];
     for i in 0..NUM_FEATURES {
-        whitelist[i] = freq[i] >= 500;
+        whitelist[i] = freq[i] >= config.min_feature_frequency;
     }
-    run_value_gen(in_path, Some(out_file), whitelist);
-    let mut freq_file = File::create("frequencies.debug.txt").expect("create");
+    run_value_gen(config, in_path, Some((&mut out_file, &whitelist)));
+    let mut freq_file = File::create(config.path("frequencies.debug.txt")).expect("create");
     let mut indices =
         (0..NUM_FEATURES)
         .map(|x| (freq[x], x))
         .collect::<Vec<_>>();
     indices.sort();
     for &(freq, feature) in &indices {
-        write!(freq_file, "{} {}\n", feature, freq).unwrap();
+        writeln!(freq_file, "{} {}", feature, freq).unwrap();
     }
-    let mut whitelist_file = File::create("feature_whitelist.txt").expect("create");
-    for i in 0..NUM_FEATURES {
-        write!(whitelist_file, "{}\n", whitelist[i]).unwrap();
+    let mut whitelist_file = File::create(config.path("feature_whitelist.txt")).expect("create");
+    for allowed in whitelist.iter() {
+        writeln!(whitelist_file, "{}", allowed).unwrap();
     }
 }
 
-pub fn train(in_path: &str, out_path: &str, policy: bool) {
-    write_feature_names();
-    write_policy_feature_names();
+/// Writes the training data from the games in `in_path`, and the side files, into `config.output_dir`.
+pub fn train(in_path: &str, out_path: &str, policy: bool, config: &TrainingConfig) {
+    fs::create_dir_all(&config.output_dir).expect("mkdir");
+    write_feature_names(config);
+    write_policy_feature_names(config);
     if policy {
-        train_policy(in_path, out_path);
+        train_policy(in_path, out_path, config);
     } else {
-        train_value(in_path, out_path);
+        train_value(in_path, out_path, config);
     }
 }
 
-pub fn train_policy(in_path: &str, out_path: &str) {
-    let out_path = format!("policy_{}", out_path);
+pub fn train_policy(in_path: &str, out_path: &str, config: &TrainingConfig) {
+    let out_path = config.path(&format!("policy_{}", out_path));
 
-    let out_file = BufWriter::new(File::create(out_path).expect("create"));
-    let key_file = BufWriter::new(File::create("policy_key.txt").expect("create"));
-    let mut generator = PolicyDataGenerator {
-        out_file,
-        key_file,
-        state: StateBuilder::default(),
-        skip: true,
-    };
-    let file = File::open(in_path).expect("fopen");
-    let pgn = unsafe { Mmap::map(&file).expect("mmap") };
-    pgn.advise_memory_access(AccessPattern::Sequential).expect("madvise");
-    Reader::new(&mut generator, &pgn[..]).read_all();
+    let mut out_file = BufWriter::new(File::create(out_path).expect("create"));
+    let mut key_file = BufWriter::new(File::create(config.path("policy_key.txt")).expect("create"));
+    for_each_chunk(in_path, config, |_| PolicyDataGenerator::new(config), |generator| {
+        out_file.write_all(&generator.out).expect("write");
+        key_file.write_all(&generator.keys).expect("write");
+    });
 }
 
-struct PolicyDataGenerator {
-    out_file: BufWriter<File>,
-    key_file: BufWriter<File>,
+struct PolicyDataGenerator<'a> {
+    config: &'a TrainingConfig,
+    out: Vec<u8>,
+    keys: Vec<u8>,
     state: StateBuilder,
+    headers: StartingPosition,
+    game_headers: GameHeaders,
     skip: bool,
+    games: usize,
 }
 
-impl<'pgn> Visitor<'pgn> for PolicyDataGenerator {
+impl<'a> PolicyDataGenerator<'a> {
+    fn new(config: &'a TrainingConfig) -> Self {
+        PolicyDataGenerator {
+            config,
+            out: Vec::new(),
+            keys: Vec::new(),
+            state: StateBuilder::default(),
+            headers: StartingPosition::default(),
+            game_headers: GameHeaders::default(),
+            skip: true,
+            games: 0,
+        }
+    }
+}
+
+impl<'a> ChunkGenerator for PolicyDataGenerator<'a> {
+    fn games(&self) -> usize {
+        self.games
+    }
+}
+
+impl<'a, 'pgn> Visitor<'pgn> for PolicyDataGenerator<'a> {
     type Result = ();
 
     fn begin_game(&mut self) {
         self.state = StateBuilder::default();
+        self.headers = StartingPosition::default();
+        self.game_headers = GameHeaders::default();
         self.skip = false;
     }
 
@@ -208,33 +501,114 @@ impl<'pgn> Visitor<'pgn> for PolicyDataGenerator {
     }
 
     fn end_headers(&mut self) -> Skip {
+        match self.headers.state() {
+            Some(state) => self.state = state,
+            None => self.skip = true,
+        }
+        if !self.config.accepts(&self.game_headers, self.config.min_elo_policy) {
+            self.skip = true;
+        }
         Skip(self.skip)
     }
 
     fn header(&mut self, key: &[u8], value: &[u8]) {
-        if key == b"WhiteElo" || key == b"BlackElo" {
-            let elo: i32 = str::from_utf8(value).unwrap().parse().unwrap();
-            if elo < MIN_ELO_POLICY {
-                self.skip = true;
-            }
-        }
+        self.headers.header(key, value);
+        self.game_headers.header(key, value);
     }
 
     fn begin_variation(&mut self) -> Skip {
         Skip(true) // stay in the mainline
     }
 
-    fn end_game(&mut self, _game: &'pgn [u8]) -> Self::Result {
+    fn end_game(&mut self, game: &'pgn [u8]) -> Self::Result {
+        if is_game(game) {
+            self.games += 1;
+        }
         let (mut state, moves) = self.state.extract();
         for m in moves {
             let legals = state.available_moves();
             let legals = legals.as_slice();
             let index = legals.iter().position(|x| m == *x).unwrap();
-            write!(self.key_file, "{} {}\n", legals.len(), index).unwrap();
+            writeln!(self.keys, "{} {}", legals.len(), index).unwrap();
             for opt in legals {
-                policy_features::featurize(&state, &opt).write_libsvm(&mut self.out_file, 0, |_| true);
+                policy_features::featurize(&state, opt).write_libsvm(&mut self.out, 0, |_| true);
             }
             state.make_move(&m);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> GameHeaders {
+        let mut headers = GameHeaders::default();
+        for &(key, value) in pairs {
+            headers.header(key.as_bytes(), value.as_bytes());
+        }
+        headers
+    }
+
+    #[test]
+    fn split_at_games() {
+        let pgn = b"[Event \"a\"]\n\n1. e4 { [%clk 0:03:00] } e5 1-0\n\n[Event \"b\"]\r\n\r\n1. d4 *\r\n\r\n[Event \"c\"]\n\n1. c4 *\n";
+        let chunks = split_games(pgn, 1);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| chunk.starts_with(b"[Event")));
+        assert_eq!(chunks.concat(), &pgn[..]);
+        assert_eq!(split_games(pgn, 60).len(), 2);
+        assert_eq!(split_games(pgn, pgn.len()), vec![&pgn[..]]);
+    }
+
+    #[test]
+    fn same_data_on_any_number_of_threads() {
+        let games = ["1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7 1-0",
+                     "1. d4 d5 2. c4 e6 3. Nc3 Nf6 4. Bg5 Be7 5. e3 O-O 1/2-1/2",
+                     "1. c4 e5 2. Nc3 Nf6 3. g3 d5 4. cxd5 Nxd5 5. Bg2 Nb6 0-1"];
+        let pgn: String = (0..12)
+            .map(|i| format!("[Event \"{}\"]\n\n{}\n\n", i, games[i % games.len()]))
+            .collect();
+        let chunks = split_games(pgn.as_bytes(), 1);
+        let whitelist = [true; NUM_FEATURES];
+        let data = |num_threads| {
+            let config = TrainingConfig {num_threads, samples_per_game: 3, ..TrainingConfig::default()};
+            let mut value = Vec::new();
+            run_chunks(&chunks, &config, |chunk| ValueDataGenerator::new(&config, chunk, Some(&whitelist)),
+                       |generator| value.extend(generator.out));
+            let mut policy = Vec::new();
+            let mut keys = Vec::new();
+            run_chunks(&chunks, &config, |_| PolicyDataGenerator::new(&config), |generator| {
+                policy.extend(generator.out);
+                keys.extend(generator.keys);
+            });
+            (value, policy, keys)
+        };
+        let (value, policy, keys) = data(1);
+        assert!(!value.is_empty() && !policy.is_empty());
+        assert_eq!(str::from_utf8(&keys).unwrap().lines().count(), 12 * 10);
+        assert!(data(4) == (value, policy, keys));
+    }
+
+    #[test]
+    fn game_filters() {
+        let game = headers(&[("Event", "Rated Blitz game"), ("WhiteElo", "2100"), ("BlackElo", "1900"),
+                             ("TimeControl", "180+0"), ("Termination", "Time forfeit")]);
+        let config = TrainingConfig::default();
+        assert!(config.accepts(&game, config.min_elo));
+        assert!(!config.accepts(&game, config.min_elo_policy));
+        assert!(config.accepts(&headers(&[]), config.min_elo_policy));
+        assert!(!config.accepts(&headers(&[("WhiteElo", "?")]), config.min_elo));
+        let config = TrainingConfig {
+            time_controls: vec!["180+0".into(), "300+0".into()],
+            terminations: vec!["Normal".into()],
+            ..TrainingConfig::default()
+        };
+        assert!(!config.accepts(&game, 0));
+        assert!(config.accepts(&headers(&[("TimeControl", "300+0"), ("Termination", "Normal")]), 0));
+        assert!(!config.accepts(&headers(&[("Termination", "Normal")]), 0));
+        let casual = TrainingConfig {rated: Some(false), ..TrainingConfig::default()};
+        assert!(!casual.accepts(&game, 0));
+        assert!(casual.accepts(&headers(&[("Event", "Casual Blitz game")]), 0));
+    }
+}
@@ -2,19 +2,25 @@ use std::io::{stdin, BufRead};
 use search::Search;
 use state::State;
 use std::str::SplitWhitespace;
-use std::sync::mpsc::{SendError, channel};
+use std::sync::mpsc::{Receiver, SendError, Sender, channel};
 use std::thread;
+use config::Config;
+use engine::Engine;
+use explain;
+use xboard;
+use strength::{MIN_ELO, MAX_ELO};
 
 pub type Tokens<'a> = SplitWhitespace<'a>;
 
-pub const TIMEUP: &'static str = "timeup";
-const ENGINE_NAME: &'static str = "Sashimi";
-const ENGINE_AUTHOR: &'static str = "Jacob Jackson";
-const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
+pub const TIMEUP: &str = "timeup";
+pub const ENGINE_NAME: &str = "Sashimi";
+const ENGINE_AUTHOR: &str = "Jacob Jackson";
+pub const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
+const DEFAULT_ELO: u32 = 1500;
 
-pub fn main(commands: Vec<String>) {
-    let mut search = Search::new(State::default());
-    let mut position_num: u64 = 0;
+/// A channel that gets `commands`, then the lines of standard input, then `quit`. The search
+/// threads send `TIMEUP` down it too.
+pub fn read_commands(commands: Vec<String>) -> (Sender<String>, Receiver<String>) {
     let (sender, receiver) = channel();
     for cmd in commands {
         sender.send(cmd).unwrap();
@@ -30,20 +36,68 @@ pub fn main(commands: Vec<String>) {
             Ok(())
         });
     }
-    for line in receiver {
+    (sender, receiver)
+}
+
+pub fn main(commands: Vec<String>, mut config: Config) {
+    let default_seed = config.seed;
+    let mut limit_strength = config.elo.is_some();
+    let mut elo = config.elo.unwrap_or(DEFAULT_ELO);
+    let mut chess960 = false;
+    let mut state = State::default();
+    let mut search = Search::new(state.clone(), &config);
+    let mut position_num: u64 = 0;
+    let (sender, receiver) = read_commands(commands);
+    for line in receiver.iter() {
         debug!("Received '{}'.", line);
         let mut tokens = line.split_whitespace();
         if let Some(first_word) = tokens.next() {
             match first_word {
-                "uci"        => uci(),
+                "uci"        => uci(default_seed, elo),
+                // The GUI speaks CECP instead.
+                "xboard" | "protover" => {
+                    drop(search);
+                    return xboard::run(&line, &sender, &receiver, config);
+                }
                 "isready"    => println!("readyok"),
-                "setoption"  => (),
+                "setoption"  => match parse_option(tokens) {
+                    Some((ref name, ref value)) if name.eq_ignore_ascii_case("Seed") => match value.parse() {
+                        Ok(x) => {
+                            config.seed = x;
+                            search = search.set_seed(x);
+                        }
+                        Err(_) => error!("Couldn't parse '{}' as a seed", value),
+                    },
+                    Some((ref name, ref value)) if name.eq_ignore_ascii_case("UCI_LimitStrength") => match value.parse() {
+                        Ok(x) => {
+                            limit_strength = x;
+                            config.elo = if limit_strength { Some(elo) } else { None };
+                            search = search.set_elo(config.elo);
+                        }
+                        Err(_) => error!("Couldn't parse '{}' as true or false", value),
+                    },
+                    Some((ref name, ref value)) if name.eq_ignore_ascii_case("UCI_Elo") => match value.parse() {
+                        Ok(x) => {
+                            elo = x;
+                            config.elo = if limit_strength { Some(elo) } else { None };
+                            search = search.set_elo(config.elo);
+                        }
+                        Err(_) => error!("Couldn't parse '{}' as an Elo", value),
+                    },
+                    Some((ref name, ref value)) if name.eq_ignore_ascii_case("UCI_Chess960") => match value.parse() {
+                        Ok(x) => chess960 = x,
+                        Err(_) => error!("Couldn't parse '{}' as true or false", value),
+                    },
+                    Some((name, _)) => error!("Unknown option: {}", name),
+                    None => error!("Couldn't parse '{}' as setoption", line),
+                },
                 "ucinewgame" => position_num += 1,
                 "position"   => {
                     position_num += 1;
-                    if let Some(state) = State::from_tokens(tokens) {
-                        debug!("\n{}", state.board());
-                        search = Search::new(state);
+                    if let Some(new_state) = State::from_tokens(tokens, chess960) {
+                        debug!("\n{}", new_state.board());
+                        state = new_state;
+                        search = Search::new(state.clone(), &config);
                     } else {
                         error!("Couldn't parse '{}' as position", line);
                     }
@@ -57,6 +111,11 @@ pub fn main(commands: Vec<String>) {
                 }
                 "quit"       => return,
                 "n/s"        => search = search.nodes_per_sec(),
+                "eval"       => {
+                    let engine = Engine::new(config.clone());
+                    println!("{}", explain::eval(&engine, &state, is_json(tokens)).trim_end());
+                }
+                "policy"     => println!("{}", explain::policy(&state, is_json(tokens)).trim_end()),
                 "go"         => {
                     search = search.go(tokens, position_num, &sender);
                 },
@@ -66,12 +125,42 @@ pub fn main(commands: Vec<String>) {
     }
 }
 
-pub fn uci() {
+pub fn uci(default_seed: u64, default_elo: u32) {
     println!("id name {} {}", ENGINE_NAME, VERSION.unwrap_or("unknown"));
     println!("id author {}", ENGINE_AUTHOR);
+    println!("option name Seed type spin default {} min 0 max {}", default_seed, i64::MAX);
+    println!("option name UCI_LimitStrength type check default false");
+    println!("option name UCI_Chess960 type check default false");
+    println!("option name UCI_Elo type spin default {} min {} max {}", default_elo, MIN_ELO, MAX_ELO);
     println!("uciok");
 }
 
+/// Whether an `eval` or `policy` command asked for JSON.
+fn is_json(mut tokens: Tokens) -> bool {
+    tokens.any(|x| x == "--json")
+}
+
+/// Splits the rest of `setoption name <name> value <value>` into the name and the value.
+fn parse_option(mut tokens: Tokens) -> Option<(String, String)> {
+    if tokens.next() != Some("name") {
+        return None;
+    }
+    let mut name = Vec::new();
+    let mut value = Vec::new();
+    let mut in_value = false;
+    for token in tokens {
+        match token {
+            "value" if !in_value => in_value = true,
+            _ if in_value => value.push(token),
+            _ => name.push(token),
+        }
+    }
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.join(" "), value.join(" ")))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,6 +168,13 @@ mod tests {
     fn en_passant() {
         let s = String::from("startpos moves g1f3 g8f6 d2d4 b8c6 b1c3 e7e6 c1g5 h7h6 g5h4 g7g5 h4g3 f8b4 d1d3 g5g4 f3d2 e8g8 e1c1 d7d6 a2a3 b4a5 d2c4 a5c3 d3c3 c8d7 c3b3 b7b6 g3h4 a7a5 a3a4 d8e7 c4e3 h6h5 g2g3 d6d5 f1g2 f8b8 c2c4 b6b5 c4b5 c6b4 c1b1 c7c6 b5c6 b4c6 b3c3 b8b4 c3a3 a8b8 d1d3 b4b2 a3b2 b8b2 b1b2 e7b4 d3b3 b4d4 b2b1 c6b4 h1d1 d4e5 d1c1 d7a4 b3a3 a4b5 f2f4 g4f3");
         let tokens = s.split_whitespace();
-        State::from_tokens(tokens).unwrap();
+        State::from_tokens(tokens, false).unwrap();
+    }
+
+    #[test]
+    fn setoption() {
+        assert_eq!(parse_option("name Seed value 42".split_whitespace()), Some(("Seed".into(), "42".into())));
+        assert_eq!(parse_option("name Clear Hash".split_whitespace()), Some(("Clear Hash".into(), "".into())));
+        assert_eq!(parse_option("Seed 42".split_whitespace()), None);
     }
 }
@@ -0,0 +1,472 @@
+//! The Xboard protocol (CECP, version 2), for the GUIs and tournament managers that don't speak
+//! UCI. It's chosen with `--xboard`, or when the first command is `xboard` or `protover`.
+//!
+//! Moves are in coordinate notation, like `e2e4`, and the engine thinks for as long as `st`, or
+//! the clock from `level` and `time`, allows. With `post`, a line of thinking output is printed
+//! when a move is chosen, and every second while analysing.
+
+use mcts::GameState;
+use search::{Report, Search, to_uci};
+use state::{State, Move, BoardStatus};
+use config::Config;
+use strength;
+use uci::{self, TIMEUP, ENGINE_NAME, VERSION};
+use shakmaty::{Color, Setup};
+use std::cmp::{max, min};
+use std::io::{self, Write};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Sent while analysing, so that the thinking output is printed every `ANALYSIS_INTERVAL`.
+const TICK: &str = "tick";
+const ANALYSIS_INTERVAL: Duration = Duration::from_secs(1);
+/// How many more moves the clock is assumed to have to last, when `level` doesn't say.
+const MOVES_TO_GO: u32 = 30;
+/// Kept back from every move for the time it takes to send it.
+const MOVE_OVERHEAD: Duration = Duration::from_millis(100);
+const MIN_THINK_TIME: Duration = Duration::from_millis(10);
+
+/// The time control, from `st` or `level`, and the engine's clock, from `time`.
+struct Clock {
+    per_move: Option<Duration>,
+    moves_per_control: u32,
+    increment: Duration,
+    remaining: Option<Duration>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock {
+            per_move: Some(Duration::from_secs(5)),
+            moves_per_control: 0,
+            increment: Duration::from_secs(0),
+            remaining: None,
+        }
+    }
+}
+
+impl Clock {
+    /// How long to think about a move, `fullmoves` into the game.
+    fn think_time(&self, fullmoves: u32) -> Duration {
+        if let Some(t) = self.per_move {
+            return max(t.saturating_sub(MOVE_OVERHEAD), MIN_THINK_TIME);
+        }
+        let remaining = self.remaining.unwrap_or(Duration::from_secs(60));
+        let moves_to_go = if self.moves_per_control == 0 {
+            MOVES_TO_GO
+        } else {
+            self.moves_per_control - (fullmoves.saturating_sub(1) % self.moves_per_control)
+        };
+        let t = remaining / moves_to_go + self.increment;
+        max(min(t, remaining / 2).saturating_sub(MOVE_OVERHEAD), MIN_THINK_TIME)
+    }
+}
+
+/// `level`'s base time, in minutes or as `minutes:seconds`.
+fn parse_base(s: &str) -> Option<Duration> {
+    let mut parts = s.split(':');
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: u64 = match parts.next() {
+        Some(x) => x.parse().ok()?,
+        None => 0,
+    };
+    Some(Duration::from_secs(minutes * 60 + seconds))
+}
+
+fn parse_seconds(s: &str) -> Option<Duration> {
+    s.parse::<f64>().ok().filter(|x| *x >= 0.0).map(Duration::from_secs_f64)
+}
+
+/// The result command for a finished game, like `1-0 {White mates}`.
+fn result(state: &State) -> Option<&'static str> {
+    match state.outcome() {
+        BoardStatus::Ongoing => None,
+        BoardStatus::Checkmate if state.turn() == Color::White => Some("0-1 {Black mates}"),
+        BoardStatus::Checkmate => Some("1-0 {White mates}"),
+        BoardStatus::Stalemate if state.available_moves().is_empty() && !state.checkers().any() =>
+            Some("1/2-1/2 {Stalemate}"),
+        BoardStatus::Stalemate => Some("1/2-1/2 {Draw by repetition}"),
+    }
+}
+
+/// The engine's side of the protocol, which writes its output to `out`.
+struct Xboard<W: Write> {
+    out: W,
+    config: Config,
+    state: State,
+    /// `None` while the engine waits.
+    search: Option<Search>,
+    /// The side the engine plays, or `None` in force mode.
+    engine_color: Option<Color>,
+    analysing: bool,
+    post: bool,
+    clock: Clock,
+    /// Whether `search` is choosing a move to play.
+    thinking: bool,
+    started: Instant,
+    /// Counts the searches, so that the timers of old ones are ignored.
+    position_num: u64,
+}
+
+impl<W: Write> Xboard<W> {
+    fn new(config: Config, out: W) -> Self {
+        Xboard {
+            out,
+            config,
+            state: State::default(),
+            search: None,
+            engine_color: Some(Color::Black),
+            analysing: false,
+            post: false,
+            clock: Clock::default(),
+            thinking: false,
+            started: Instant::now(),
+            position_num: 0,
+        }
+    }
+
+    /// Stops any search, and starts whatever the engine should be doing in the current position.
+    fn restart(&mut self, sender: &Sender<String>) {
+        self.position_num += 1;
+        self.thinking = false;
+        self.search = None;
+        if self.analysing {
+            if self.state.outcome() == BoardStatus::Ongoing {
+                self.started = Instant::now();
+                let search = Search::new(self.state.clone(), &self.config);
+                self.search = Some(search.think(None, self.position_num, sender));
+                self.schedule_tick(sender);
+            }
+        } else if self.engine_color == Some(self.state.turn()) {
+            if let Some(result) = result(&self.state) {
+                writeln!(self.out, "{}", result).unwrap();
+                self.engine_color = None;
+            } else if let Some(elo) = self.config.elo {
+                // Limited strength searches a fixed number of playouts, so it doesn't need the clock.
+                info!("Playing at Elo {} ({:?}).", elo, strength::Strength::for_elo(elo));
+                self.thinking = true;
+                let search = Search::new(self.state.clone(), &self.config);
+                self.search = Some(search.think_limited(elo, self.position_num, sender));
+            } else {
+                let think_time = self.clock.think_time(self.state.chess().fullmoves());
+                info!("Thinking for {:?}.", think_time);
+                self.thinking = true;
+                self.started = Instant::now();
+                let search = Search::new(self.state.clone(), &self.config);
+                self.search = Some(search.think(Some(think_time), self.position_num, sender));
+            }
+        }
+    }
+
+    fn schedule_tick(&self, sender: &Sender<String>) {
+        let sender = sender.clone();
+        let position_num = self.position_num;
+        thread::spawn(move || {
+            thread::sleep(ANALYSIS_INTERVAL);
+            let _ = sender.send(format!("{} {}", TICK, position_num));
+        });
+    }
+
+    /// A line of thinking output: depth, score in centipawns, time in centiseconds, nodes and
+    /// the principal variation.
+    fn print_thinking(&mut self, report: &Report) {
+        if self.post {
+            let centis = self.started.elapsed().as_millis() / 10;
+            writeln!(self.out, "{} {} {} {}{}", report.pv.len(), report.score_cp, centis, report.nodes,
+                report.pv_string()).unwrap();
+        }
+    }
+
+    /// Plays the engine's move, and says so.
+    fn play(&mut self, mov: Move, sender: &Sender<String>) {
+        writeln!(self.out, "move {}", to_uci(&mov, false)).unwrap();
+        self.state.make_move(&mov);
+        if let Some(result) = result(&self.state) {
+            writeln!(self.out, "{}", result).unwrap();
+            self.engine_color = None;
+        }
+        self.restart(sender);
+    }
+
+    /// Stops thinking and plays the best move so far.
+    fn move_now(&mut self, sender: &Sender<String>) {
+        if !self.thinking {
+            return;
+        }
+        self.thinking = false;
+        let report = self.search.take().and_then(Search::stop);
+        if let Some(report) = report {
+            // At a limited strength, there is no thinking to show.
+            if self.config.elo.is_none() {
+                self.print_thinking(&report);
+            }
+            self.play(report.best_move, sender);
+        }
+    }
+
+    fn user_move(&mut self, mov_str: &str, sender: &Sender<String>) {
+        match self.state.parse_uci(mov_str) {
+            Some(mov) if self.state.outcome() == BoardStatus::Ongoing => {
+                self.state.make_move(&mov);
+                self.restart(sender);
+            }
+            _ => writeln!(self.out, "Illegal move: {}", mov_str).unwrap(),
+        }
+    }
+
+    /// Returns whether to go on.
+    fn command(&mut self, line: &str, sender: &Sender<String>) -> bool {
+        let mut tokens = line.split_whitespace();
+        let first_word = match tokens.next() {
+            Some(x) => x,
+            None => return true,
+        };
+        match first_word {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name"
+                | "rating" | "ics" | "otim" | "." => (),
+            "protover" => writeln!(self.out,
+                "feature myname=\"{} {}\" setboard=1 usermove=1 analyze=1 ping=1 colors=0 sigint=0 \
+                 sigterm=0 reuse=1 variants=\"normal\" done=1",
+                ENGINE_NAME, VERSION.unwrap_or("unknown")).unwrap(),
+            "ping" => writeln!(self.out, "pong {}", tokens.next().unwrap_or("")).unwrap(),
+            "new" => {
+                self.state = State::default();
+                self.engine_color = Some(Color::Black);
+                self.clock.remaining = None;
+                self.restart(sender);
+            }
+            "force" => {
+                self.engine_color = None;
+                self.restart(sender);
+            }
+            "go" => {
+                self.engine_color = Some(self.state.turn());
+                self.restart(sender);
+            }
+            "usermove" => match tokens.next() {
+                Some(mov_str) => self.user_move(mov_str, sender),
+                None => writeln!(self.out, "Error (no move): usermove").unwrap(),
+            },
+            "?" => self.move_now(sender),
+            "st" => match tokens.next().and_then(parse_seconds) {
+                Some(t) => self.clock.per_move = Some(t),
+                None => writeln!(self.out, "Error (bad time): {}", line).unwrap(),
+            },
+            "level" => {
+                let mps = tokens.next().and_then(|x| x.parse().ok());
+                let base = tokens.next().and_then(parse_base);
+                let inc = tokens.next().and_then(parse_seconds);
+                match (mps, base, inc) {
+                    (Some(mps), Some(base), Some(inc)) => {
+                        self.clock = Clock {
+                            per_move: None,
+                            moves_per_control: mps,
+                            increment: inc,
+                            remaining: Some(base),
+                        };
+                    }
+                    _ => writeln!(self.out, "Error (bad time control): {}", line).unwrap(),
+                }
+            }
+            "time" => match tokens.next().and_then(|x| x.parse::<u64>().ok()) {
+                Some(centis) => self.clock.remaining = Some(Duration::from_millis(centis * 10)),
+                None => writeln!(self.out, "Error (bad time): {}", line).unwrap(),
+            },
+            "analyze" => {
+                self.analysing = true;
+                self.engine_color = None;
+                self.restart(sender);
+            }
+            "exit" => {
+                self.analysing = false;
+                self.restart(sender);
+            }
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "setboard" => {
+                let fen: Vec<_> = tokens.collect();
+                match State::from_fen(&fen.join(" ")) {
+                    Some(state) => {
+                        self.state = state;
+                        self.restart(sender);
+                    }
+                    None => writeln!(self.out, "tellusererror Illegal position").unwrap(),
+                }
+            }
+            "result" => {
+                self.engine_color = None;
+                self.restart(sender);
+            }
+            TIMEUP => {
+                if tokens.next().and_then(|x| x.parse().ok()) == Some(self.position_num) {
+                    self.move_now(sender);
+                }
+            }
+            TICK => {
+                if self.analysing && tokens.next().and_then(|x| x.parse().ok()) == Some(self.position_num) {
+                    if let Some(search) = self.search.take() {
+                        let (search, report) = search.peek();
+                        self.search = Some(search);
+                        if let Some(report) = report {
+                            self.print_thinking(&report);
+                        }
+                    }
+                    self.schedule_tick(sender);
+                }
+            }
+            "quit" => return false,
+            _ => writeln!(self.out, "Error (unknown command): {}", first_word).unwrap(),
+        }
+        true
+    }
+}
+
+/// Speaks CECP, with the commands from `commands` and then standard input.
+pub fn main(commands: Vec<String>, config: Config) {
+    let (sender, receiver) = uci::read_commands(commands);
+    run("xboard", &sender, &receiver, config);
+}
+
+/// Speaks CECP from `first_line` on, with the channel that the UCI front end was reading.
+pub fn run(first_line: &str, sender: &Sender<String>, receiver: &Receiver<String>, config: Config) {
+    let mut xboard = Xboard::new(config, io::stdout());
+    if !xboard.command(first_line, sender) {
+        return;
+    }
+    for line in receiver.iter() {
+        debug!("Received '{}'.", line);
+        if !xboard.command(&line, sender) {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem;
+    use std::sync::mpsc::channel;
+
+    fn output(xboard: &mut Xboard<Vec<u8>>) -> String {
+        String::from_utf8(mem::take(&mut xboard.out)).unwrap()
+    }
+
+    /// Passes on the messages from the engine's own threads until one of `kind` for the current
+    /// search has been handled.
+    fn wait_for(xboard: &mut Xboard<Vec<u8>>, sender: &Sender<String>, receiver: &Receiver<String>,
+                kind: &str) {
+        loop {
+            let line = receiver.recv_timeout(Duration::from_secs(60)).unwrap();
+            let current = format!("{} {}", kind, xboard.position_num);
+            xboard.command(&line, sender);
+            if line == current {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn xboard_commands() {
+        let (sender, receiver) = channel();
+        let config = Config {num_threads: 1, ..Config::default()};
+        let mut xboard = Xboard::new(config, Vec::new());
+        for line in &["xboard", "protover 2", "new", "st 1"] {
+            assert!(xboard.command(line, &sender));
+        }
+        assert!(output(&mut xboard).starts_with("feature myname="));
+
+        // In force mode the engine only keeps track of the moves.
+        xboard.command("force", &sender);
+        xboard.command("usermove e2e4", &sender);
+        xboard.command("usermove e7e5", &sender);
+        assert!(xboard.search.is_none());
+        assert_eq!(output(&mut xboard), "");
+        xboard.command("usermove e2e5", &sender);
+        assert_eq!(output(&mut xboard), "Illegal move: e2e5\n");
+
+        // `go` makes it play the side to move, and go on playing it.
+        xboard.command("go", &sender);
+        assert!(xboard.thinking);
+        wait_for(&mut xboard, &sender, &receiver, TIMEUP);
+        let reply = output(&mut xboard);
+        assert!(reply.starts_with("move "), "{}", reply);
+        assert_eq!(xboard.state.turn(), Color::Black);
+        xboard.command("usermove g8f6", &sender);
+        assert!(xboard.thinking);
+        // `result` ends the game, and the move the engine was thinking about is never played.
+        xboard.command("result 1/2-1/2 {Draw agreed}", &sender);
+        assert!(xboard.search.is_none());
+        assert_eq!(xboard.engine_color, None);
+
+        // After `new` the engine plays Black.
+        xboard.command("new", &sender);
+        xboard.command("usermove d2d4", &sender);
+        assert!(xboard.thinking);
+        thread::sleep(Duration::from_millis(100));
+        xboard.command("?", &sender);
+        let reply = output(&mut xboard);
+        assert!(reply.starts_with("move "), "{}", reply);
+        assert_eq!(xboard.state.turn(), Color::White);
+
+        // Setting up a stalemate with the engine to move ends the game.
+        xboard.command("force", &sender);
+        xboard.command("setboard 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", &sender);
+        xboard.command("go", &sender);
+        assert_eq!(output(&mut xboard), "1/2-1/2 {Stalemate}\n");
+        assert_eq!(xboard.engine_color, None);
+        xboard.command("setboard not a position", &sender);
+        assert_eq!(output(&mut xboard), "tellusererror Illegal position\n");
+
+        // Analysing prints the thinking output every so often, without moving.
+        xboard.command("setboard rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &sender);
+        xboard.command("post", &sender);
+        xboard.command("analyze", &sender);
+        assert!(xboard.search.is_some());
+        wait_for(&mut xboard, &sender, &receiver, TICK);
+        let thinking = output(&mut xboard);
+        assert!(thinking.split_whitespace().next().unwrap().parse::<usize>().is_ok(), "{}", thinking);
+        assert!(!thinking.contains("move"), "{}", thinking);
+        xboard.command("exit", &sender);
+        assert!(xboard.search.is_none());
+        assert!(!xboard.command("quit", &sender));
+    }
+
+    #[test]
+    fn limited_strength_thinks_in_the_background() {
+        let (sender, receiver) = channel();
+        let config = Config {num_threads: 1, elo: Some(1600), ..Config::default()};
+        let mut xboard = Xboard::new(config, Vec::new());
+        xboard.command("new", &sender);
+        xboard.command("usermove e2e4", &sender);
+        // The move is played when the search says it has been chosen.
+        assert!(xboard.thinking);
+        assert_eq!(output(&mut xboard), "");
+        wait_for(&mut xboard, &sender, &receiver, TIMEUP);
+        let reply = output(&mut xboard);
+        assert!(reply.starts_with("move "), "{}", reply);
+        // Meanwhile, the commands are still read.
+        xboard.command("usermove d2d4", &sender);
+        assert!(xboard.thinking);
+        xboard.command("force", &sender);
+        assert!(xboard.search.is_none());
+        assert_eq!(output(&mut xboard), "");
+    }
+
+    #[test]
+    fn xboard_clock() {
+        assert_eq!(parse_base("5"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_base("0:30"), Some(Duration::from_secs(30)));
+        let clock = Clock {
+            per_move: None,
+            moves_per_control: 40,
+            increment: Duration::from_secs(0),
+            remaining: Some(Duration::from_secs(100)),
+        };
+        // Move 40 is the last before the time control.
+        assert_eq!(clock.think_time(1), Duration::from_millis(2500) - MOVE_OVERHEAD);
+        assert_eq!(clock.think_time(40), Duration::from_secs(50) - MOVE_OVERHEAD);
+        let clock = Clock {per_move: Some(Duration::from_secs(2)), ..clock};
+        assert_eq!(clock.think_time(1), Duration::from_secs(2) - MOVE_OVERHEAD);
+    }
+}
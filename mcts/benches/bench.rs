@@ -1,14 +1,105 @@
-#![feature(test)]
+// The benchmarks are timed by hand so that they build on stable Rust. Run them with `cargo bench -p mcts`.
 
-include!("../examples/counting_game.rs");
+extern crate mcts;
 
-extern crate test;
-use self::test::Bencher;
+use mcts::*;
+use mcts::tree_policy::*;
+use mcts::transposition_table::*;
+use std::time::Instant;
 
-#[bench]
-fn counting_game(b: &mut Bencher) {
-    b.iter(|| {
-        let mut mcts = MCTSManager::new(CountingGame(0), MyMCTS, UCTPolicy::new(1e6), MyEvaluator);
-        mcts.playout_n(100000);
+#[derive(Clone)]
+struct CountingGame(i64);
+
+#[derive(Clone, Debug)]
+enum Move {
+    Add, Sub
+}
+
+impl GameState for CountingGame {
+    type Move = Move;
+    type Player = ();
+    type MoveList = Vec<Move>;
+
+    fn current_player(&self) {}
+    fn available_moves(&self) -> Vec<Move> {
+        if self.0 == 100 {
+            vec![]
+        } else {
+            vec![Move::Add, Move::Sub]
+        }
+    }
+    fn make_move(&mut self, mov: &Move) {
+        match *mov {
+            Move::Add => self.0 += 1,
+            Move::Sub => self.0 -= 1,
+        }
+    }
+}
+
+impl TranspositionHash for CountingGame {
+    fn hash(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
+struct MyEvaluator;
+
+impl Evaluator<MyMCTS> for MyEvaluator {
+    type StateEvaluation = i64;
+
+    fn evaluate_new_state(&self, state: &CountingGame, moves: &Vec<Move>, _: Option<SearchHandle<MyMCTS>>)
+        -> (Vec<()>, i64) {
+        (vec![(); moves.len()], state.0)
+    }
+    fn interpret_evaluation_for_player(&self, evaln: &i64, _: &()) -> i64 {
+        *evaln
+    }
+    fn evaluate_existing_state(&self, _: &CountingGame, evaln: &i64, _: SearchHandle<MyMCTS>) -> i64 {
+        *evaln
+    }
+}
+
+#[derive(Default)]
+struct MyMCTS;
+
+impl MCTS for MyMCTS {
+    type State = CountingGame;
+    type Reward = i64;
+    type Eval = MyEvaluator;
+    type NodeData = ();
+    type ExtraThreadData = ();
+    type TreePolicy = UCTPolicy;
+    type TranspositionTable = ApproxTable<Self>;
+    type PlayoutData = ();
+
+    fn virtual_loss(&self) -> i64 {
+        500
+    }
+    fn cycle_behaviour(&self) -> CycleBehaviour<Self> {
+        CycleBehaviour::UseCurrentEvalWhenCycleDetected
+    }
+}
+
+fn new_manager() -> MCTSManager<MyMCTS> {
+    MCTSManager::new(CountingGame(0), MyMCTS, MyEvaluator, UCTPolicy::new(5.0), ApproxTable::new(1024))
+}
+
+/// Prints the average time of `iterations` runs of `f`, after one run to warm up.
+fn bench<F: FnMut()>(name: &str, iterations: u32, mut f: F) {
+    f();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    println!("{:24} {:>10.2?} per iteration", name, start.elapsed() / iterations);
+}
+
+fn main() {
+    bench("counting_game", 10, || new_manager().playout_n(10_000));
+    bench("counting_game_4_threads", 10, || new_manager().playout_n_parallel(10_000, 4));
+    bench("counting_game_root_4", 10, || {
+        let mut mcts = new_manager();
+        mcts.root_parallel(4, || (MyMCTS, MyEvaluator, UCTPolicy::new(5.0), ApproxTable::new(1024)));
+        mcts.playout_n_root_parallel(10_000, Some(1000));
     });
 }
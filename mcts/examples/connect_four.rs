@@ -0,0 +1,167 @@
+// Plays a game of Connect Four against itself. Rollouts prefer winning moves and central columns,
+// and are cut off after 20 moves, in which case the position is scored as a draw.
+
+extern crate mcts;
+
+use mcts::*;
+use mcts::rollout::*;
+use mcts::tree_policy::*;
+
+const WIDTH: usize = 7;
+const HEIGHT: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Disc {
+    Red, Yellow
+}
+
+#[derive(Clone)]
+struct ConnectFour {
+    // Column-major, from the bottom up.
+    cells: [[Option<Disc>; HEIGHT]; WIDTH],
+    heights: [usize; WIDTH],
+    to_move: Disc,
+    winner: Option<Disc>,
+    moves_made: usize,
+}
+
+impl ConnectFour {
+    fn new() -> Self {
+        Self {
+            cells: [[None; HEIGHT]; WIDTH],
+            heights: [0; WIDTH],
+            to_move: Disc::Red,
+            winner: None,
+            moves_made: 0,
+        }
+    }
+
+    fn get(&self, col: isize, row: isize) -> Option<Disc> {
+        if col < 0 || row < 0 || col >= WIDTH as isize || row >= HEIGHT as isize {
+            None
+        } else {
+            self.cells[col as usize][row as usize]
+        }
+    }
+
+    /// Whether the disc at `col`, `row` is part of a line of four.
+    fn makes_four(&self, col: usize, row: usize, disc: Disc) -> bool {
+        let (col, row) = (col as isize, row as isize);
+        [(1, 0), (0, 1), (1, 1), (1, -1)].iter().any(|&(dc, dr)| {
+            let count = |sign: isize| (1..4)
+                .take_while(|&i| self.get(col + sign * i * dc, row + sign * i * dr) == Some(disc))
+                .count();
+            1 + count(1) + count(-1) >= 4
+        })
+    }
+
+    fn wins_immediately(&self, col: usize) -> bool {
+        self.makes_four(col, self.heights[col], self.to_move)
+    }
+
+    fn print(&self) {
+        for row in (0..HEIGHT).rev() {
+            let line: String = (0..WIDTH).map(|col| match self.cells[col][row] {
+                Some(Disc::Red) => 'R',
+                Some(Disc::Yellow) => 'Y',
+                None => '.',
+            }).collect();
+            println!("{}", line);
+        }
+        println!();
+    }
+}
+
+impl GameState for ConnectFour {
+    type Move = usize;
+    type Player = Disc;
+    type MoveList = Vec<usize>;
+
+    fn current_player(&self) -> Disc {
+        self.to_move
+    }
+    fn available_moves(&self) -> Vec<usize> {
+        if self.winner.is_some() {
+            return vec![];
+        }
+        (0..WIDTH).filter(|&col| self.heights[col] < HEIGHT).collect()
+    }
+    fn make_move(&mut self, &col: &usize) {
+        let row = self.heights[col];
+        self.cells[col][row] = Some(self.to_move);
+        self.heights[col] += 1;
+        self.moves_made += 1;
+        if self.makes_four(col, row, self.to_move) {
+            self.winner = Some(self.to_move);
+        }
+        self.to_move = if self.to_move == Disc::Red { Disc::Yellow } else { Disc::Red };
+    }
+}
+
+impl RolloutState for ConnectFour {
+    // The winner, or `None` for a draw.
+    type Outcome = Option<Disc>;
+    type Reward = i64;
+
+    fn outcome(&self) -> Option<Option<Disc>> {
+        if self.winner.is_some() {
+            Some(self.winner)
+        } else if self.moves_made == WIDTH * HEIGHT {
+            Some(None)
+        } else {
+            None
+        }
+    }
+    fn reward(winner: &Option<Disc>, player: &Disc) -> i64 {
+        match *winner {
+            Some(disc) if disc == *player => 1,
+            Some(_) => -1,
+            None => 0,
+        }
+    }
+}
+
+fn rollout_weight(state: &ConnectFour, &col: &usize) -> f32 {
+    if state.wins_immediately(col) {
+        100.0
+    } else {
+        // 4 for the centre column down to 1 for the edges.
+        4.0 - (col as f32 - 3.0).abs()
+    }
+}
+
+fn unfinished_is_a_draw(_: &ConnectFour) -> Option<Disc> {
+    None
+}
+
+type Policy = WeightedRollout<fn(&ConnectFour, &usize) -> f32>;
+
+#[derive(Default)]
+struct MyMCTS;
+
+impl MCTS for MyMCTS {
+    type State = ConnectFour;
+    type Reward = i64;
+    type Eval = RolloutEvaluator<Self, Policy>;
+    type NodeData = ();
+    type ExtraThreadData = ();
+    type TreePolicy = UCTPolicy;
+    type TranspositionTable = ();
+    type PlayoutData = ();
+}
+
+fn main() {
+    let mut state = ConnectFour::new();
+    while state.outcome().is_none() {
+        let eval = RolloutEvaluator::new(WeightedRollout::new(rollout_weight as fn(&ConnectFour, &usize) -> f32))
+            .with_max_depth(20, unfinished_is_a_draw);
+        let mut mcts = MCTSManager::new(state.clone(), MyMCTS, eval, UCTPolicy::new(1.0), ());
+        mcts.playout_n(20000);
+        state.make_move(&mcts.best_move().unwrap());
+        state.print();
+    }
+    match state.outcome().unwrap() {
+        Some(disc) => println!("{:?} wins", disc),
+        None => println!("Draw"),
+    }
+}
@@ -17,9 +17,7 @@ impl GameState for CountingGame {
     type Player = ();
     type MoveList = Vec<Self::Move>;
 
-    fn current_player(&self) -> Self::Player {
-        ()
-    }
+    fn current_player(&self) -> Self::Player {}
 
     fn available_moves(&self) -> Vec<Self::Move> {
         let x = self.0;
@@ -69,6 +67,7 @@ struct MyMCTS;
 
 impl MCTS for MyMCTS {
     type State = CountingGame;
+    type Reward = i64;
     type Eval = MyEvaluator;
     type NodeData = ();
     type ExtraThreadData = ();
@@ -79,6 +78,9 @@ impl MCTS for MyMCTS {
     fn virtual_loss(&self) -> i64 {
         500
     }
+    fn cycle_behaviour(&self) -> CycleBehaviour<Self> {
+        CycleBehaviour::UseCurrentEvalWhenCycleDetected
+    }
 }
 
 fn main() {
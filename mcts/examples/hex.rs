@@ -0,0 +1,225 @@
+// Plays Hex between a UCT player and a RAVE player that get the same number of playouts.
+// Neither has any knowledge of Hex: positions are evaluated by playing random moves until the board is full.
+
+extern crate mcts;
+extern crate rand;
+
+use mcts::*;
+use mcts::rave::*;
+use mcts::tree_policy::*;
+use rand::Rng;
+
+const SIZE: usize = 5;
+const PLAYOUTS_PER_MOVE: u64 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Player {
+    // Red connects the top and bottom edges, Blue connects the left and right edges.
+    Red, Blue
+}
+
+impl Player {
+    fn other(self) -> Self {
+        match self {
+            Player::Red => Player::Blue,
+            Player::Blue => Player::Red,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Hex {
+    cells: [Option<Player>; SIZE * SIZE],
+    to_move: Player,
+    winner: Option<Player>,
+}
+
+impl Hex {
+    fn new() -> Self {
+        Self {cells: [None; SIZE * SIZE], to_move: Player::Red, winner: None}
+    }
+
+    fn neighbours(cell: usize) -> Vec<usize> {
+        let (row, col) = ((cell / SIZE) as isize, (cell % SIZE) as isize);
+        [(-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0)].iter()
+            .map(|&(dr, dc)| (row + dr, col + dc))
+            .filter(|&(r, c)| r >= 0 && c >= 0 && r < SIZE as isize && c < SIZE as isize)
+            .map(|(r, c)| r as usize * SIZE + c as usize)
+            .collect()
+    }
+
+    fn connects(&self, player: Player) -> bool {
+        let starts_on_edge = |cell: usize| match player {
+            Player::Red => cell / SIZE == 0,
+            Player::Blue => cell.is_multiple_of(SIZE),
+        };
+        let ends_on_edge = |cell: usize| match player {
+            Player::Red => cell / SIZE == SIZE - 1,
+            Player::Blue => cell % SIZE == SIZE - 1,
+        };
+        let mut seen = [false; SIZE * SIZE];
+        let mut stack: Vec<usize> = (0..SIZE * SIZE)
+            .filter(|&cell| starts_on_edge(cell) && self.cells[cell] == Some(player))
+            .collect();
+        while let Some(cell) = stack.pop() {
+            if ends_on_edge(cell) {
+                return true;
+            }
+            for next in Self::neighbours(cell) {
+                if !seen[next] && self.cells[next] == Some(player) {
+                    seen[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        false
+    }
+
+    /// Fills the board with random moves. A full Hex board always has exactly one winner.
+    fn rollout<R: Rng>(&self, rng: &mut R) -> Rollout {
+        if let Some(winner) = self.winner {
+            return Rollout {winner, moves: vec![]};
+        }
+        let mut board = self.clone();
+        let mut empty = board.available_moves();
+        rng.shuffle(&mut empty);
+        let mut moves = Vec::with_capacity(empty.len());
+        let mut player = board.to_move;
+        for cell in empty {
+            board.cells[cell] = Some(player);
+            moves.push((player, cell));
+            player = player.other();
+        }
+        let winner = if board.connects(Player::Red) { Player::Red } else { Player::Blue };
+        Rollout {winner, moves}
+    }
+}
+
+/// The result of a random rollout, and the moves it made so that RAVE can learn from them.
+struct Rollout {
+    winner: Player,
+    moves: Vec<(Player, usize)>,
+}
+
+impl GameState for Hex {
+    type Move = usize;
+    type Player = Player;
+    type MoveList = Vec<usize>;
+
+    fn current_player(&self) -> Player {
+        self.to_move
+    }
+    fn available_moves(&self) -> Vec<usize> {
+        if self.winner.is_some() {
+            return vec![];
+        }
+        (0..SIZE * SIZE).filter(|&cell| self.cells[cell].is_none()).collect()
+    }
+    fn make_move(&mut self, mov: &usize) {
+        self.cells[*mov] = Some(self.to_move);
+        if self.connects(self.to_move) {
+            self.winner = Some(self.to_move);
+        }
+        self.to_move = self.to_move.other();
+    }
+}
+
+struct HexEvaluator;
+
+impl<Spec> Evaluator<Spec> for HexEvaluator
+    where Spec: MCTS<State=Hex, Reward=f64, ExtraThreadData=PolicyRng>,
+          Spec::TreePolicy: TreePolicy<Spec, MoveEvaluation=()>
+{
+    type StateEvaluation = Rollout;
+
+    fn evaluate_new_state(&self, state: &Hex, moves: &Vec<usize>, handle: Option<SearchHandle<Spec>>)
+        -> (Vec<()>, Rollout) {
+        let rollout = match handle {
+            Some(mut handle) => state.rollout(&mut handle.thread_data().extra_data.rng),
+            None => state.rollout(&mut PolicyRng::new().rng),
+        };
+        (vec![(); moves.len()], rollout)
+    }
+    fn interpret_evaluation_for_player(&self, rollout: &Rollout, player: &Player) -> f64 {
+        if rollout.winner == *player { 1.0 } else { 0.0 }
+    }
+    fn evaluate_existing_state(&self, state: &Hex, _: &Rollout, mut handle: SearchHandle<Spec>) -> Rollout {
+        state.rollout(&mut handle.thread_data().extra_data.rng)
+    }
+}
+
+#[derive(Default)]
+struct UctMCTS;
+
+impl MCTS for UctMCTS {
+    type State = Hex;
+    type Reward = f64;
+    type Eval = HexEvaluator;
+    type NodeData = ();
+    type ExtraThreadData = PolicyRng;
+    type TreePolicy = UCTPolicy;
+    type TranspositionTable = ();
+    type PlayoutData = ();
+}
+
+#[derive(Default)]
+struct RaveMCTS;
+
+impl MCTS for RaveMCTS {
+    type State = Hex;
+    type Reward = f64;
+    type Eval = HexEvaluator;
+    type NodeData = ();
+    type ExtraThreadData = PolicyRng;
+    type TreePolicy = RAVEPolicy;
+    type TranspositionTable = ();
+    type PlayoutData = MoveHistory<Self>;
+
+    fn amaf_history<'a>(&self, data: &'a mut MoveHistory<Self>) -> Option<&'a mut dyn AmafHistory<Self>> {
+        Some(data)
+    }
+    fn record_evaluation_moves(&self, rollout: &Rollout, history: &mut dyn AmafHistory<Self>) {
+        for &(player, cell) in &rollout.moves {
+            history.record(player, &cell);
+        }
+    }
+}
+
+fn uct_move(state: &Hex) -> usize {
+    let mut mcts = MCTSManager::new(state.clone(), UctMCTS, HexEvaluator, UCTPolicy::new(0.5), ());
+    mcts.playout_n(PLAYOUTS_PER_MOVE);
+    mcts.best_move().unwrap()
+}
+
+fn rave_move(state: &Hex) -> usize {
+    let mut mcts = MCTSManager::new(state.clone(), RaveMCTS, HexEvaluator, RAVEPolicy::new(0.0, 1000.0), ());
+    mcts.playout_n(PLAYOUTS_PER_MOVE);
+    mcts.best_move().unwrap()
+}
+
+/// Plays a game after `opening` has been played for Red. The searches are seeded the same way
+/// every time, so the opening is what makes the games differ.
+fn play(opening: usize, rave_player: Player) -> Player {
+    let mut state = Hex::new();
+    state.make_move(&opening);
+    while state.winner.is_none() {
+        let mov = if state.to_move == rave_player {
+            rave_move(&state)
+        } else {
+            uct_move(&state)
+        };
+        state.make_move(&mov);
+    }
+    state.winner.unwrap()
+}
+
+fn main() {
+    let mut rave_wins = 0;
+    for &rave_player in &[Player::Red, Player::Blue] {
+        let wins = (0..SIZE * SIZE).filter(|&opening| play(opening, rave_player) == rave_player).count();
+        println!("RAVE as {:?}: won {} of {} games", rave_player, wins, SIZE * SIZE);
+        rave_wins += wins;
+    }
+    println!("RAVE won {} of {} games against UCT with {} playouts per move",
+        rave_wins, 2 * SIZE * SIZE, PLAYOUTS_PER_MOVE);
+}
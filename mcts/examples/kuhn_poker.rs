@@ -0,0 +1,144 @@
+// Kuhn poker is a tiny poker game. There are three cards, J, Q and K, and each of the two players
+// antes 1 and is dealt one of them. Then each player in turn can pass or bet 1 more: after a bet,
+// passing folds and betting calls. If nobody folds, the higher card takes the pot.
+//
+// The opponent's card is hidden, so the search samples it at the start of every playout
+// (information-set MCTS). This prints how often the search bets with each card in each situation.
+
+extern crate mcts;
+extern crate rand;
+
+use mcts::*;
+use mcts::ismcts::*;
+use mcts::rollout::*;
+use mcts::tree_policy::*;
+use rand::{Rng, XorShiftRng};
+
+const CARDS: [char; 3] = ['J', 'Q', 'K'];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Pass, Bet
+}
+
+#[derive(Clone, Debug)]
+struct Kuhn {
+    // Indices into `CARDS`.
+    cards: [usize; 2],
+    history: Vec<Action>,
+}
+
+impl Kuhn {
+    /// What player 0 wins, if the game is over.
+    fn payoff(&self) -> Option<f64> {
+        use Action::*;
+        let showdown = |stake: f64| if self.cards[0] > self.cards[1] { stake } else { -stake };
+        match self.history[..] {
+            [Pass, Pass] => Some(showdown(1.)),
+            [Bet, Pass] => Some(1.),
+            [Bet, Bet] | [Pass, Bet, Bet] => Some(showdown(2.)),
+            [Pass, Bet, Pass] => Some(-1.),
+            _ => None,
+        }
+    }
+}
+
+impl GameState for Kuhn {
+    type Move = Action;
+    type Player = usize;
+    type MoveList = Vec<Action>;
+
+    fn current_player(&self) -> usize {
+        self.history.len() % 2
+    }
+    fn available_moves(&self) -> Vec<Action> {
+        if self.payoff().is_some() {
+            vec![]
+        } else {
+            vec![Action::Pass, Action::Bet]
+        }
+    }
+    fn make_move(&mut self, action: &Action) {
+        self.history.push(*action);
+    }
+}
+
+impl RolloutState for Kuhn {
+    // What player 0 wins.
+    type Outcome = f64;
+    type Reward = f64;
+
+    fn outcome(&self) -> Option<f64> {
+        self.payoff()
+    }
+    fn reward(payoff: &f64, player: &usize) -> f64 {
+        if *player == 0 { *payoff } else { -payoff }
+    }
+}
+
+/// What the player to move knows: its own card and the actions so far.
+struct Knowledge {
+    player: usize,
+    card: usize,
+    history: Vec<Action>,
+}
+
+impl InformationSet<KuhnMCTS> for Knowledge {
+    fn sample(&self, rng: &mut XorShiftRng) -> Kuhn {
+        let others: Vec<usize> = (0..3).filter(|&card| card != self.card).collect();
+        let mut cards = [self.card; 2];
+        cards[1 - self.player] = *rng.choose(&others).unwrap();
+        Kuhn {cards, history: self.history.clone()}
+    }
+    fn is_available(&self, _: &Kuhn, _: &Action) -> bool {
+        // Both actions are always available, whatever the cards.
+        true
+    }
+}
+
+struct KuhnMCTS {
+    knowledge: Knowledge,
+}
+
+impl MCTS for KuhnMCTS {
+    type State = Kuhn;
+    type Reward = f64;
+    type Eval = RolloutEvaluator<Self, UniformRollout>;
+    type NodeData = ();
+    type ExtraThreadData = ();
+    type TreePolicy = UCTPolicy;
+    type TranspositionTable = ();
+    type PlayoutData = ();
+
+    fn information_set(&self) -> Option<&dyn InformationSet<Self>> {
+        Some(&self.knowledge)
+    }
+}
+
+/// The fraction of the search's playouts that bet.
+fn bet_frequency(card: usize, history: Vec<Action>) -> f64 {
+    let player = history.len() % 2;
+    let knowledge = Knowledge {player, card, history};
+    let root = knowledge.sample(&mut rand::weak_rng());
+    let mut mcts = MCTSManager::new(root, KuhnMCTS {knowledge}, RolloutEvaluator::new(UniformRollout),
+        UCTPolicy::new(1.0), ());
+    mcts.playout_n(10000);
+    let moves: Vec<_> = mcts.tree().root_node().moves().collect();
+    moves[1].visits() as f64 / (moves[0].visits() + moves[1].visits()) as f64
+}
+
+fn main() {
+    use Action::*;
+    let situations = [
+        ("first to act", vec![]),
+        ("after a pass", vec![Pass]),
+        ("after a bet", vec![Bet]),
+        ("after pass, bet", vec![Pass, Bet]),
+    ];
+    for &(name, ref history) in &situations {
+        let frequencies: Vec<String> = (0..3)
+            .map(|card| format!("{} {:.2}", CARDS[card], bet_frequency(card, history.clone())))
+            .collect();
+        println!("Bets {:16}: {}", name, frequencies.join(", "));
+    }
+}
@@ -0,0 +1,123 @@
+// Pig is a dice game. On your turn you keep rolling a die and adding it to your turn total until
+// you either hold, banking the total, or roll a 1 and lose it. The first to bank 20 points wins.
+// The die rolls are chance nodes. This prints whether the search rolls or holds at the start of
+// the game for different turn totals. Perfect play rolls in all of them, with an expected reward
+// for rolling of 0.25 at a turn total of 1 up to 0.69 at 15.
+
+extern crate mcts;
+
+use mcts::*;
+use mcts::rollout::*;
+use mcts::tree_policy::*;
+
+const TARGET: u32 = 20;
+
+#[derive(Clone, Debug)]
+struct Pig {
+    scores: [u32; 2],
+    turn_total: u32,
+    to_move: usize,
+    // Waiting for the die.
+    rolling: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Move {
+    Roll, Hold, Die(u32)
+}
+
+impl Pig {
+    fn winner(&self) -> Option<usize> {
+        (0..2).find(|&player| self.scores[player] >= TARGET)
+    }
+
+    fn end_turn(&mut self) {
+        self.turn_total = 0;
+        self.to_move = 1 - self.to_move;
+    }
+}
+
+impl GameState for Pig {
+    type Move = Move;
+    type Player = usize;
+    type MoveList = Vec<Move>;
+
+    fn current_player(&self) -> usize {
+        self.to_move
+    }
+    fn available_moves(&self) -> Vec<Move> {
+        if self.winner().is_some() {
+            vec![]
+        } else if self.rolling {
+            (1..7).map(Move::Die).collect()
+        } else if self.turn_total == 0 {
+            vec![Move::Roll]
+        } else {
+            vec![Move::Roll, Move::Hold]
+        }
+    }
+    fn make_move(&mut self, mov: &Move) {
+        match *mov {
+            Move::Roll => self.rolling = true,
+            Move::Hold => {
+                self.scores[self.to_move] += self.turn_total;
+                self.end_turn();
+            }
+            Move::Die(1) => {
+                self.rolling = false;
+                self.end_turn();
+            }
+            Move::Die(n) => {
+                self.rolling = false;
+                self.turn_total += n;
+            }
+        }
+    }
+    fn chance_probabilities(&self) -> Option<Vec<f64>> {
+        if self.rolling {
+            Some(vec![1.0 / 6.0; 6])
+        } else {
+            None
+        }
+    }
+}
+
+impl RolloutState for Pig {
+    // The winner.
+    type Outcome = usize;
+    type Reward = i64;
+
+    fn outcome(&self) -> Option<usize> {
+        self.winner()
+    }
+    fn reward(winner: &usize, player: &usize) -> i64 {
+        if winner == player { 1 } else { -1 }
+    }
+}
+
+#[derive(Default)]
+struct MyMCTS;
+
+impl MCTS for MyMCTS {
+    type State = Pig;
+    type Reward = i64;
+    type Eval = RolloutEvaluator<Self, UniformRollout>;
+    type NodeData = ();
+    type ExtraThreadData = ();
+    type TreePolicy = UCTPolicy;
+    type TranspositionTable = ();
+    type PlayoutData = ();
+}
+
+fn main() {
+    for turn_total in 1..16 {
+        let state = Pig {scores: [0, 0], turn_total, to_move: 0, rolling: false};
+        let mut mcts = MCTSManager::new(state, MyMCTS, RolloutEvaluator::new(UniformRollout),
+            UCTPolicy::new(1.0), ());
+        mcts.playout_n(20000);
+        let moves: Vec<_> = mcts.tree().root_node().moves()
+            .map(|mov| format!("{:?} {:.3}", mov.get_move(), mov.average_reward().unwrap_or(0.0)))
+            .collect();
+        println!("Turn total {:2}: {:?} ({})", turn_total, mcts.best_move().unwrap(), moves.join(", "));
+    }
+}
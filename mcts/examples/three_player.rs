@@ -0,0 +1,136 @@
+// A three-player game: there is a row of coins, and in turn each player takes the coin at either
+// end of the row. Each player wants as much money as possible for itself. The search plays every
+// player, and each of its moves is checked against an exhaustive max^n search.
+
+extern crate mcts;
+
+use mcts::*;
+use mcts::multiplayer::*;
+use mcts::rollout::*;
+use mcts::tree_policy::*;
+
+const PLAYERS: usize = 3;
+const COINS: [u32; 11] = [4, 9, 1, 7, 2, 8, 3, 6, 5, 2, 9];
+
+#[derive(Clone, Debug)]
+struct CoinRow {
+    // The coins that are left are `COINS[left..right]`.
+    left: usize,
+    right: usize,
+    scores: [u32; PLAYERS],
+    to_move: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Move {
+    Left, Right
+}
+
+impl CoinRow {
+    fn new() -> Self {
+        Self {left: 0, right: COINS.len(), scores: [0; PLAYERS], to_move: 0}
+    }
+
+    /// The final scores if every player moves to maximize its own score.
+    fn max_n(&self) -> ([u32; PLAYERS], Option<Move>) {
+        if self.left == self.right {
+            return (self.scores, None);
+        }
+        [Move::Left, Move::Right].iter()
+            .map(|&mov| {
+                let mut next = self.clone();
+                next.make_move(&mov);
+                (next.max_n().0, Some(mov))
+            })
+            .max_by_key(|&(scores, _)| scores[self.to_move])
+            .unwrap()
+    }
+}
+
+impl GameState for CoinRow {
+    type Move = Move;
+    type Player = usize;
+    type MoveList = Vec<Move>;
+
+    fn current_player(&self) -> usize {
+        self.to_move
+    }
+    fn available_moves(&self) -> Vec<Move> {
+        match self.right - self.left {
+            0 => vec![],
+            1 => vec![Move::Left],
+            _ => vec![Move::Left, Move::Right],
+        }
+    }
+    fn make_move(&mut self, mov: &Move) {
+        let coin = match *mov {
+            Move::Left => {
+                self.left += 1;
+                COINS[self.left - 1]
+            }
+            Move::Right => {
+                self.right -= 1;
+                COINS[self.right]
+            }
+        };
+        self.scores[self.to_move] += coin;
+        self.to_move = (self.to_move + 1) % PLAYERS;
+    }
+}
+
+impl RolloutState for CoinRow {
+    // Each player's share of the money.
+    type Outcome = RewardVector<f64>;
+    type Reward = f64;
+
+    fn outcome(&self) -> Option<RewardVector<f64>> {
+        if self.left < self.right {
+            return None;
+        }
+        let total: u32 = COINS.iter().sum();
+        let shares: Vec<f64> = self.scores.iter().map(|&score| score as f64 / total as f64).collect();
+        Some(RewardVector::new(&shares))
+    }
+    fn reward(shares: &RewardVector<f64>, player: &usize) -> f64 {
+        shares.reward_for(player)
+    }
+}
+
+#[derive(Default)]
+struct MyMCTS;
+
+impl MCTS for MyMCTS {
+    type State = CoinRow;
+    type Reward = f64;
+    type Eval = RolloutEvaluator<Self, UniformRollout>;
+    type NodeData = ();
+    type ExtraThreadData = ();
+    type TreePolicy = UCTPolicy;
+    type TranspositionTable = ();
+    type PlayoutData = ();
+}
+
+fn main() {
+    let mut state = CoinRow::new();
+    let mut agreed = 0;
+    let mut moves = 0;
+    while state.outcome().is_none() {
+        let mut mcts = MCTSManager::new(state.clone(), MyMCTS, RolloutEvaluator::new(UniformRollout),
+            UCTPolicy::new(0.2), ());
+        mcts.playout_n(20000);
+        let mov = mcts.best_move().unwrap();
+        let (best_scores, best_move) = state.max_n();
+        let agrees = Some(mov) == best_move || {
+            // Both ends may be equally good.
+            let mut next = state.clone();
+            next.make_move(&mov);
+            next.max_n().0[state.to_move] == best_scores[state.to_move]
+        };
+        println!("Player {} takes {:?}{}", state.to_move, mov, if agrees { "" } else { " (not the max^n move)" });
+        agreed += agrees as usize;
+        moves += 1;
+        state.make_move(&mov);
+    }
+    println!("Final scores {:?}, max^n scores {:?}", state.scores, CoinRow::new().max_n().0);
+    println!("{} of {} moves agreed with max^n", agreed, moves);
+}
@@ -0,0 +1,119 @@
+// Plays a game of tic-tac-toe against itself, evaluating positions with uniformly random rollouts.
+// Perfect play is a draw, and so is this game.
+
+extern crate mcts;
+
+use mcts::*;
+use mcts::rollout::*;
+use mcts::tree_policy::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mark {
+    X, O
+}
+
+#[derive(Clone)]
+struct TicTacToe {
+    cells: [Option<Mark>; 9],
+    to_move: Mark,
+}
+
+impl TicTacToe {
+    fn new() -> Self {
+        Self {cells: [None; 9], to_move: Mark::X}
+    }
+
+    fn winner(&self) -> Option<Mark> {
+        const LINES: [[usize; 3]; 8] = [
+            [0, 1, 2], [3, 4, 5], [6, 7, 8],
+            [0, 3, 6], [1, 4, 7], [2, 5, 8],
+            [0, 4, 8], [2, 4, 6]];
+        LINES.iter()
+            .find(|line| self.cells[line[0]].is_some()
+                && self.cells[line[0]] == self.cells[line[1]]
+                && self.cells[line[1]] == self.cells[line[2]])
+            .and_then(|line| self.cells[line[0]])
+    }
+
+    fn print(&self) {
+        for row in self.cells.chunks(3) {
+            let row: String = row.iter().map(|cell| match *cell {
+                Some(Mark::X) => 'X',
+                Some(Mark::O) => 'O',
+                None => '.',
+            }).collect();
+            println!("{}", row);
+        }
+        println!();
+    }
+}
+
+impl GameState for TicTacToe {
+    type Move = usize;
+    type Player = Mark;
+    type MoveList = Vec<usize>;
+
+    fn current_player(&self) -> Mark {
+        self.to_move
+    }
+    fn available_moves(&self) -> Vec<usize> {
+        if self.winner().is_some() {
+            return vec![];
+        }
+        (0..9).filter(|&i| self.cells[i].is_none()).collect()
+    }
+    fn make_move(&mut self, mov: &usize) {
+        self.cells[*mov] = Some(self.to_move);
+        self.to_move = if self.to_move == Mark::X { Mark::O } else { Mark::X };
+    }
+}
+
+impl RolloutState for TicTacToe {
+    // The winner, or `None` for a draw.
+    type Outcome = Option<Mark>;
+    type Reward = i64;
+
+    fn outcome(&self) -> Option<Option<Mark>> {
+        match self.winner() {
+            Some(mark) => Some(Some(mark)),
+            None if self.cells.iter().all(|cell| cell.is_some()) => Some(None),
+            None => None,
+        }
+    }
+    fn reward(winner: &Option<Mark>, player: &Mark) -> i64 {
+        match *winner {
+            Some(mark) if mark == *player => 1,
+            Some(_) => -1,
+            None => 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct MyMCTS;
+
+impl MCTS for MyMCTS {
+    type State = TicTacToe;
+    type Reward = i64;
+    type Eval = RolloutEvaluator<Self, UniformRollout>;
+    type NodeData = ();
+    type ExtraThreadData = ();
+    type TreePolicy = UCTPolicy;
+    type TranspositionTable = ();
+    type PlayoutData = ();
+}
+
+fn main() {
+    let mut state = TicTacToe::new();
+    while state.outcome().is_none() {
+        let mut mcts = MCTSManager::new(state.clone(), MyMCTS,
+            RolloutEvaluator::new(UniformRollout), UCTPolicy::new(1.0), ());
+        mcts.playout_n(10000);
+        state.make_move(&mcts.best_move().unwrap());
+        state.print();
+    }
+    match state.outcome().unwrap() {
+        Some(mark) => println!("{:?} wins", mark),
+        None => println!("Draw"),
+    }
+}
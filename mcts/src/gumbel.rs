@@ -0,0 +1,256 @@
+//! Gumbel root search (Danihelka et al. 2022, "Policy improvement by planning with Gumbel").
+//!
+//! With a small playout budget, PUCT at the root spends visits on moves that can't become the best
+//! move before the search ends. Instead, `GumbelRoot` samples `k` root moves without replacement
+//! by adding Gumbel noise to their policy logits and keeping the top `k` (the Gumbel-top-k trick),
+//! then splits the budget between them by sequential halving: in each of about log2(k) phases the
+//! remaining moves get the same number of visits, and then the worse half is dropped. Moves are
+//! scored by their Gumbel noise plus their logit plus σ(Q), where
+//! σ(Q) = (`C_VISIT` + the most visits of a root move) * `C_SCALE` * Q and Q is the move's mean reward,
+//! rescaled to [0, 1] over the considered moves. Below the root the tree policy is used as usual.
+//!
+//! If `MCTS::gumbel_root` returns `Some`, the root is searched this way, and `best_move` returns
+//! the highest-scoring of the moves that were visited the most.
+
+extern crate rand;
+use self::rand::{Open01, Rng};
+
+use super::*;
+use tree_policy::PolicyRng;
+use std::cmp::max;
+
+/// The number of root moves considered unless `with_considered_moves` says otherwise.
+pub const DEFAULT_CONSIDERED_MOVES: usize = 16;
+pub const C_VISIT: f64 = 50.0;
+pub const C_SCALE: f64 = 1.0;
+
+#[derive(Clone, Debug)]
+pub struct GumbelRoot {
+    // The Gumbel noise plus the logit of each root move.
+    perturbed_logits: Vec<f64>,
+    // The indices of the considered moves, best first.
+    considered: Vec<usize>,
+    budget: u64,
+}
+
+impl GumbelRoot {
+    /// `logits` are the policy logits of the root moves, in the order of `available_moves()` of the
+    /// root state (any constant can be added to them, so the logarithms of the move probabilities
+    /// work too). `budget` is the number of playouts that the search is planned for; playouts beyond
+    /// it keep visiting the last two moves in turn. The Gumbel noise is sampled with `seed`.
+    pub fn new(logits: &[f32], budget: u64, seed: u64) -> Self {
+        let mut rng = PolicyRng::with_seed(seed).rng;
+        let perturbed_logits = logits.iter()
+            .map(|&logit| {
+                let Open01(u) = rng.gen::<Open01<f64>>();
+                logit as f64 - (-u.ln()).ln()
+            })
+            .collect();
+        let mut result = Self {perturbed_logits, considered: Vec::new(), budget};
+        result.consider(DEFAULT_CONSIDERED_MOVES);
+        result
+    }
+
+    /// Considers the top `k` moves instead of `DEFAULT_CONSIDERED_MOVES`.
+    pub fn with_considered_moves(mut self, k: usize) -> Self {
+        assert!(k != 0);
+        self.consider(k);
+        self
+    }
+
+    fn consider(&mut self, k: usize) {
+        let perturbed_logits = &self.perturbed_logits;
+        let mut indices: Vec<usize> = (0..perturbed_logits.len()).collect();
+        indices.sort_by(|&a, &b| perturbed_logits[b].partial_cmp(&perturbed_logits[a]).unwrap());
+        indices.truncate(k);
+        self.considered = indices;
+    }
+
+    /// The indices of the root moves that are considered, in decreasing order of logit plus Gumbel noise.
+    pub fn considered_moves(&self) -> &[usize] {
+        &self.considered
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// The move for the next playout: of the considered moves with at least as many visits as
+    /// sequential halving has planned for this playout, the one with the fewest, which is one with
+    /// exactly that many unless other threads got there first. Ties go to the highest score.
+    pub(crate) fn choose_child<'a, Spec: MCTS>(&self, moves: Moves<'a, Spec>) -> MoveInfoHandle<'a, Spec> {
+        let moves: Vec<_> = moves.collect();
+        let playouts = moves.iter().map(|mov| mov.visits()).sum();
+        let target = target_visits(self.considered.len() as u64, self.budget, playouts);
+        let scores = self.scores(&moves);
+        let best = self.considered.iter()
+            .zip(&scores)
+            .filter(|&(&i, _)| moves[i].visits() >= target)
+            .max_by(|&(&a, x), &(&b, y)| {
+                moves[b].visits().cmp(&moves[a].visits()).then(x.partial_cmp(y).unwrap())
+            })
+            .map(|(&i, _)| i);
+        match best {
+            Some(i) => moves[i],
+            None => moves[self.highest_scoring(&scores)],
+        }
+    }
+
+    /// The highest-scoring of the considered moves that were visited the most.
+    pub(crate) fn select_child_after_search<'a, Spec: MCTS>(&self, moves: &[MoveInfoHandle<'a, Spec>])
+        -> MoveInfoHandle<'a, Spec>
+    {
+        let scores = self.scores(moves);
+        let most_visits = self.considered.iter().map(|&i| moves[i].visits()).max().unwrap();
+        let best = self.considered.iter()
+            .zip(&scores)
+            .filter(|&(&i, _)| moves[i].visits() == most_visits)
+            .max_by(|&(_, x), &(_, y)| x.partial_cmp(y).unwrap())
+            .map(|(&i, _)| i)
+            .unwrap();
+        moves[best]
+    }
+
+    fn highest_scoring(&self, scores: &[f64]) -> usize {
+        let best = (0..scores.len()).max_by(|&a, &b| scores[a].partial_cmp(&scores[b]).unwrap()).unwrap();
+        self.considered[best]
+    }
+
+    /// The score of each considered move, in the order of `considered`.
+    fn scores<Spec: MCTS>(&self, moves: &[MoveInfoHandle<Spec>]) -> Vec<f64> {
+        assert_eq!(moves.len(), self.perturbed_logits.len(),
+            "the Gumbel root search was given {} logits for {} root moves", self.perturbed_logits.len(), moves.len());
+        // Unvisited moves get the mean reward of the visited ones.
+        let total_visits: u64 = moves.iter().map(|mov| mov.visits()).sum();
+        let total_rewards: f64 = moves.iter().map(|mov| mov.sum_rewards().to_f64()).sum();
+        let mean = if total_visits == 0 { 0.0 } else { total_rewards / total_visits as f64 };
+        let q: Vec<f64> = self.considered.iter()
+            .map(|&i| match moves[i].visits() {
+                0 => mean,
+                visits => moves[i].sum_rewards().to_f64() / visits as f64,
+            })
+            .collect();
+        let min = q.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = q.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let range = (max - min).max(1e-9);
+        let most_visits = moves.iter().map(|mov| mov.visits()).max().unwrap_or(0);
+        let sigma_scale = (C_VISIT + most_visits as f64) * C_SCALE;
+        self.considered.iter()
+            .zip(q)
+            .map(|(&i, q)| self.perturbed_logits[i] + sigma_scale * (q - min) / range)
+            .collect()
+    }
+}
+
+/// The number of visits that the move chosen by playout `t` (counting from 0) has before it,
+/// when `budget` playouts are split between `k` moves by sequential halving.
+fn target_visits(k: u64, budget: u64, t: u64) -> u64 {
+    if k <= 1 {
+        return t;
+    }
+    let num_phases = 64 - (k - 1).leading_zeros() as u64;
+    let mut num_considered = k;
+    let mut start = 0;
+    let mut visits = 0;
+    loop {
+        if num_considered == 2 {
+            // The phases with two moves left just alternate between them.
+            return visits + (t - start) / 2;
+        }
+        let extra_visits = max(1, budget / (num_phases * num_considered));
+        let end = start + extra_visits * num_considered;
+        if t < end {
+            return visits + (t - start) / num_considered;
+        }
+        start = end;
+        visits += extra_visits;
+        num_considered = max(2, num_considered / 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_policy::UCTPolicy;
+
+    const ARMS: [f64; 8] = [0.3, 0.5, 0.55, 0.6, 0.4, 0.45, 0.35, 0.2];
+
+    #[derive(Clone)]
+    struct Bandit(Option<usize>);
+
+    impl GameState for Bandit {
+        type Move = usize;
+        type Player = ();
+        type MoveList = Vec<usize>;
+
+        fn current_player(&self) -> Self::Player {}
+        fn available_moves(&self) -> Vec<usize> {
+            match self.0 {
+                None => (0..ARMS.len()).collect(),
+                Some(_) => vec![],
+            }
+        }
+        fn make_move(&mut self, mov: &usize) {
+            self.0 = Some(*mov);
+        }
+    }
+
+    struct BanditEvaluator;
+
+    impl Evaluator<GumbelMCTS> for BanditEvaluator {
+        type StateEvaluation = f64;
+
+        fn evaluate_new_state(&self, state: &Bandit, moves: &Vec<usize>, _: Option<SearchHandle<GumbelMCTS>>)
+            -> (Vec<()>, f64) {
+            (vec![(); moves.len()], state.0.map(|arm| ARMS[arm]).unwrap_or(0.0))
+        }
+        fn evaluate_existing_state(&self, _: &Bandit, evaln: &f64, _: SearchHandle<GumbelMCTS>) -> f64 {
+            *evaln
+        }
+        fn interpret_evaluation_for_player(&self, evaln: &f64, _: &()) -> f64 {
+            *evaln
+        }
+    }
+
+    struct GumbelMCTS(GumbelRoot);
+
+    impl MCTS for GumbelMCTS {
+        type State = Bandit;
+        type Reward = f64;
+        type Eval = BanditEvaluator;
+        type NodeData = ();
+        type ExtraThreadData = ();
+        type TreePolicy = UCTPolicy;
+        type TranspositionTable = ();
+        type PlayoutData = ();
+
+        fn gumbel_root(&self) -> Option<&GumbelRoot> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn sequential_halving_schedule() {
+        // 4 moves and 16 playouts: 2 phases, first 2 visits for each of the 4, then 4 more for each of 2.
+        let schedule: Vec<_> = (0..16).map(|t| target_visits(4, 16, t)).collect();
+        assert_eq!(schedule, vec![0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5]);
+        assert_eq!(target_visits(1, 16, 7), 7);
+    }
+
+    #[test]
+    fn gumbel_root_halves_the_considered_moves() {
+        let gumbel = GumbelRoot::new(&[0.0; 8], 64, 3).with_considered_moves(4);
+        let considered = gumbel.considered_moves().to_vec();
+        let mut mcts = MCTSManager::new(Bandit(None), GumbelMCTS(gumbel), BanditEvaluator, UCTPolicy::new(1.0), ());
+        mcts.playout_n(64);
+        let mut visits: Vec<_> = mcts.tree().root_node().moves().map(|mov| mov.visits()).collect();
+        for (arm, &v) in visits.iter().enumerate() {
+            assert_eq!(v == 0, !considered.contains(&arm));
+        }
+        visits.sort();
+        // 8 visits for each of the 4 moves, then 16 more for each of the better 2.
+        assert_eq!(visits, vec![0, 0, 0, 0, 8, 8, 24, 24]);
+        let best = *considered.iter().max_by(|&&a, &&b| ARMS[a].partial_cmp(&ARMS[b]).unwrap()).unwrap();
+        assert_eq!(mcts.best_move(), Some(best));
+    }
+}
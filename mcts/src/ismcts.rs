@@ -0,0 +1,153 @@
+//! Information-set MCTS, for games with hidden information.
+//!
+//! The searching player doesn't know the true state, only an information set of states it could be in.
+//! If `MCTS::information_set` returns `Some`, every playout starts from a state sampled from it
+//! (a determinization) instead of from the root state. Nodes are identified by the moves that lead to
+//! them, so a node can be reached in many determinizations; it is created with every move that is
+//! possible in any of them, and a playout only considers the moves available in its own
+//! determinization. Each edge counts how many times it was available (its availability), and
+//! `UCTPolicy` uses that count in place of the parent's visit count.
+//!
+//! The evaluator is called on determinized states, so `evaluate_existing_state` should evaluate
+//! the state it is given rather than reuse the old evaluation.
+//!
+//! Unlike the published algorithm, nodes aren't keyed by information set: the tree has a node for
+//! every sequence of moves, and a transposition table is looked up with the determinized state,
+//! through its `TranspositionHash`. For two move sequences to share a node, the hash of a state
+//! must only depend on what the searching player knows, so that every determinization of an
+//! information set has the same hash. When the tree is compacted (see `MCTS::memory_limit`), the
+//! nodes that are kept aren't put back in the table, because the moves leading to them may not be
+//! playable in the root state; transpositions to them are lost until they are created again.
+
+extern crate rand;
+use self::rand::XorShiftRng;
+
+use super::*;
+
+pub trait InformationSet<Spec: MCTS>: Sync {
+    /// Samples a state that is consistent with what the searching player knows.
+    fn sample(&self, rng: &mut XorShiftRng) -> Spec::State;
+    /// Whether `mov`, one of the moves of a node, can be played in `state`.
+    fn is_available(&self, state: &Spec::State, mov: &Move<Spec>) -> bool;
+    /// The moves of a new node for `state`: every move that could be available in a position that
+    /// looks the same to the searching player. Defaults to `state.available_moves()`, which is right
+    /// if the moves don't depend on the hidden information. At most `MAX_MOVES`.
+    fn possible_moves(&self, state: &Spec::State) -> MoveList<Spec> {
+        state.available_moves()
+    }
+}
+
+/// The most moves a node can have in an information-set search.
+pub const MAX_MOVES: usize = 256;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::rand::Rng;
+    use tree_policy::UCTPolicy;
+
+    /// The player chooses a number from 0 to 2, except for one hidden number that it doesn't know.
+    /// Choosing 0 is worth 1, and the others are worth 0.5.
+    #[derive(Clone, Debug)]
+    struct Hidden {
+        hidden: usize,
+        chosen: Option<usize>,
+    }
+
+    impl GameState for Hidden {
+        type Move = usize;
+        type Player = ();
+        type MoveList = Vec<usize>;
+
+        fn current_player(&self) {}
+        fn available_moves(&self) -> Vec<usize> {
+            match self.chosen {
+                Some(_) => vec![],
+                None => (0..3).filter(|&x| x != self.hidden).collect(),
+            }
+        }
+        fn make_move(&mut self, mov: &usize) {
+            assert!(*mov != self.hidden, "chose the hidden number");
+            self.chosen = Some(*mov);
+        }
+    }
+
+    struct HiddenNumber;
+
+    impl InformationSet<HiddenMCTS> for HiddenNumber {
+        fn sample(&self, rng: &mut XorShiftRng) -> Hidden {
+            Hidden {hidden: rng.gen_range(0, 3), chosen: None}
+        }
+        fn is_available(&self, state: &Hidden, mov: &usize) -> bool {
+            *mov != state.hidden
+        }
+        fn possible_moves(&self, state: &Hidden) -> Vec<usize> {
+            match state.chosen {
+                Some(_) => vec![],
+                None => vec![0, 1, 2],
+            }
+        }
+    }
+
+    struct ChoiceEvaluator;
+
+    impl ChoiceEvaluator {
+        fn value(state: &Hidden) -> f64 {
+            match state.chosen {
+                Some(0) => 1.0,
+                Some(_) => 0.5,
+                None => 0.0,
+            }
+        }
+    }
+
+    impl Evaluator<HiddenMCTS> for ChoiceEvaluator {
+        type StateEvaluation = f64;
+
+        fn evaluate_new_state(&self, state: &Hidden, moves: &Vec<usize>, _: Option<SearchHandle<HiddenMCTS>>)
+            -> (Vec<()>, f64) {
+            (vec![(); moves.len()], Self::value(state))
+        }
+        fn interpret_evaluation_for_player(&self, evaln: &f64, _: &()) -> f64 {
+            *evaln
+        }
+        fn evaluate_existing_state(&self, state: &Hidden, _: &f64, _: SearchHandle<HiddenMCTS>) -> f64 {
+            Self::value(state)
+        }
+    }
+
+    struct HiddenMCTS;
+
+    impl MCTS for HiddenMCTS {
+        type State = Hidden;
+        type Reward = f64;
+        type Eval = ChoiceEvaluator;
+        type NodeData = ();
+        type ExtraThreadData = ();
+        type TreePolicy = UCTPolicy;
+        type TranspositionTable = ();
+        type PlayoutData = ();
+
+        fn information_set(&self) -> Option<&dyn InformationSet<Self>> {
+            Some(&HiddenNumber)
+        }
+    }
+
+    #[test]
+    fn playouts_only_choose_available_moves() {
+        // The root state is just one determinization. Each number is available in two thirds of them.
+        let root = Hidden {hidden: 0, chosen: None};
+        let mut mcts = MCTSManager::new(root, HiddenMCTS, ChoiceEvaluator, UCTPolicy::new(0.5), ());
+        mcts.playout_n(3000);
+        let moves: Vec<_> = mcts.tree().root_node().moves().collect();
+        assert_eq!(moves.len(), 3);
+        for mov in &moves {
+            let availability = mov.availability().unwrap();
+            assert!(mov.visits() <= availability);
+            assert!(availability > 1800 && availability < 2200, "{} was available {} times", mov.get_move(), availability);
+        }
+        // 0 is chosen whenever it's available.
+        assert!(moves[0].visits() as f64 > 0.9 * moves[0].availability().unwrap() as f64);
+        assert_eq!(mcts.best_move(), Some(0));
+    }
+}
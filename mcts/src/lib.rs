@@ -1,5 +1,3 @@
-#![feature(integer_atomics)]
-
 //! This is a library for Monte Carlo tree search.
 //!
 //! It is still under development and the documentation isn't good. However, the following example may be helpful:
@@ -76,6 +74,7 @@
 //!
 //! impl MCTS for MyMCTS {
 //!     type State = CountingGame;
+//!     type Reward = i64;
 //!     type Eval = MyEvaluator;
 //!     type NodeData = ();
 //!     type ExtraThreadData = ();
@@ -106,17 +105,23 @@
 //!         CountingGame(5)]);
 //! ```
 
-extern crate crossbeam;
 extern crate smallvec;
 extern crate arena;
 extern crate pod;
 
 mod search_tree;
 mod atomics;
+pub mod reward;
+pub mod gumbel;
+pub mod ismcts;
+pub mod multiplayer;
+pub mod rave;
+pub mod rollout;
 pub mod tree_policy;
 pub mod transposition_table;
 
 pub use search_tree::*;
+pub use reward::Reward;
 use tree_policy::*;
 use transposition_table::*;
 use arena::ArenaAllocator;
@@ -124,10 +129,12 @@ use arena::ArenaAllocator;
 use atomics::*;
 use std::sync::Arc;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub trait MCTS: Sized + Sync {
     type State: GameState + Sync;
+    /// The type of the values backed up through the tree, e.g. `i64` or `f64`.
+    type Reward: Reward;
     type Eval: Evaluator<Self>;
     type TreePolicy: TreePolicy<Self>;
     type NodeData: Default + Sync + Send;
@@ -138,8 +145,8 @@ pub trait MCTS: Sized + Sync {
     /// Virtual loss subtracted from a node's evaluation when a search thread chooses it in a playout,
     /// then added back when the playout is complete.
     /// Used to reduce contention between threads. Defaults to 0.
-    fn virtual_loss(&self) -> i64 {
-        0
+    fn virtual_loss(&self) -> Self::Reward {
+        Self::Reward::zero()
     }
     /// The number of times a node must be visited before expanding its children.
     /// Defaults to 1.
@@ -147,18 +154,63 @@ pub trait MCTS: Sized + Sync {
     fn visits_before_expansion(&self) -> u64 {
         1
     }
-    /// Maximum number of nodes beyond which calling `playout` will do nothing. Defaults to `std::usize::MAX`.
+    /// Maximum number of nodes beyond which calling `playout` will do nothing. Defaults to `usize::MAX`.
     fn node_limit(&self) -> usize {
-        std::usize::MAX
+        usize::MAX
+    }
+    /// If `Some(n)`, the tree is compacted whenever it takes up more than `n` bytes, counting the
+    /// arena and the transposition table (see `SearchTree::memory_in_use`): the least-visited
+    /// subtrees are discarded, the rest is copied into a new arena, and the search carries on.
+    /// The transposition table must implement `clear`, which `SearchTree::new` checks.
+    /// This costs two atomic stores per playout.
+    /// Defaults to `None`.
+    fn memory_limit(&self) -> Option<usize> {
+        None
+    }
+    /// If true, the arena's 2 MB chunks are advised to use transparent huge pages,
+    /// which reduces TLB misses in large trees. Only has an effect on Linux. Defaults to false.
+    fn huge_pages(&self) -> bool {
+        false
     }
     /// Rule for selecting the best move once the search is over. Defaults to choosing the child with the most visits.
     fn select_child_after_search<'a>(&self, children: &[MoveInfoHandle<'a, Self>]) -> MoveInfoHandle<'a, Self> {
-        *children.into_iter().max_by_key(|child| child.visits()).unwrap()
+        *children.iter().max_by_key(|child| child.visits()).unwrap()
     }
     /// `playout` panics when this length is exceeded. Defaults to one million.
     fn max_playout_length(&self) -> usize {
         1_000_000
     }
+    /// If true, nodes shared through the transposition table are treated as a graph
+    /// (Monte Carlo Graph Search): each edge keeps its own visit count, and its reward is backed up
+    /// from the child node's current average instead of being copied from the child's totals.
+    /// Defaults to false.
+    fn graph_search(&self) -> bool {
+        false
+    }
+    /// If true, nodes and edges also accumulate the sum of the squares of their rewards.
+    /// This is turned on anyway if the tree policy needs it. Defaults to false.
+    fn track_sum_of_squares(&self) -> bool {
+        false
+    }
+    /// The record of the moves made in a playout, if `PlayoutData` keeps one. This is required
+    /// if the tree policy needs AMAF statistics (e.g. `RAVEPolicy`); use `rave::MoveHistory`
+    /// as the `PlayoutData` and return `Some(data)`. Defaults to `None`.
+    fn amaf_history<'a>(&self, _data: &'a mut Self::PlayoutData) -> Option<&'a mut dyn rave::AmafHistory<Self>> {
+        None
+    }
+    /// The information set to sample the state from at the start of every playout, for a game with
+    /// hidden information (see the `ismcts` module). Defaults to `None`, which searches from the root state.
+    fn information_set(&self) -> Option<&dyn ismcts::InformationSet<Self>> {
+        None
+    }
+    /// If `Some`, the root moves are chosen by a Gumbel root search with sequential halving
+    /// instead of the tree policy (see the `gumbel` module). Defaults to `None`.
+    fn gumbel_root(&self) -> Option<&gumbel::GumbelRoot> {
+        None
+    }
+    /// Called before AMAF statistics are updated, so that moves made while evaluating the leaf
+    /// (for example in a random rollout) can be recorded too. The default implementation does nothing.
+    fn record_evaluation_moves(&self, _evaln: &StateEvaluation<Self>, _history: &mut dyn rave::AmafHistory<Self>) {}
     /// Called when a playout is complete. The default implementation does nothing.
     fn on_backpropagation(&self, _evaln: &StateEvaluation<Self>, _handle: SearchHandle<Self>) {}
     fn cycle_behaviour(&self) -> CycleBehaviour<Self> {
@@ -169,9 +221,9 @@ pub trait MCTS: Sized + Sync {
         }
     }
     /// Called when a child node is selected in a playout. The default implementation does nothing.
-    fn on_choice_made<'a, 'b>(&self, _data: &mut Self::PlayoutData,
-                              _state: &Self::State, _moves: Moves<'a, Self>, _choice: MoveInfoHandle<'a, Self>,
-                              _handle: SearchHandle<'a, 'b, Self>) {}
+    fn on_choice_made<'a, 'b, 'c>(&self, _data: &mut Self::PlayoutData,
+                                  _state: &Self::State, _moves: Moves<'c, Self>, _choice: MoveInfoHandle<'c, Self>,
+                                  _handle: SearchHandle<'a, 'b, Self>) {}
     /// Called before the tree policy is run. If it returns `Some(x)`, the tree policy is ignored
     /// and `x` is used instead. The default implementation returns `None`.
     fn override_policy<'a>(&self, _data: &Self::PlayoutData,
@@ -182,7 +234,15 @@ pub trait MCTS: Sized + Sync {
 pub struct ThreadData<'a, Spec: MCTS> {
     pub policy_data: TreePolicyThreadData<Spec>,
     pub extra_data: Spec::ExtraThreadData,
+    // Samples the outcomes of chance nodes and the states of information sets.
+    chance_rng: PolicyRng,
+    // Only valid while `arena_generation` is the tree's. When the tree is compacted, the arena
+    // this allocates from is freed while `'a` still claims it, so the allocator must not be used
+    // before `SearchTree::playout` has compared the generations, with the playout flag raised,
+    // and fetched a new allocator if they differ.
     allocator: ArenaAllocator<'a>,
+    arena_generation: usize,
+    playout_flag: Arc<PlayoutFlag>,
 }
 
 impl<'a, Spec: MCTS> ThreadData<'a, Spec>
@@ -194,9 +254,20 @@ where
         Self {
             policy_data: Default::default(),
             extra_data: Default::default(),
-            allocator: tree.arena().allocator(),
+            chance_rng: PolicyRng::new(),
+            allocator: tree.allocator(),
+            arena_generation: tree.arena_generation(),
+            playout_flag: tree.register_playout_thread(),
         }
     }
+
+    /// Thread data whose random number generators are seeded with `seed`.
+    fn with_seed(tree: &'a SearchTree<Spec>, seed: u64) -> Self {
+        let mut tld = Self::create(tree);
+        tld.policy_data.seed(seed);
+        tld.chance_rng = PolicyRng::with_seed(!seed);
+        tld
+    }
 }
 
 pub type MoveEvaluation<Spec> = <<Spec as MCTS>::TreePolicy as TreePolicy<Spec>>::MoveEvaluation;
@@ -208,12 +279,20 @@ pub type TreePolicyThreadData<Spec> = <<Spec as MCTS>::TreePolicy as TreePolicy<
 
 pub trait GameState: Clone {
     type Move: Sync + Send + Clone;
-    type Player: Sync;
+    type Player: Sync + Clone;
     type MoveList: std::iter::IntoIterator<Item=Self::Move>;
 
     fn current_player(&self) -> Self::Player;
     fn available_moves(&self) -> Self::MoveList;
     fn make_move(&mut self, mov: &Self::Move);
+    /// If the next move is random rather than chosen by a player (a dice roll, say), the probability
+    /// of each of `available_moves()`, in the same order. The search samples these moves, and the
+    /// value of the position is the expectation over them. At such a chance node, `current_player()`
+    /// is ignored and the statistics are kept from the point of view of the player who moved last.
+    /// The default implementation returns `None`.
+    fn chance_probabilities(&self) -> Option<Vec<f64>> {
+        None
+    }
 }
 
 pub trait Evaluator<Spec: MCTS>: Sync {
@@ -230,13 +309,26 @@ pub trait Evaluator<Spec: MCTS>: Sync {
 
     fn interpret_evaluation_for_player(&self,
                                        evaluation: &Self::StateEvaluation,
-                                       player: &Player<Spec>) -> i64;
+                                       player: &Player<Spec>) -> Spec::Reward;
 }
 
 
 pub struct MCTSManager<Spec: MCTS> {
-    search_tree: SearchTree<Spec>,
+    search_tree: Arc<SearchTree<Spec>>,
     print_on_playout_error: bool,
+    seed: u64,
+    // The number of `ThreadData`s seeded so far, which each get their own seed.
+    threads_seeded: AtomicUsize,
+    deterministic: bool,
+    // The other trees of a root-parallel search. Empty unless `root_parallel` was called.
+    root_parallel_trees: Vec<RootParallelTree<Spec>>,
+}
+
+/// One of the independent trees of a root-parallel search, other than the manager's own tree.
+struct RootParallelTree<Spec: MCTS> {
+    tree: SearchTree<Spec>,
+    // What has already been added to the manager's tree.
+    merged: Vec<MergedEdge<Spec>>,
 }
 
 impl<Spec: MCTS> MCTSManager<Spec>
@@ -246,8 +338,15 @@ where
 {
     pub fn new(state: Spec::State, manager: Spec, eval: Spec::Eval, tree_policy: Spec::TreePolicy,
                table: Spec::TranspositionTable) -> Self {
-        let search_tree = SearchTree::new(state, manager, tree_policy, eval, table);
-        Self {search_tree, print_on_playout_error: true}
+        let search_tree = Arc::new(SearchTree::new(state, manager, tree_policy, eval, table));
+        Self {
+            search_tree,
+            print_on_playout_error: true,
+            seed: 0,
+            threads_seeded: 0.into(),
+            deterministic: false,
+            root_parallel_trees: Vec::new(),
+        }
     }
 
     pub fn print_on_playout_error(&mut self, v: bool) -> &mut Self {
@@ -255,23 +354,213 @@ where
         self
     }
 
+    /// Seeds the random choices of the search (ties in the tree policy, chance outcomes and
+    /// information-set samples). Each search thread gets its own seed, derived from this one and
+    /// the number of threads started before it since the last `reset`. The default seed is 0.
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// If true, every search runs in the calling thread, whatever number of threads it is asked for,
+    /// and the trees of a root-parallel search are searched one after the other. Then a search of
+    /// a given number of playouts builds the same tree every time it is run with the same seed.
+    /// (Searches that stop after some time still vary with the number of playouts that fit in it.)
+    pub fn deterministic(&mut self, v: bool) -> &mut Self {
+        self.deterministic = v;
+        self
+    }
+
+    fn thread_data<'a>(&self, tree: &'a SearchTree<Spec>) -> ThreadData<'a, Spec> {
+        ThreadData::with_seed(tree, self.next_thread_seed())
+    }
+    fn next_thread_seed(&self) -> u64 {
+        let index = self.threads_seeded.fetch_add(1, Ordering::SeqCst) as u64;
+        // Mix the seed so that nearby seeds don't share threads' streams.
+        self.seed.wrapping_mul(0x9E37_79B9_7F4A_7C15).rotate_left(32) ^ index
+    }
+    fn num_threads(&self, num_threads: usize) -> usize {
+        assert!(num_threads != 0);
+        if self.deterministic { 1 } else { num_threads }
+    }
+
+    /// Sets up root parallelization with `num_trees` trees in all, counting the manager's own tree.
+    /// Each tree is searched by its own thread with its own seed, so the threads don't contend
+    /// for the same nodes; the root statistics of the other trees are added to the manager's tree,
+    /// which is the one that `best_move` and the other queries look at. `make_tree` creates the
+    /// parts of each additional tree. The `*_root_parallel` methods then search this way, while
+    /// the other methods keep searching only the manager's tree.
+    pub fn root_parallel<F>(&mut self, num_trees: usize, mut make_tree: F) -> &mut Self
+        where F: FnMut() -> (Spec, Spec::Eval, Spec::TreePolicy, Spec::TranspositionTable)
+    {
+        assert!(num_trees != 0);
+        let state = self.search_tree.root_state().clone();
+        self.root_parallel_trees = (1..num_trees).map(|_| {
+            let (manager, eval, tree_policy, table) = make_tree();
+            RootParallelTree {
+                tree: SearchTree::new(state.clone(), manager, tree_policy, eval, table),
+                merged: Vec::new(),
+            }
+        }).collect();
+        self
+    }
+    /// The number of trees searched by the `*_root_parallel` methods, including the manager's own.
+    pub fn num_root_parallel_trees(&self) -> usize {
+        self.root_parallel_trees.len() + 1
+    }
     pub fn playout_until<Predicate: FnMut() -> bool>(&mut self, mut pred: Predicate) {
-        let mut tld = ThreadData::create(self.tree());
+        let mut tld = self.thread_data(self.tree());
         while !pred() {
             self.tree().playout(&mut tld);
         }
     }
     pub fn playout_n(&mut self, n: u64) {
-        let mut tld = ThreadData::create(self.tree());
+        let mut tld = self.thread_data(self.tree());
         for _ in 0..n {
             self.tree().playout(&mut tld);
         }
     }
-    unsafe fn spawn_worker_thread(&self, stop_signal: Arc<AtomicBool>) -> JoinHandle<()> {
-        let search_tree = &self.search_tree;
+    pub fn playout_n_parallel(&mut self, n: u32, num_threads: usize) {
+        if n == 0 {
+            return;
+        }
+        let num_threads = self.num_threads(num_threads);
+        let counter = AtomicIsize::new(n as isize);
+        let search_tree = &*self.search_tree;
+        let counter = &counter;
+        std::thread::scope(|scope| {
+            for _ in 0..num_threads {
+                let seed = self.next_thread_seed();
+                scope.spawn(move || {
+                    let mut tld = ThreadData::with_seed(search_tree, seed);
+                    loop {
+                        let count = counter.fetch_sub(1, Ordering::SeqCst);
+                        if count <= 0 {
+                            break;
+                        }
+                        search_tree.playout(&mut tld);
+                    }
+                });
+            }
+        });
+    }
+    /// Runs `n` playouts in all, split between the trees set up by `root_parallel`. If
+    /// `merge_interval` is `Some(k)`, each tree's root statistics are added to the manager's tree
+    /// after every `k` of its playouts, so that the manager's tree chooses its root moves with
+    /// them; otherwise they are only added at the end.
+    pub fn playout_n_root_parallel(&mut self, n: u32, merge_interval: Option<u32>) {
+        let num_trees = self.num_root_parallel_trees();
+        let counters: Vec<_> = (0..num_trees)
+            .map(|i| AtomicIsize::new(((n as usize + num_trees - 1 - i) / num_trees) as isize))
+            .collect();
+        self.search_root_parallel(merge_interval, |i| counters[i].fetch_sub(1, Ordering::SeqCst) > 0);
+    }
+    /// Like `playout_n_root_parallel`, but searches for `duration`.
+    pub fn playout_root_parallel_for(&mut self, duration: Duration, merge_interval: Option<u32>) {
+        let deadline = Instant::now() + duration;
+        self.search_root_parallel(merge_interval, |_| Instant::now() < deadline);
+    }
+    fn search_root_parallel<F>(&mut self, merge_interval: Option<u32>, keep_going: F)
+        where F: Fn(usize) -> bool + Sync
+    {
+        let seeds: Vec<_> = (0..self.num_root_parallel_trees()).map(|_| self.next_thread_seed()).collect();
+        let MCTSManager {ref search_tree, ref mut root_parallel_trees, deterministic, ..} = *self;
+        let main_tree = &**search_tree;
+        let keep_going = &keep_going;
+        let print_on_playout_error = self.print_on_playout_error;
+        let search = move |index: usize, tree: &SearchTree<Spec>, merged: Option<&mut Vec<MergedEdge<Spec>>>| {
+            let mut tld = ThreadData::with_seed(tree, seeds[index]);
+            let mut merged = merged;
+            let mut playouts = 0;
+            while keep_going(index) {
+                if !tree.playout(&mut tld) {
+                    if print_on_playout_error {
+                        eprintln!("Node limit of {} reached. Halting search.", tree.spec().node_limit());
+                    }
+                    break;
+                }
+                playouts += 1;
+                if let (Some(k), Some(ref mut merged)) = (merge_interval, merged.as_mut()) {
+                    if playouts % k == 0 {
+                        main_tree.merge_root_statistics(tree, merged, false);
+                    }
+                }
+            }
+            if let Some(merged) = merged {
+                main_tree.merge_root_statistics(tree, merged, true);
+            }
+        };
+        let search = &search;
+        if deterministic {
+            search(0, main_tree, None);
+            for (i, other) in root_parallel_trees.iter_mut().enumerate() {
+                search(i + 1, &other.tree, Some(&mut other.merged));
+            }
+            return;
+        }
+        std::thread::scope(|scope| {
+            scope.spawn(move || search(0, main_tree, None));
+            for (i, other) in root_parallel_trees.iter_mut().enumerate() {
+                let RootParallelTree {ref tree, ref mut merged} = *other;
+                scope.spawn(move || search(i + 1, tree, Some(merged)));
+            }
+        });
+    }
+    pub fn principal_variation_info(&self, num_moves: usize) -> Vec<MoveInfoHandle<'_, Spec>> {
+        self.search_tree.principal_variation(num_moves)
+    }
+    pub fn principal_variation(&self, num_moves: usize) -> Vec<Move<Spec>> {
+        self.search_tree.principal_variation(num_moves)
+            .into_iter()
+            .map(|x| x.get_move()).cloned()
+            .collect()
+    }
+    pub fn principal_variation_states(&self, num_moves: usize)
+                                      -> Vec<Spec::State> {
+        let moves = self.principal_variation(num_moves);
+        let mut states = vec![self.search_tree.root_state().clone()];
+        for mov in moves {
+            let mut state = states[states.len() - 1].clone();
+            state.make_move(&mov);
+            states.push(state);
+        }
+        states
+    }
+    pub fn tree(&self) -> &SearchTree<Spec> {&self.search_tree}
+    pub fn best_move(&self) -> Option<Move<Spec>> {
+        self.principal_variation(1).first().cloned()
+    }
+    pub fn reset(self) -> Self {
+        let MCTSManager {search_tree, print_on_playout_error, seed, deterministic, root_parallel_trees, ..} = self;
+        let search_tree = Arc::try_unwrap(search_tree).ok().expect("search threads are still running");
+        let root_parallel_trees = root_parallel_trees.into_iter()
+            .map(|x| RootParallelTree {tree: x.tree.reset(), merged: Vec::new()})
+            .collect();
+        Self {
+            search_tree: Arc::new(search_tree.reset()),
+            print_on_playout_error,
+            seed,
+            threads_seeded: 0.into(),
+            deterministic,
+            root_parallel_trees,
+        }
+    }
+}
+
+/// Searching in the background needs threads that outlive the call that starts them,
+/// so they share the tree through an `Arc`.
+impl<Spec: MCTS + 'static> MCTSManager<Spec>
+where
+    TreePolicyThreadData<Spec>: Default,
+    Spec::ExtraThreadData: Default,
+    SearchTree<Spec>: Send,
+{
+    fn spawn_worker_thread(&self, stop_signal: Arc<AtomicBool>) -> JoinHandle<()> {
+        let search_tree = self.search_tree.clone();
         let print_on_playout_error = self.print_on_playout_error;
-        crossbeam::spawn_unsafe(move || {
-            let mut tld = ThreadData::create(search_tree);
+        let seed = self.next_thread_seed();
+        std::thread::spawn(move || {
+            let mut tld = ThreadData::with_seed(&search_tree, seed);
             loop {
                 if stop_signal.load(Ordering::SeqCst) {
                     break;
@@ -287,13 +576,10 @@ where
         })
     }
     pub fn playout_parallel_async<'a>(&'a mut self, num_threads: usize) -> AsyncSearch<'a, Spec> {
-        assert!(num_threads != 0);
+        let num_threads = self.num_threads(num_threads);
         let stop_signal = Arc::new(AtomicBool::new(false));
         let threads = (0..num_threads).map(|_| {
-            let stop_signal = stop_signal.clone();
-            unsafe {
-                self.spawn_worker_thread(stop_signal)
-            }
+            self.spawn_worker_thread(stop_signal.clone())
         }).collect();
         AsyncSearch {
             manager: self,
@@ -302,17 +588,13 @@ where
         }
     }
     pub fn into_playout_parallel_async(self, num_threads: usize) -> AsyncSearchOwned<Spec> {
-        assert!(num_threads != 0);
-        let self_box = Box::new(self);
+        let num_threads = self.num_threads(num_threads);
         let stop_signal = Arc::new(AtomicBool::new(false));
         let threads = (0..num_threads).map(|_| {
-            let stop_signal = stop_signal.clone();
-            unsafe {
-                self_box.spawn_worker_thread(stop_signal)
-            }
+            self.spawn_worker_thread(stop_signal.clone())
         }).collect();
         AsyncSearchOwned {
-            manager: Some(self_box),
+            manager: Some(self),
             stop_signal,
             threads
         }
@@ -322,64 +604,13 @@ where
         std::thread::sleep(duration);
         search.halt();
     }
-    pub fn playout_n_parallel(&mut self, n: u32, num_threads: usize) {
-        if n == 0 {
-            return;
-        }
-        assert!(num_threads != 0);
-        let counter = AtomicIsize::new(n as isize);
-        let search_tree = &self.search_tree;
-        crossbeam::scope(|scope| {
-            for _ in 0..num_threads {
-                scope.spawn(|| {
-                    let mut tld = ThreadData::create(search_tree);
-                    loop {
-                        let count = counter.fetch_sub(1, Ordering::SeqCst);
-                        if count <= 0 {
-                            break;
-                        }
-                        search_tree.playout(&mut tld);
-                    }
-                });
-            }
-        });
-    }
-    pub fn principal_variation_info(&self, num_moves: usize) -> Vec<MoveInfoHandle<Spec>> {
-        self.search_tree.principal_variation(num_moves)
-    }
-    pub fn principal_variation(&self, num_moves: usize) -> Vec<Move<Spec>> {
-        self.search_tree.principal_variation(num_moves)
-            .into_iter()
-            .map(|x| x.get_move())
-            .map(|x| x.clone())
-            .collect()
-    }
-    pub fn principal_variation_states(&self, num_moves: usize)
-                                      -> Vec<Spec::State> {
-        let moves = self.principal_variation(num_moves);
-        let mut states = vec![self.search_tree.root_state().clone()];
-        for mov in moves {
-            let mut state = states[states.len() - 1].clone();
-            state.make_move(&mov);
-            states.push(state);
-        }
-        states
-    }
-    pub fn tree(&self) -> &SearchTree<Spec> {&self.search_tree}
-    pub fn best_move(&self) -> Option<Move<Spec>> {
-        self.principal_variation(1).get(0).map(|x| x.clone())
-    }
     pub fn perf_test<F>(&mut self, num_threads: usize, mut f: F) where F: FnMut(usize) {
         let search = self.playout_parallel_async(num_threads);
         for _ in 0..10 {
             let n1 = search.manager.search_tree.num_nodes();
             std::thread::sleep(Duration::from_secs(1));
             let n2 = search.manager.search_tree.num_nodes();
-            let diff = if n2 > n1 {
-                n2 - n1
-            } else {
-                0
-            };
+            let diff = n2.saturating_sub(n1);
             f(diff);
         }
     }
@@ -391,12 +622,6 @@ where
         });
         eprintln!("{} nodes total", thousands_separate(running_total));
     }
-    pub fn reset(self) -> Self {
-        Self {
-            search_tree: self.search_tree.reset(),
-            print_on_playout_error: self.print_on_playout_error,
-        }
-    }
 }
 
 // https://stackoverflow.com/questions/26998485/rust-print-format-number-with-thousand-separator
@@ -431,7 +656,7 @@ impl<'a, Spec: MCTS> Drop for AsyncSearch<'a, Spec> {
 
 #[must_use]
 pub struct AsyncSearchOwned<Spec: MCTS> {
-    manager: Option<Box<MCTSManager<Spec>>>,
+    manager: Option<MCTSManager<Spec>>,
     stop_signal: Arc<AtomicBool>,
     threads: Vec<JoinHandle<()>>,
 }
@@ -443,7 +668,7 @@ impl<Spec: MCTS> AsyncSearchOwned<Spec> {
     }
     pub fn halt(mut self) -> MCTSManager<Spec> {
         self.stop_threads();
-        *self.manager.take().unwrap()
+        self.manager.take().unwrap()
     }
     pub fn num_threads(&self) -> usize {
         self.threads.len()
@@ -460,7 +685,7 @@ impl<Spec: MCTS> From<MCTSManager<Spec>> for AsyncSearchOwned<Spec> {
     /// An `MCTSManager` is an `AsyncSearchOwned` with zero threads searching.
     fn from(m: MCTSManager<Spec>) -> Self {
         Self {
-            manager: Some(Box::new(m)),
+            manager: Some(m),
             stop_signal: Arc::new(AtomicBool::new(false)),
             threads: Vec::new(),
         }
@@ -0,0 +1,153 @@
+//! Games with more than two players.
+//!
+//! The search doesn't assume that there are two players whose rewards are each other's negation.
+//! Every edge accumulates the reward that `Evaluator::interpret_evaluation_for_player` gives the
+//! player who made the move, so each player maximizes its own reward. For a game with a fixed
+//! number of players, number them with `PlayerIndex`, use a `RewardVector` as the
+//! `StateEvaluation`, and implement `interpret_evaluation_for_player` with `RewardVector::reward_for`.
+
+use super::*;
+use smallvec::SmallVec;
+
+/// A player's position in the turn order, counting from 0.
+pub trait PlayerIndex {
+    fn index(&self) -> usize;
+}
+
+impl PlayerIndex for usize {
+    fn index(&self) -> usize {
+        *self
+    }
+}
+
+impl PlayerIndex for u8 {
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// A reward for each player.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RewardVector<R> {
+    rewards: SmallVec<[R; 4]>,
+}
+
+impl<R: Reward> RewardVector<R> {
+    pub fn new(rewards: &[R]) -> Self {
+        Self {rewards: rewards.iter().cloned().collect()}
+    }
+
+    /// `win` for `winner` and `loss` for everybody else.
+    pub fn winner_takes_all(num_players: usize, winner: usize, win: R, loss: R) -> Self {
+        Self {rewards: (0..num_players).map(|i| if i == winner { win } else { loss }).collect()}
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.rewards.len()
+    }
+
+    pub fn rewards(&self) -> &[R] {
+        &self.rewards
+    }
+
+    pub fn reward_for<P: PlayerIndex>(&self, player: &P) -> R {
+        self.rewards[player.index()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_policy::UCTPolicy;
+
+    /// Three players move in turn, each choosing 0 or 1, and then the game is over.
+    #[derive(Clone, Debug)]
+    struct Tree(Vec<usize>);
+
+    impl GameState for Tree {
+        type Move = usize;
+        type Player = usize;
+        type MoveList = Vec<usize>;
+
+        fn current_player(&self) -> usize {
+            self.0.len()
+        }
+        fn available_moves(&self) -> Vec<usize> {
+            if self.0.len() == 3 {
+                vec![]
+            } else {
+                vec![0, 1]
+            }
+        }
+        fn make_move(&mut self, mov: &usize) {
+            self.0.push(*mov);
+        }
+    }
+
+    // The rewards of the players at each leaf. Each player's best move given what the later
+    // players will do (the max^n solution) is 0, 1, 0 with rewards of 9, 2 and 3; a player who
+    // tried to help or hurt the others would play differently.
+    const LEAVES: [[f64; 3]; 8] = [
+        [5., 1., 0.], [0., 0., 9.], [9., 2., 3.], [1., 8., 1.],
+        [3., 5., 5.], [4., 4., 4.], [2., 9., 2.], [6., 6., 6.]];
+
+    struct LeafEvaluator;
+
+    impl Evaluator<ThreePlayers> for LeafEvaluator {
+        type StateEvaluation = RewardVector<f64>;
+
+        fn evaluate_new_state(&self, state: &Tree, moves: &Vec<usize>, _: Option<SearchHandle<ThreePlayers>>)
+            -> (Vec<()>, RewardVector<f64>) {
+            let rewards = if state.0.len() == 3 {
+                let leaf = state.0[0] * 4 + state.0[1] * 2 + state.0[2];
+                RewardVector::new(&LEAVES[leaf].iter().map(|x| x / 10.).collect::<Vec<_>>())
+            } else {
+                RewardVector::new(&[0.5; 3])
+            };
+            (vec![(); moves.len()], rewards)
+        }
+        fn interpret_evaluation_for_player(&self, rewards: &RewardVector<f64>, player: &usize) -> f64 {
+            rewards.reward_for(player)
+        }
+        fn evaluate_existing_state(&self, _: &Tree, rewards: &RewardVector<f64>, _: SearchHandle<ThreePlayers>)
+            -> RewardVector<f64> {
+            rewards.clone()
+        }
+    }
+
+    #[derive(Default)]
+    struct ThreePlayers;
+
+    impl MCTS for ThreePlayers {
+        type State = Tree;
+        type Reward = f64;
+        type Eval = LeafEvaluator;
+        type NodeData = ();
+        type ExtraThreadData = ();
+        type TreePolicy = UCTPolicy;
+        type TranspositionTable = ();
+        type PlayoutData = ();
+    }
+
+    #[test]
+    fn each_player_maximizes_its_own_reward() {
+        let mut mcts = MCTSManager::new(Tree(vec![]), ThreePlayers, LeafEvaluator, UCTPolicy::new(0.2), ());
+        mcts.playout_n(20000);
+        assert_eq!(mcts.principal_variation(3), vec![0, 1, 0]);
+        // Each edge is credited with the reward of the player who made the move.
+        let pv = mcts.principal_variation_info(3);
+        let averages: Vec<f32> = pv.iter().map(|x| x.average_reward().unwrap()).collect();
+        for (average, expected) in averages.iter().zip(&[0.9, 0.2, 0.3]) {
+            assert!((average - expected).abs() < 0.05, "averages {:?}", averages);
+        }
+    }
+
+    #[test]
+    fn reward_vectors() {
+        let rewards = RewardVector::winner_takes_all(3, 1, 1i64, -1);
+        assert_eq!(rewards.rewards(), &[-1, 1, -1]);
+        assert_eq!(rewards.num_players(), 3);
+        assert_eq!(rewards.reward_for(&1u8), 1);
+        assert_eq!(rewards.reward_for(&2usize), -1);
+    }
+}
@@ -0,0 +1,281 @@
+//! Support for RAVE (rapid action value estimation).
+//!
+//! RAVE shares information between moves: a move that was made later in a playout is treated
+//! as if it had been made first, and its all-moves-as-first (AMAF) statistics are updated too.
+//! To use it, choose a tree policy that needs AMAF statistics (such as `RAVEPolicy`), use a
+//! `MoveHistory` as `MCTS::PlayoutData`, and return it from `MCTS::amaf_history`.
+//! The search records the moves it makes in the tree; moves made by the evaluator (in a random
+//! rollout, say) can be added with `MCTS::record_evaluation_moves`. RAVE works much better with them.
+
+use super::*;
+use std::collections::HashMap;
+use std::hash::Hash;
+use smallvec::SmallVec;
+
+/// A record of the moves made in a playout.
+pub trait AmafHistory<Spec: MCTS> {
+    /// Called for every move made in the playout, in order.
+    fn record(&mut self, player: Player<Spec>, mov: &Move<Spec>);
+    /// Whether `player` made `mov` at move number `ply` of the playout or later.
+    fn played_since(&self, ply: usize, player: &Player<Spec>, mov: &Move<Spec>) -> bool;
+}
+
+/// An `AmafHistory` for games whose moves can be hashed and players compared.
+pub struct MoveHistory<Spec: MCTS> {
+    players: Vec<Player<Spec>>,
+    plies: HashMap<Move<Spec>, SmallVec<[usize; 2]>>,
+}
+
+impl<Spec: MCTS> Default for MoveHistory<Spec> {
+    fn default() -> Self {
+        Self {
+            players: Vec::new(),
+            plies: HashMap::new(),
+        }
+    }
+}
+
+impl<Spec: MCTS> MoveHistory<Spec> {
+    /// The number of moves recorded.
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+}
+
+impl<Spec: MCTS> AmafHistory<Spec> for MoveHistory<Spec>
+    where Move<Spec>: Hash + Eq, Player<Spec>: PartialEq
+{
+    fn record(&mut self, player: Player<Spec>, mov: &Move<Spec>) {
+        let ply = self.players.len();
+        self.players.push(player);
+        self.plies.entry(mov.clone()).or_default().push(ply);
+    }
+    fn played_since(&self, ply: usize, player: &Player<Spec>, mov: &Move<Spec>) -> bool {
+        match self.plies.get(mov) {
+            Some(plies) => plies.iter().any(|&x| x >= ply && self.players[x] == *player),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_policy::{UCTPolicy, RAVEPolicy};
+
+    #[derive(Clone)]
+    struct Game;
+
+    impl GameState for Game {
+        type Move = u8;
+        type Player = bool;
+        type MoveList = Vec<u8>;
+
+        fn current_player(&self) -> bool {
+            true
+        }
+        fn available_moves(&self) -> Vec<u8> {
+            vec![]
+        }
+        fn make_move(&mut self, _: &u8) {}
+    }
+
+    struct Eval;
+
+    impl Evaluator<Spec> for Eval {
+        type StateEvaluation = ();
+
+        fn evaluate_new_state(&self, _: &Game, _: &Vec<u8>, _: Option<SearchHandle<Spec>>) -> (Vec<()>, ()) {
+            (vec![], ())
+        }
+        fn interpret_evaluation_for_player(&self, _: &(), _: &bool) -> i64 {
+            0
+        }
+        fn evaluate_existing_state(&self, _: &Game, _: &(), _: SearchHandle<Spec>) {}
+    }
+
+    #[derive(Default)]
+    struct Spec;
+
+    impl MCTS for Spec {
+        type State = Game;
+        type Reward = i64;
+        type Eval = Eval;
+        type NodeData = ();
+        type ExtraThreadData = ();
+        type TreePolicy = UCTPolicy;
+        type TranspositionTable = ();
+        type PlayoutData = MoveHistory<Self>;
+    }
+
+    #[test]
+    fn played_since_checks_ply_and_player() {
+        let mut history = MoveHistory::<Spec>::default();
+        history.record(true, &1);
+        history.record(false, &2);
+        history.record(true, &3);
+        history.record(false, &1);
+        assert_eq!(history.len(), 4);
+        assert!(history.played_since(0, &true, &1));
+        assert!(!history.played_since(1, &true, &1));
+        assert!(history.played_since(1, &false, &1));
+        assert!(history.played_since(2, &true, &3));
+        assert!(!history.played_since(0, &false, &3));
+        assert!(!history.played_since(0, &true, &4));
+    }
+
+    // Two players take turns to pick one of the numbers 0 to 3; whoever picks 0 wins.
+    // Leaves are evaluated by picking the numbers that are left in increasing order.
+    // If `toss` is set, the game starts with a coin toss that can only come up `TOSS`.
+    #[derive(Clone)]
+    struct Picking {
+        turn: bool,
+        left: Vec<u8>,
+        zero_picked_by: Option<bool>,
+        toss: bool,
+    }
+
+    const TOSS: u8 = 10;
+
+    impl GameState for Picking {
+        type Move = u8;
+        type Player = bool;
+        type MoveList = Vec<u8>;
+
+        fn current_player(&self) -> bool {
+            self.turn
+        }
+        fn available_moves(&self) -> Vec<u8> {
+            if self.toss {
+                vec![TOSS]
+            } else {
+                self.left.clone()
+            }
+        }
+        fn make_move(&mut self, mov: &u8) {
+            if *mov == TOSS {
+                self.toss = false;
+                return;
+            }
+            if *mov == 0 {
+                self.zero_picked_by = Some(self.turn);
+            }
+            self.left.retain(|x| x != mov);
+            self.turn = !self.turn;
+        }
+        fn chance_probabilities(&self) -> Option<Vec<f64>> {
+            if self.toss {
+                Some(vec![1.0])
+            } else {
+                None
+            }
+        }
+    }
+
+    /// The players' moves of the rollout from a leaf, and the player who picked 0.
+    struct Rollout {
+        moves: Vec<(bool, u8)>,
+        winner: bool,
+    }
+
+    struct PickingEval;
+
+    impl Evaluator<PickingSpec> for PickingEval {
+        type StateEvaluation = Rollout;
+
+        fn evaluate_new_state(&self, state: &Picking, moves: &Vec<u8>, _: Option<SearchHandle<PickingSpec>>)
+                -> (Vec<()>, Rollout) {
+            let mut state = state.clone();
+            let mut rollout = Vec::new();
+            while let Some(&mov) = state.available_moves().first() {
+                if !state.toss {
+                    rollout.push((state.turn, mov));
+                }
+                state.make_move(&mov);
+            }
+            (vec![(); moves.len()], Rollout {moves: rollout, winner: state.zero_picked_by.unwrap()})
+        }
+        fn interpret_evaluation_for_player(&self, evaln: &Rollout, player: &bool) -> i64 {
+            if evaln.winner == *player { 1 } else { -1 }
+        }
+        fn evaluate_existing_state(&self, _: &Picking, evaln: &Rollout, _: SearchHandle<PickingSpec>) -> Rollout {
+            Rollout {moves: evaln.moves.clone(), winner: evaln.winner}
+        }
+    }
+
+    #[derive(Default)]
+    struct PickingSpec;
+
+    impl MCTS for PickingSpec {
+        type State = Picking;
+        type Reward = i64;
+        type Eval = PickingEval;
+        type NodeData = ();
+        type ExtraThreadData = ();
+        type TreePolicy = RAVEPolicy;
+        type TranspositionTable = ();
+        type PlayoutData = MoveHistory<Self>;
+
+        fn amaf_history<'a>(&self, data: &'a mut MoveHistory<Self>) -> Option<&'a mut dyn AmafHistory<Self>> {
+            Some(data)
+        }
+        fn record_evaluation_moves(&self, evaln: &Rollout, history: &mut dyn AmafHistory<Self>) {
+            for &(player, mov) in &evaln.moves {
+                history.record(player, &mov);
+            }
+        }
+    }
+
+    fn new_picking_search(toss: bool) -> MCTSManager<PickingSpec> {
+        let state = Picking {turn: true, left: vec![0, 1, 2, 3], zero_picked_by: None, toss};
+        MCTSManager::new(state, PickingSpec, PickingEval, RAVEPolicy::new(0.5, 100.0), ())
+    }
+
+    /// Checks the AMAF statistics of the first player's first node, after the only playout that
+    /// has made a choice there.
+    fn check_first_choice(node: NodeHandle<PickingSpec>) {
+        let first = node.moves().find(|mov| mov.visits() == 1).unwrap();
+        let picked = *first.get_move();
+        // The playout picks `picked` for the first player, then the rollout picks the rest in order,
+        // starting with the second player.
+        let rest: Vec<u8> = (0..4).filter(|&x| x != picked).collect();
+        let first_player_wins = picked == 0 || rest[1] == 0;
+        let reward = if first_player_wins { 1.0 } else { -1.0 };
+        for mov in node.moves() {
+            let m = *mov.get_move();
+            let first_player_picked = m == picked || m == rest[1];
+            assert_eq!(mov.amaf_visits(), first_player_picked as u64, "{}", m);
+            assert_eq!(mov.amaf_sum_rewards(), if first_player_picked { reward } else { 0.0 }, "{}", m);
+        }
+        // No move was chosen at the leaf, so it has no AMAF statistics yet.
+        assert!(first.child().unwrap().moves().all(|mov| mov.amaf_visits() == 0));
+    }
+
+    #[test]
+    fn amaf_statistics_count_every_later_move_of_the_player() {
+        let mut mcts = new_picking_search(false);
+        mcts.playout_n(1);
+        check_first_choice(mcts.tree().root_node());
+    }
+
+    #[test]
+    fn amaf_statistics_skip_chance_nodes() {
+        let mut mcts = new_picking_search(true);
+        // The first playout only expands the node after the toss.
+        mcts.playout_n(2);
+        let root = mcts.tree().root_node();
+        assert!(root.is_chance_node());
+        assert!(root.moves().all(|mov| mov.amaf_visits() == 0));
+        check_first_choice(root.moves().next().unwrap().child().unwrap());
+    }
+
+    #[test]
+    fn rave_picks_the_winning_number() {
+        let mut mcts = new_picking_search(false);
+        mcts.playout_n(200);
+        assert_eq!(mcts.best_move(), Some(0));
+    }
+}
@@ -0,0 +1,195 @@
+//! Rewards are the values that playouts back up through the tree.
+//!
+//! An `MCTS` chooses its reward type with `MCTS::Reward`. `i64` is the fixed-point option:
+//! it accumulates with a single atomic add, but the evaluator has to scale its values to integers.
+//! `f64` accumulates with a compare-and-swap loop instead. Tuples of rewards can be used to carry
+//! extra components (for example a draw rate) alongside the first component, which is the one
+//! tree policies try to maximize.
+
+use atomics::*;
+use std::fmt::Debug;
+
+pub trait Reward: Copy + Debug + Send + Sync + 'static {
+    /// The type that node and edge statistics accumulate rewards in.
+    type Atomic: Default + Send + Sync;
+
+    fn zero() -> Self;
+    fn add(self, other: Self) -> Self;
+    fn sub(self, other: Self) -> Self;
+    /// Multiplies every component by `factor`.
+    fn scale(self, factor: f64) -> Self;
+    /// The value that tree policies maximize.
+    fn to_f64(self) -> f64;
+
+    fn load(atomic: &Self::Atomic) -> Self;
+    fn store(atomic: &Self::Atomic, value: Self);
+    fn fetch_add(atomic: &Self::Atomic, value: Self);
+    fn fetch_sub(atomic: &Self::Atomic, value: Self) {
+        Self::fetch_add(atomic, Self::zero().sub(value));
+    }
+}
+
+impl Reward for i64 {
+    type Atomic = AtomicI64;
+
+    fn zero() -> Self {
+        0
+    }
+    fn add(self, other: Self) -> Self {
+        self + other
+    }
+    fn sub(self, other: Self) -> Self {
+        self - other
+    }
+    fn scale(self, factor: f64) -> Self {
+        (self as f64 * factor) as i64
+    }
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn load(atomic: &AtomicI64) -> Self {
+        atomic.load(Ordering::Relaxed)
+    }
+    fn store(atomic: &AtomicI64, value: Self) {
+        atomic.store(value, Ordering::Relaxed);
+    }
+    fn fetch_add(atomic: &AtomicI64, value: Self) {
+        atomic.fetch_add(value, Ordering::Relaxed);
+    }
+    fn fetch_sub(atomic: &AtomicI64, value: Self) {
+        atomic.fetch_sub(value, Ordering::Relaxed);
+    }
+}
+
+impl Reward for f64 {
+    type Atomic = AtomicF64;
+
+    fn zero() -> Self {
+        0.
+    }
+    fn add(self, other: Self) -> Self {
+        self + other
+    }
+    fn sub(self, other: Self) -> Self {
+        self - other
+    }
+    fn scale(self, factor: f64) -> Self {
+        self * factor
+    }
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn load(atomic: &AtomicF64) -> Self {
+        atomic.load()
+    }
+    fn store(atomic: &AtomicF64, value: Self) {
+        atomic.store(value);
+    }
+    fn fetch_add(atomic: &AtomicF64, value: Self) {
+        atomic.fetch_add(value);
+    }
+}
+
+macro_rules! tuple_reward {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: Reward),+> Reward for ($($name,)+) {
+            type Atomic = ($($name::Atomic,)+);
+
+            fn zero() -> Self {
+                ($($name::zero(),)+)
+            }
+            fn add(self, other: Self) -> Self {
+                ($(self.$index.add(other.$index),)+)
+            }
+            fn sub(self, other: Self) -> Self {
+                ($(self.$index.sub(other.$index),)+)
+            }
+            fn scale(self, factor: f64) -> Self {
+                ($(self.$index.scale(factor),)+)
+            }
+            fn to_f64(self) -> f64 {
+                (self.0).to_f64()
+            }
+
+            fn load(atomic: &Self::Atomic) -> Self {
+                ($($name::load(&atomic.$index),)+)
+            }
+            fn store(atomic: &Self::Atomic, value: Self) {
+                $($name::store(&atomic.$index, value.$index);)+
+            }
+            fn fetch_add(atomic: &Self::Atomic, value: Self) {
+                $($name::fetch_add(&atomic.$index, value.$index);)+
+            }
+            fn fetch_sub(atomic: &Self::Atomic, value: Self) {
+                $($name::fetch_sub(&atomic.$index, value.$index);)+
+            }
+        }
+    }
+}
+
+tuple_reward!(A 0, B 1);
+tuple_reward!(A 0, B 1, C 2);
+
+/// An `f64` that can be shared between threads, stored as its bits in an atomic integer.
+#[derive(Default)]
+pub struct AtomicF64 {
+    bits: AtomicU64,
+}
+
+impl AtomicF64 {
+    pub fn new(x: f64) -> Self {
+        Self {bits: AtomicU64::new(x.to_bits())}
+    }
+    pub fn load(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+    pub fn store(&self, x: f64) {
+        self.bits.store(x.to_bits(), Ordering::Relaxed);
+    }
+    pub fn fetch_add(&self, x: f64) -> f64 {
+        let mut current = self.bits.load(Ordering::Relaxed);
+        loop {
+            let new = (f64::from_bits(current) + x).to_bits();
+            match self.bits.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(old) => return f64::from_bits(old),
+                Err(old) => current = old,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn atomic_f64_adds_from_many_threads() {
+        let x = Arc::new(AtomicF64::default());
+        let threads: Vec<_> = (0..4).map(|_| {
+            let x = x.clone();
+            thread::spawn(move || for _ in 0..10000 {
+                x.fetch_add(0.5);
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(x.load(), 20000.);
+    }
+
+    #[test]
+    fn tuple_components_accumulate_separately() {
+        let atomic = <(f64, i64) as Reward>::Atomic::default();
+        Reward::fetch_add(&atomic, (1.5, 2));
+        Reward::fetch_add(&atomic, (0.5, 3));
+        Reward::fetch_sub(&atomic, (1., 1));
+        let sum: (f64, i64) = Reward::load(&atomic);
+        assert_eq!(sum, (1., 4));
+        assert_eq!(sum.to_f64(), 1.);
+        assert_eq!(sum.scale(0.5), (0.5, 2));
+    }
+}
@@ -0,0 +1,312 @@
+//! Evaluation by random rollouts, for games without a heuristic.
+//!
+//! A `RolloutEvaluator` evaluates a position by playing it out to the end with a cheap default
+//! policy and reporting the result. The game has to implement `RolloutState`, which says when the
+//! game is over and what each player gets. Rollouts draw their random numbers from the search
+//! thread's `PolicyRng`, so the tree policy's `ThreadLocalData` must be a `PolicyRng`
+//! (as it is for `UCTPolicy`, `AlphaGoPolicy` and the others in `tree_policy`).
+
+extern crate rand;
+use self::rand::Rng;
+
+use super::*;
+use smallvec::SmallVec;
+
+/// A game that can be played out to the end.
+pub trait RolloutState: GameState {
+    /// The result of a game, e.g. the winner.
+    type Outcome: Sync + Send;
+    /// The type of `MCTS::Reward` for this game.
+    type Reward: Reward;
+
+    /// The result of the game if it is over, otherwise `None`.
+    /// A position with no available moves must have an outcome.
+    fn outcome(&self) -> Option<Self::Outcome>;
+    /// The reward that `player` gets for `outcome`.
+    fn reward(outcome: &Self::Outcome, player: &Self::Player) -> Self::Reward;
+}
+
+/// The default policy of a rollout, which chooses the moves until the game is over.
+/// It isn't consulted at chance nodes, whose moves are sampled with their probabilities.
+pub trait RolloutPolicy<State: GameState>: Sync {
+    /// Chooses one of `moves`, which is never empty.
+    fn choose_move<'a>(&self, state: &State, moves: &'a [State::Move], rng: &mut PolicyRng) -> &'a State::Move;
+}
+
+/// Chooses every move with equal probability.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UniformRollout;
+
+impl<State: GameState> RolloutPolicy<State> for UniformRollout {
+    fn choose_move<'a>(&self, _: &State, moves: &'a [State::Move], rng: &mut PolicyRng) -> &'a State::Move {
+        rng.rng.choose(moves).unwrap()
+    }
+}
+
+/// Chooses moves with probability proportional to a weight, which must not be negative.
+/// If every weight is 0, the moves are chosen uniformly.
+#[derive(Clone, Copy, Debug)]
+pub struct WeightedRollout<F> {
+    weight: F,
+}
+
+impl<F> WeightedRollout<F> {
+    pub fn new(weight: F) -> Self {
+        Self {weight}
+    }
+}
+
+impl<State, F> RolloutPolicy<State> for WeightedRollout<F>
+    where State: GameState, F: Fn(&State, &State::Move) -> f32 + Sync
+{
+    fn choose_move<'a>(&self, state: &State, moves: &'a [State::Move], rng: &mut PolicyRng) -> &'a State::Move {
+        let weights: SmallVec<[f32; 64]> = moves.iter().map(|mov| (self.weight)(state, mov)).collect();
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            return rng.rng.choose(moves).unwrap();
+        }
+        let mut x = rng.rng.gen_range(0.0, total);
+        for (mov, &weight) in moves.iter().zip(weights.iter()) {
+            if x < weight {
+                return mov;
+            }
+            x -= weight;
+        }
+        // Rounding can leave a little of `x` over.
+        moves.iter().zip(weights.iter()).rev().find(|&(_, &weight)| weight > 0.0).unwrap().0
+    }
+}
+
+pub type Outcome<Spec> = <<Spec as MCTS>::State as RolloutState>::Outcome;
+type Heuristic<Spec> = fn(&<Spec as MCTS>::State) -> Outcome<Spec>;
+
+/// An evaluator that plays positions out with a `RolloutPolicy`. Every move gets the default
+/// move evaluation (`()` for `UCTPolicy`).
+pub struct RolloutEvaluator<Spec: MCTS, P> where Spec::State: RolloutState {
+    policy: P,
+    max_depth: Option<usize>,
+    heuristic: Option<Heuristic<Spec>>,
+}
+
+impl<Spec: MCTS, P> RolloutEvaluator<Spec, P> where Spec::State: RolloutState {
+    pub fn new(policy: P) -> Self {
+        Self {policy, max_depth: None, heuristic: None}
+    }
+
+    /// Stops rollouts after `max_depth` moves and scores the position they reached with `heuristic`.
+    pub fn with_max_depth(self, max_depth: usize, heuristic: fn(&Spec::State) -> Outcome<Spec>) -> Self {
+        Self {max_depth: Some(max_depth), heuristic: Some(heuristic), ..self}
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    /// Plays `moves`, the moves available in `state`, out to the end of the game or the depth limit.
+    pub fn rollout(&self, state: &Spec::State, moves: Vec<Move<Spec>>, rng: &mut PolicyRng) -> Outcome<Spec>
+        where P: RolloutPolicy<Spec::State>
+    {
+        if let Some(outcome) = state.outcome() {
+            return outcome;
+        }
+        let mut state = state.clone();
+        let mut moves = moves;
+        let mut depth = 0;
+        loop {
+            if self.max_depth == Some(depth) {
+                return (self.heuristic.unwrap())(&state);
+            }
+            assert!(!moves.is_empty(), "a position with no available moves has no outcome");
+            let mov = match state.chance_probabilities() {
+                Some(probabilities) => moves[rng.sample_index(&probabilities)].clone(),
+                None => self.policy.choose_move(&state, &moves, rng).clone(),
+            };
+            state.make_move(&mov);
+            depth += 1;
+            if let Some(outcome) = state.outcome() {
+                return outcome;
+            }
+            moves = state.available_moves().into_iter().collect();
+        }
+    }
+}
+
+impl<Spec, P> Evaluator<Spec> for RolloutEvaluator<Spec, P>
+    where Spec: MCTS<Reward=<<Spec as MCTS>::State as RolloutState>::Reward>,
+          Spec::State: RolloutState,
+          Spec::TreePolicy: TreePolicy<Spec, ThreadLocalData=PolicyRng>,
+          MoveEvaluation<Spec>: Default,
+          MoveList<Spec>: Clone,
+          P: RolloutPolicy<Spec::State>
+{
+    type StateEvaluation = Outcome<Spec>;
+
+    fn evaluate_new_state(&self, state: &Spec::State, moves: &MoveList<Spec>, handle: Option<SearchHandle<Spec>>)
+        -> (Vec<MoveEvaluation<Spec>>, Outcome<Spec>) {
+        // The node's moves aren't always the available moves (see `ismcts`).
+        let move_evaluations = moves.clone().into_iter().map(|_| Default::default()).collect();
+        let moves: Vec<_> = state.available_moves().into_iter().collect();
+        let outcome = match handle {
+            Some(mut handle) => self.rollout(state, moves, &mut handle.thread_data().policy_data),
+            None => self.rollout(state, moves, &mut PolicyRng::new()),
+        };
+        (move_evaluations, outcome)
+    }
+
+    fn evaluate_existing_state(&self, state: &Spec::State, _: &Outcome<Spec>, mut handle: SearchHandle<Spec>)
+        -> Outcome<Spec> {
+        let moves = state.available_moves().into_iter().collect();
+        self.rollout(state, moves, &mut handle.thread_data().policy_data)
+    }
+
+    fn interpret_evaluation_for_player(&self, outcome: &Outcome<Spec>, player: &Player<Spec>) -> Spec::Reward {
+        Spec::State::reward(outcome, player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Mark {
+        X, O
+    }
+
+    #[derive(Clone)]
+    struct TicTacToe {
+        cells: [Option<Mark>; 9],
+        to_move: Mark,
+    }
+
+    impl TicTacToe {
+        fn from_str(board: &str) -> Self {
+            let cells: Vec<_> = board.chars().filter(|c| !c.is_whitespace()).map(|c| match c {
+                'X' => Some(Mark::X),
+                'O' => Some(Mark::O),
+                _ => None,
+            }).collect();
+            let mut result = Self {cells: [None; 9], to_move: Mark::X};
+            result.cells.copy_from_slice(&cells);
+            let xs = cells.iter().filter(|&&c| c == Some(Mark::X)).count();
+            let os = cells.iter().filter(|&&c| c == Some(Mark::O)).count();
+            result.to_move = if xs == os { Mark::X } else { Mark::O };
+            result
+        }
+
+        fn winner(&self) -> Option<Mark> {
+            const LINES: [[usize; 3]; 8] = [
+                [0, 1, 2], [3, 4, 5], [6, 7, 8],
+                [0, 3, 6], [1, 4, 7], [2, 5, 8],
+                [0, 4, 8], [2, 4, 6]];
+            LINES.iter()
+                .find(|line| self.cells[line[0]].is_some()
+                    && self.cells[line[0]] == self.cells[line[1]]
+                    && self.cells[line[1]] == self.cells[line[2]])
+                .and_then(|line| self.cells[line[0]])
+        }
+    }
+
+    impl GameState for TicTacToe {
+        type Move = usize;
+        type Player = Mark;
+        type MoveList = Vec<usize>;
+
+        fn current_player(&self) -> Mark {
+            self.to_move
+        }
+        fn available_moves(&self) -> Vec<usize> {
+            if self.winner().is_some() {
+                return vec![];
+            }
+            (0..9).filter(|&i| self.cells[i].is_none()).collect()
+        }
+        fn make_move(&mut self, mov: &usize) {
+            self.cells[*mov] = Some(self.to_move);
+            self.to_move = if self.to_move == Mark::X { Mark::O } else { Mark::X };
+        }
+    }
+
+    impl RolloutState for TicTacToe {
+        // The winner, or `None` for a draw.
+        type Outcome = Option<Mark>;
+        type Reward = i64;
+
+        fn outcome(&self) -> Option<Option<Mark>> {
+            match self.winner() {
+                Some(mark) => Some(Some(mark)),
+                None if self.cells.iter().all(|c| c.is_some()) => Some(None),
+                None => None,
+            }
+        }
+        fn reward(winner: &Option<Mark>, player: &Mark) -> i64 {
+            match *winner {
+                Some(mark) if mark == *player => 1,
+                Some(_) => -1,
+                None => 0,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Uniform;
+
+    impl MCTS for Uniform {
+        type State = TicTacToe;
+        type Reward = i64;
+        type Eval = RolloutEvaluator<Self, UniformRollout>;
+        type NodeData = ();
+        type ExtraThreadData = ();
+        type TreePolicy = UCTPolicy;
+        type TranspositionTable = ();
+        type PlayoutData = ();
+    }
+
+    fn best_move(board: &str) -> usize {
+        let mut mcts = MCTSManager::new(TicTacToe::from_str(board), Uniform,
+            RolloutEvaluator::new(UniformRollout), UCTPolicy::new(1.0), ());
+        mcts.playout_n(5000);
+        mcts.best_move().unwrap()
+    }
+
+    #[test]
+    fn rollouts_find_wins_and_blocks() {
+        // X wins at once.
+        assert_eq!(best_move("XX. OO. ..."), 2);
+        // O has to block X's diagonal.
+        assert_eq!(best_move("X.. .X. O.."), 8);
+    }
+
+    #[test]
+    fn depth_limit_uses_heuristic() {
+        fn always_o_wins(_: &TicTacToe) -> Option<Mark> {
+            Some(Mark::O)
+        }
+        let evaluator: RolloutEvaluator<Uniform, _> = RolloutEvaluator::new(UniformRollout)
+            .with_max_depth(2, always_o_wins);
+        let state = TicTacToe::from_str("... ... ...");
+        let mut rng = PolicyRng::new();
+        for _ in 0..10 {
+            assert_eq!(evaluator.rollout(&state, state.available_moves(), &mut rng), Some(Mark::O));
+        }
+        // The heuristic isn't used if the game is already over.
+        let won = TicTacToe::from_str("XXX OO. ...");
+        assert_eq!(evaluator.rollout(&won, won.available_moves(), &mut rng), Some(Mark::X));
+    }
+
+    #[test]
+    fn weighted_rollout_follows_weights() {
+        let policy = WeightedRollout::new(|_: &TicTacToe, &mov: &usize| if mov == 4 { 3.0 } else if mov == 0 { 1.0 } else { 0.0 });
+        let state = TicTacToe::from_str("... ... ...");
+        let moves = state.available_moves();
+        let mut rng = PolicyRng::new();
+        let centre = (0..4000).filter(|_| *policy.choose_move(&state, &moves, &mut rng) == 4).count();
+        assert!(centre > 2800 && centre < 3200, "centre chosen {} times", centre);
+        let never = WeightedRollout::new(|_: &TicTacToe, _: &usize| 0.0);
+        assert!(moves.contains(never.choose_move(&state, &moves, &mut rng)));
+    }
+}
@@ -1,73 +1,156 @@
-#![cfg_attr(feature = "nightly", feature(integer_atomics))]
-
 use atomics::*;
+use reward::AtomicF64;
+use rave::AmafHistory;
 use super::*;
-use std::ptr::{null, null_mut};
+use std::ptr::null_mut;
 use smallvec::SmallVec;
 use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
+use std::cell::UnsafeCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::iter;
+use std::mem;
+use std::ptr;
 
 use pod::Pod;
 
 use tree_policy::TreePolicy;
 
-use arena::{ArenaAllocator, Arena};
+use arena::{ArenaAllocator, Arena, MAPPING_SIZE};
 
 /// You're not intended to use this class (use an `MCTSManager` instead),
 /// but you can use it if you want to manage the threads yourself.
 pub struct SearchTree<Spec: MCTS> {
-    root_node: SearchNode<Spec>,
+    root_node: UnsafeCell<SearchNode<Spec>>,
     root_state: Spec::State,
     tree_policy: Spec::TreePolicy,
     table: Spec::TranspositionTable,
     eval: Spec::Eval,
     manager: Spec,
-    arena: Box<Arena>,
+    arena: UnsafeCell<Box<Arena>>,
+    // Held for writing while the tree is being compacted into a new arena, and for reading by
+    // whatever looks at the arena outside a playout, like `memory_in_use` and root merges.
+    compaction_lock: RwLock<()>,
+    // Playouts don't take `compaction_lock`, so that they don't all write to the same cache line.
+    // Instead each search thread raises its own flag in `playout_flags` for the length of a
+    // playout, and backs off if `compacting` is set; compaction sets it and waits for every flag
+    // to be lowered.
+    compacting: AtomicBool,
+    playout_flags: Mutex<Vec<Arc<PlayoutFlag>>>,
+    arena_generation: AtomicUsize,
+    track_sum_of_squares: bool,
+    track_amaf: bool,
 
     num_nodes: AtomicUsize,
+    // The bytes of the arena taken by nodes, as opposed to the whole chunks given to allocators.
+    node_bytes: AtomicUsize,
+    compactions: AtomicUsize,
     transposition_table_hits: AtomicUsize,
     delayed_transposition_table_hits: AtomicUsize,
     expansion_contention_events: AtomicUsize,
 }
 
-trait NodeStats {
-    fn get_visits(&self) -> &FakeU32;
-    fn get_sum_evaluations(&self) -> &AtomicI64;
+trait NodeStats<Spec: MCTS> {
+    fn get_visits(&self) -> &AtomicU32;
+    fn get_sum_evaluations(&self) -> &<Spec::Reward as Reward>::Atomic;
+    fn get_sum_squares(&self) -> &AtomicF64;
 
-    fn down<Spec: MCTS>(&self, manager: &Spec) {
-        self.get_sum_evaluations().fetch_sub(manager.virtual_loss() as FakeI64, Ordering::Relaxed);
+    fn down(&self, manager: &Spec) {
+        Spec::Reward::fetch_sub(self.get_sum_evaluations(), manager.virtual_loss());
         self.get_visits().fetch_add(1, Ordering::Relaxed);
     }
-    fn up<Spec: MCTS>(&self, manager: &Spec, evaln: i64) {
-        let delta = evaln + manager.virtual_loss();
-        self.get_sum_evaluations().fetch_add(delta as FakeI64, Ordering::Relaxed);
+    fn up(&self, manager: &Spec, evaln: Spec::Reward, track_sum_of_squares: bool) {
+        let delta = evaln.add(manager.virtual_loss());
+        Spec::Reward::fetch_add(self.get_sum_evaluations(), delta);
+        if track_sum_of_squares {
+            let x = evaln.to_f64();
+            self.get_sum_squares().fetch_add(x * x);
+        }
+    }
+    fn add(&self, visits: u32, sum_evaluations: Spec::Reward, sum_squares: f64) {
+        self.get_visits().fetch_add(visits, Ordering::Relaxed);
+        Spec::Reward::fetch_add(self.get_sum_evaluations(), sum_evaluations);
+        self.get_sum_squares().fetch_add(sum_squares);
     }
-    fn replace<T: NodeStats>(&self, other: &T) {
+    fn replace<T: NodeStats<Spec>>(&self, other: &T) {
         self.get_visits().store(other.get_visits().load(Ordering::Relaxed), Ordering::Relaxed);
-        self.get_sum_evaluations().store(other.get_sum_evaluations().load(Ordering::Relaxed), Ordering::Relaxed);
+        Spec::Reward::store(self.get_sum_evaluations(), Spec::Reward::load(other.get_sum_evaluations()));
+        self.get_sum_squares().store(other.get_sum_squares().load());
+    }
+    /// Sets our averages to the expected value of the chance node `child`: the average of each
+    /// visited outcome weighted by its probability. Copies `child`'s visit count unless `keep_visits`.
+    fn replace_expectation(&self, child: &SearchNode<Spec>, keep_visits: bool) {
+        let visited = || child.hots().iter().zip(child.probabilities())
+            .filter(|&(hot, _)| hot.visits.load(Ordering::Relaxed) > 0);
+        let total_probability: f64 = visited().map(|(_, &p)| p).sum();
+        if total_probability == 0.0 {
+            // None of the outcomes has been visited yet, so all we have is the evaluation of `child`.
+            if keep_visits {
+                self.replace_average(child);
+            } else {
+                self.replace(child);
+            }
+            return;
+        }
+        if !keep_visits {
+            self.get_visits().store(child.get_visits().load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        let own_visits = self.get_visits().load(Ordering::Relaxed) as f64;
+        let mut sum = Spec::Reward::zero();
+        let mut sum_squares = 0.0;
+        for (hot, &p) in visited() {
+            // Scale each outcome straight to its share of our sum, so that integer rewards don't
+            // lose more than a unit per outcome to rounding.
+            let factor = own_visits * p / (total_probability * hot.visits.load(Ordering::Relaxed) as f64);
+            sum = sum.add(Spec::Reward::load(&hot.sum_evaluations).scale(factor));
+            sum_squares += hot.sum_squares.load() * factor;
+        }
+        Spec::Reward::store(self.get_sum_evaluations(), sum);
+        self.get_sum_squares().store(sum_squares);
+    }
+    /// Keeps our own visit count, but sets our sums so that our averages equal `child`'s averages.
+    fn replace_average<T: NodeStats<Spec>>(&self, child: &T) {
+        let child_visits = child.get_visits().load(Ordering::Relaxed);
+        if child_visits == 0 {
+            return;
+        }
+        let own_visits = self.get_visits().load(Ordering::Relaxed);
+        let factor = own_visits as f64 / child_visits as f64;
+        let child_sum = Spec::Reward::load(child.get_sum_evaluations());
+        Spec::Reward::store(self.get_sum_evaluations(), child_sum.scale(factor));
+        self.get_sum_squares().store(child.get_sum_squares().load() * factor);
     }
 }
 
-impl<Spec: MCTS> NodeStats for HotMoveInfo<Spec> {
-    fn get_visits(&self) -> &FakeU32 {
+impl<Spec: MCTS> NodeStats<Spec> for HotMoveInfo<Spec> {
+    fn get_visits(&self) -> &AtomicU32 {
         &self.visits
     }
-    fn get_sum_evaluations(&self) -> &AtomicI64 {
+    fn get_sum_evaluations(&self) -> &<Spec::Reward as Reward>::Atomic {
         &self.sum_evaluations
     }
+    fn get_sum_squares(&self) -> &AtomicF64 {
+        &self.sum_squares
+    }
 }
-impl<Spec: MCTS> NodeStats for SearchNode<Spec> {
-    fn get_visits(&self) -> &FakeU32 {
+impl<Spec: MCTS> NodeStats<Spec> for SearchNode<Spec> {
+    fn get_visits(&self) -> &AtomicU32 {
         &self.visits
     }
-    fn get_sum_evaluations(&self) -> &AtomicI64 {
+    fn get_sum_evaluations(&self) -> &<Spec::Reward as Reward>::Atomic {
         &self.sum_evaluations
     }
+    fn get_sum_squares(&self) -> &AtomicF64 {
+        &self.sum_squares
+    }
 }
 
 struct HotMoveInfo<Spec: MCTS> {
-    sum_evaluations: AtomicI64,
-    visits: FakeU32,
+    sum_evaluations: <Spec::Reward as Reward>::Atomic,
+    sum_squares: AtomicF64,
+    visits: AtomicU32,
     move_evaluation: MoveEvaluation<Spec>,
 }
 struct ColdMoveInfo<Spec: MCTS> {
@@ -75,18 +158,38 @@ struct ColdMoveInfo<Spec: MCTS> {
     child: AtomicPtr<SearchNode<Spec>>,
     owned: AtomicBool,
 }
+/// All-moves-as-first statistics of an edge, kept if the tree policy needs them (for RAVE).
+struct AmafStats {
+    visits: AtomicU32,
+    sum_rewards: AtomicF64,
+}
+/// The number of times an edge was available, in an information-set search.
+struct Availability {
+    count: AtomicU32,
+}
 pub struct MoveInfoHandle<'a, Spec: 'a + MCTS> {
     hot: &'a HotMoveInfo<Spec>,
     cold: &'a ColdMoveInfo<Spec>,
+    amaf: Option<&'a AmafStats>,
+    availability: Option<&'a Availability>,
+}
+
+/// A bit for each move of a node, set if the move can be played in the current playout.
+type MoveMask = [u64; ismcts::MAX_MOVES / 64];
+
+fn mask_contains(mask: &MoveMask, i: usize) -> bool {
+    i >= ismcts::MAX_MOVES || mask[i / 64] & (1 << (i % 64)) != 0
 }
 
 unsafe impl<Spec: MCTS> Pod for HotMoveInfo<Spec> {}
 unsafe impl<Spec: MCTS> Pod for ColdMoveInfo<Spec> {}
+unsafe impl Pod for AmafStats {}
+unsafe impl Pod for Availability {}
 unsafe impl<Spec: MCTS> Pod for SearchNode<Spec> {}
 
 impl<'a, Spec: MCTS> Clone for MoveInfoHandle<'a, Spec> {
     fn clone(&self) -> Self {
-        Self {hot: self.hot, cold: self.cold}
+        *self
     }
 }
 impl<'a, Spec: MCTS> Copy for MoveInfoHandle<'a, Spec> {}
@@ -94,12 +197,28 @@ impl<'a, Spec: MCTS> Copy for MoveInfoHandle<'a, Spec> {}
 pub struct SearchNode<Spec: MCTS> {
     hots: *const [()],
     colds: *const [()],
+    // Empty unless the tree policy needs AMAF statistics.
+    amaf: *const [()],
+    // The probabilities of the moves if this is a chance node, otherwise empty.
+    probabilities: *const [()],
+    // How many times each move was available, in an information-set search. Otherwise empty.
+    availability: *const [()],
     data: Spec::NodeData,
     evaln: StateEvaluation<Spec>,
-    sum_evaluations: AtomicI64,
-    visits: FakeU32,
+    sum_evaluations: <Spec::Reward as Reward>::Atomic,
+    sum_squares: AtomicF64,
+    visits: AtomicU32,
 }
 
+unsafe impl<Spec: MCTS> Sync for SearchTree<Spec>
+    where
+        Spec::State: Sync,
+        Spec::TreePolicy: Sync,
+        Spec::TranspositionTable: Sync,
+        Spec::Eval: Sync,
+        SearchNode<Spec>: Sync,
+{}
+
 unsafe impl<Spec: MCTS> Sync for SearchNode<Spec>
     where
         Spec::NodeData: Sync,
@@ -109,33 +228,91 @@ unsafe impl<Spec: MCTS> Sync for SearchNode<Spec>
         // for<'a> &'a[ColdMoveInfo<Spec>]: Sync,
 {}
 
+// The nodes live in the tree's arena, so the tree can move to another thread with its nodes.
+unsafe impl<Spec: MCTS> Send for SearchTree<Spec>
+    where
+        Spec: Send,
+        Spec::State: Send,
+        Spec::TreePolicy: Send,
+        Spec::TranspositionTable: Send,
+        Spec::Eval: Send,
+        SearchNode<Spec>: Send,
+{}
+
+unsafe impl<Spec: MCTS> Send for SearchNode<Spec>
+    where
+        Spec::NodeData: Send,
+        StateEvaluation<Spec>: Send,
+{}
+
 impl<Spec: MCTS> SearchNode<Spec> {
     fn new<'a>(
             hots: &'a [HotMoveInfo<Spec>],
             colds: &'a [ColdMoveInfo<Spec>],
+            amaf: &'a [AmafStats],
+            probabilities: &'a [f64],
+            availability: &'a [Availability],
             evaln: StateEvaluation<Spec>) -> Self {
         Self {
             hots: hots as *const _ as *const [()],
             colds: colds as *const _ as *const [()],
+            amaf: amaf as *const _ as *const [()],
+            probabilities: probabilities as *const _ as *const [()],
+            availability: availability as *const _ as *const [()],
             data: Default::default(),
             evaln,
-            visits: FakeU32::default(),
-            sum_evaluations: AtomicI64::default(),
+            visits: AtomicU32::default(),
+            sum_evaluations: Default::default(),
+            sum_squares: AtomicF64::default(),
         }
     }
-    fn hots<'a>(&'a self) -> &'a [HotMoveInfo<Spec>] {
+    fn hots(&self) -> &[HotMoveInfo<Spec>] {
         unsafe {&*(self.hots as *const [HotMoveInfo<Spec>])}
     }
-    fn colds<'a>(&'a self) -> &'a [ColdMoveInfo<Spec>] {
+    fn colds(&self) -> &[ColdMoveInfo<Spec>] {
         unsafe {&*(self.colds as *const [ColdMoveInfo<Spec>])}
     }
-    pub fn moves(&self) -> Moves<Spec> {
+    fn amaf(&self) -> &[AmafStats] {
+        unsafe {&*(self.amaf as *const [AmafStats])}
+    }
+    fn probabilities(&self) -> &[f64] {
+        unsafe {&*(self.probabilities as *const [f64])}
+    }
+    fn availability(&self) -> &[Availability] {
+        unsafe {&*(self.availability as *const [Availability])}
+    }
+    fn is_chance_node(&self) -> bool {
+        !self.probabilities().is_empty()
+    }
+    pub fn moves(&self) -> Moves<'_, Spec> {
         Moves {
             hots: self.hots(),
             colds: self.colds(),
+            amaf: self.amaf(),
+            availability: self.availability(),
+            mask: None,
             index: 0,
         }
     }
+    /// The moves in `mask`. They borrow the mask as well as the node; `move_info` gets a handle
+    /// that only borrows the node.
+    fn moves_with_mask<'b>(&'b self, mask: &'b MoveMask) -> Moves<'b, Spec> {
+        Moves {mask: Some(mask), ..self.moves()}
+    }
+    /// The same move as `mov`, which must be one of ours.
+    fn move_info(&self, mov: MoveInfoHandle<Spec>) -> MoveInfoHandle<'_, Spec> {
+        let offset = mov.hot as *const HotMoveInfo<Spec> as usize - self.hots().as_ptr() as usize;
+        let index = offset / mem::size_of::<HotMoveInfo<Spec>>();
+        MoveInfoHandle {
+            hot: &self.hots()[index],
+            cold: &self.colds()[index],
+            amaf: self.amaf().get(index),
+            availability: self.availability().get(index),
+        }
+    }
+    pub fn visits(&self) -> u64 {
+        self.visits.load(Ordering::Relaxed) as u64
+    }
 }
 
 impl<Spec: MCTS> HotMoveInfo<Spec> {
@@ -143,12 +320,13 @@ impl<Spec: MCTS> HotMoveInfo<Spec> {
     fn new(move_evaluation: MoveEvaluation<Spec>) -> Self {
         Self {
             move_evaluation,
-            sum_evaluations: AtomicI64::default(),
-            visits: FakeU32::default(),
+            sum_evaluations: Default::default(),
+            sum_squares: AtomicF64::default(),
+            visits: AtomicU32::default(),
         }
     }
 }
-impl<'a, Spec: MCTS> ColdMoveInfo<Spec> {
+impl<Spec: MCTS> ColdMoveInfo<Spec> {
     fn new(mov: Move<Spec>) -> Self {
         Self {
             mov,
@@ -171,13 +349,35 @@ impl<'a, Spec: MCTS> MoveInfoHandle<'a, Spec> {
         self.hot.visits.load(Ordering::Relaxed) as u64
     }
 
-    pub fn sum_rewards(&self) -> i64 {
-        self.hot.sum_evaluations.load(Ordering::Relaxed) as i64
+    pub fn sum_rewards(&self) -> Spec::Reward {
+        Spec::Reward::load(&self.hot.sum_evaluations)
+    }
+
+    /// Only accumulated if `MCTS::track_sum_of_squares` or `TreePolicy::needs_sum_of_squares` is true.
+    pub fn sum_squared_rewards(&self) -> f64 {
+        self.hot.sum_squares.load()
+    }
+
+    /// The number of playouts through the parent in which the parent's player made this move
+    /// at any later point. Always 0 unless the tree policy needs AMAF statistics.
+    pub fn amaf_visits(&self) -> u64 {
+        self.amaf.map(|x| x.visits.load(Ordering::Relaxed) as u64).unwrap_or(0)
+    }
+
+    /// The sum of the rewards of the playouts counted by `amaf_visits`.
+    pub fn amaf_sum_rewards(&self) -> f64 {
+        self.amaf.map(|x| x.sum_rewards.load()).unwrap_or(0.0)
+    }
+
+    /// In an information-set search, the number of playouts through the parent in which this move
+    /// could be played. Otherwise `None`.
+    pub fn availability(&self) -> Option<u64> {
+        self.availability.map(|x| x.count.load(Ordering::Relaxed) as u64)
     }
 
     pub fn child(&self) -> Option<NodeHandle<'a, Spec>> {
         let ptr = self.cold.child.load(Ordering::Relaxed);
-        if ptr == null_mut() {
+        if ptr.is_null() {
             None
         } else {
             unsafe {Some(NodeHandle {node: &*ptr})}
@@ -187,7 +387,7 @@ impl<'a, Spec: MCTS> MoveInfoHandle<'a, Spec> {
     pub fn average_reward(&self) -> Option<f32> {
         match self.visits() {
             0 => None,
-            x => Some(self.sum_rewards() as f32 / x as f32)
+            x => Some((self.sum_rewards().to_f64() / x as f64) as f32)
         }
     }
 }
@@ -206,7 +406,7 @@ impl<'a, Spec: MCTS> Display for MoveInfoHandle<'a, Spec> where Move<Spec>: Disp
         } else {
             write!(f, "{} [{} visit{}] [{} avg reward]{}",
                 self.get_move(), self.visits(), if self.visits() == 1 {""} else {"s"},
-                self.sum_rewards() as f64 / self.visits() as f64,
+                self.sum_rewards().to_f64() / self.visits() as f64,
                 own_str)
         }
     }
@@ -226,7 +426,7 @@ impl<'a, Spec: MCTS> Debug for MoveInfoHandle<'a, Spec> where Move<Spec>: Debug
         } else {
             write!(f, "{:?} [{} visit{}] [{} avg reward]{}",
                 self.get_move(), self.visits(), if self.visits() == 1 {""} else {"s"},
-                self.sum_rewards() as f64 / self.visits() as f64,
+                self.sum_rewards().to_f64() / self.visits() as f64,
                 own_str)
         }
     }
@@ -238,8 +438,8 @@ enum CreationHelper<'a: 'b, 'b, Spec: 'a + MCTS> {
 }
 
 #[inline(always)]
-fn create_node<'a, 'b, 'c, Spec: MCTS>(eval: &Spec::Eval, policy: &Spec::TreePolicy, state: &Spec::State,
-        ch: CreationHelper<'a, 'b, Spec>)
+fn create_node<'a, 'b, Spec: MCTS>(manager: &Spec, eval: &Spec::Eval, policy: &Spec::TreePolicy,
+        state: &Spec::State, ch: CreationHelper<'a, 'b, Spec>)
         -> SearchNode<Spec> {
     let (allocator, handle) = match ch {
         CreationHelper::Allocator(x) => (x, None),
@@ -250,46 +450,163 @@ fn create_node<'a, 'b, 'c, Spec: MCTS>(eval: &Spec::Eval, policy: &Spec::TreePol
             (allocator, Some(x))
         }
     };
-    let moves = state.available_moves();
-    let (move_eval, state_eval) = eval.evaluate_new_state(&state, &moves, handle);
+    let information_set = manager.information_set();
+    let moves = match information_set {
+        Some(information_set) => information_set.possible_moves(state),
+        None => state.available_moves(),
+    };
+    let chance_probabilities = state.chance_probabilities();
+    let (move_eval, state_eval) = eval.evaluate_new_state(state, &moves, handle);
     policy.validate_evaluations(&move_eval);
     let hots = allocator.alloc_slice(move_eval.len());
     let colds = allocator.alloc_slice(move_eval.len());
-    for (x, y) in hots.iter_mut().zip(move_eval.into_iter()) {
-        *x = HotMoveInfo::new(y);
+    // The memory may have been used before an arena reset, so don't drop what's in it.
+    for (x, y) in hots.iter_mut().zip(move_eval) {
+        unsafe { ptr::write(x, HotMoveInfo::new(y)); }
+    }
+    for (x, y) in colds.iter_mut().zip(moves) {
+        unsafe { ptr::write(x, ColdMoveInfo::new(y)); }
+    }
+    let amaf: &mut [AmafStats] = if policy.needs_amaf_statistics() {
+        allocator.alloc_slice(hots.len())
+    } else {
+        &mut []
+    };
+    for x in amaf.iter_mut() {
+        unsafe { ptr::write(x, AmafStats {visits: AtomicU32::default(), sum_rewards: AtomicF64::default()}); }
+    }
+    let probabilities: &mut [f64] = match chance_probabilities {
+        Some(probabilities) => {
+            assert_eq!(probabilities.len(), hots.len(),
+                "chance_probabilities() must give a probability for each available move");
+            let slice = allocator.alloc_slice(probabilities.len());
+            slice.copy_from_slice(&probabilities);
+            slice
+        }
+        None => &mut [],
+    };
+    let availability: &mut [Availability] = if information_set.is_some() {
+        assert!(hots.len() <= ismcts::MAX_MOVES,
+            "a node has {} possible moves, but information-set search supports at most {}",
+            hots.len(), ismcts::MAX_MOVES);
+        allocator.alloc_slice(hots.len())
+    } else {
+        &mut []
+    };
+    for x in availability.iter_mut() {
+        unsafe { ptr::write(x, Availability {count: AtomicU32::default()}); }
     }
-    for (x, y) in colds.iter_mut().zip(moves.into_iter()) {
-        *x = ColdMoveInfo::new(y);
+    SearchNode::new(hots, colds, amaf, probabilities, availability, state_eval)
+}
+
+/// The number of bytes `create_node` took from the arena for `node`.
+fn node_bytes<Spec: MCTS>(node: &SearchNode<Spec>) -> usize {
+    mem::size_of::<SearchNode<Spec>>()
+        + node.hots().len() * (mem::size_of::<HotMoveInfo<Spec>>() + mem::size_of::<ColdMoveInfo<Spec>>())
+        + std::mem::size_of_val(node.amaf())
+        + std::mem::size_of_val(node.probabilities())
+        + std::mem::size_of_val(node.availability())
+}
+
+const MIN_CHUNK_SIZE: usize = 1 << 10;
+
+/// The chunk size of the tree's allocators: the arena's own, unless that is more than a 64th of
+/// the memory limit.
+fn chunk_size<Spec: MCTS>(manager: &Spec) -> usize {
+    match manager.memory_limit() {
+        Some(limit) => (limit / 64).clamp(MIN_CHUNK_SIZE, MAPPING_SIZE),
+        None => MAPPING_SIZE,
+    }
+}
+
+fn new_arena<Spec: MCTS>(manager: &Spec) -> Box<Arena> {
+    if manager.huge_pages() {
+        Box::new(Arena::with_huge_pages())
+    } else {
+        Box::new(Arena::new())
+    }
+}
+
+/// The moves of `node` that can be played in `state`, whose availability counts are incremented.
+fn available_moves<Spec: MCTS>(information_set: &dyn ismcts::InformationSet<Spec>, state: &Spec::State,
+        node: &SearchNode<Spec>) -> MoveMask {
+    let mut mask = [0; ismcts::MAX_MOVES / 64];
+    for (i, (cold, availability)) in node.colds().iter().zip(node.availability()).enumerate() {
+        if information_set.is_available(state, &cold.mov) {
+            mask[i / 64] |= 1 << (i % 64);
+            availability.count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    mask
+}
+
+/// Raised by a search thread while it is in a playout; see `SearchTree::compacting`.
+#[repr(align(64))]
+#[derive(Default)]
+pub(crate) struct PlayoutFlag(AtomicBool);
+
+/// Lowers the playout flag of its thread when the playout returns.
+struct InPlayout(Arc<PlayoutFlag>);
+
+impl Drop for InPlayout {
+    fn drop(&mut self) {
+        (self.0).0.store(false, Ordering::SeqCst);
     }
-    SearchNode::new(hots, colds, state_eval)
 }
 
 fn is_cycle<T>(past: &[&T], current: &T) -> bool {
-    past.iter().any(|x| *x as *const T == current as *const T)
+    past.iter().any(|x| std::ptr::eq(*x, current))
 }
 
 impl<Spec: MCTS> SearchTree<Spec> {
     pub fn new(state: Spec::State, manager: Spec, tree_policy: Spec::TreePolicy, eval: Spec::Eval,
             table: Spec::TranspositionTable) -> Self {
-        let arena = Box::new(Arena::new());
-        let root_node = create_node(&eval, &tree_policy, &state, CreationHelper::Allocator(&arena.allocator()));
+        if let Some(limit) = manager.memory_limit() {
+            assert!(table.memory_usage() < limit, "the transposition table doesn't fit in the memory limit");
+            // Compaction clears the table, so find out now if it can't.
+            table.clear();
+        }
+        let arena = new_arena(&manager);
+        Self::with_arena(state, manager, tree_policy, eval, table, arena)
+    }
+
+    fn with_arena(state: Spec::State, manager: Spec, tree_policy: Spec::TreePolicy, eval: Spec::Eval,
+            table: Spec::TranspositionTable, arena: Box<Arena>) -> Self {
+        let root_node = create_node(&manager, &eval, &tree_policy, &state,
+            CreationHelper::Allocator(&arena.allocator_with_chunk_size(chunk_size(&manager))));
+        let node_bytes = node_bytes(&root_node);
+        let track_sum_of_squares = manager.track_sum_of_squares() || tree_policy.needs_sum_of_squares();
+        let track_amaf = tree_policy.needs_amaf_statistics();
         Self {
             root_state: state,
-            root_node,
+            root_node: UnsafeCell::new(root_node),
             manager,
             tree_policy,
             eval,
             table,
             num_nodes: 1.into(),
-            arena,
+            node_bytes: node_bytes.into(),
+            compactions: 0.into(),
+            arena: UnsafeCell::new(arena),
+            compaction_lock: RwLock::new(()),
+            compacting: AtomicBool::new(false),
+            playout_flags: Mutex::new(Vec::new()),
+            arena_generation: 0.into(),
+            track_sum_of_squares,
+            track_amaf,
             transposition_table_hits: 0.into(),
             delayed_transposition_table_hits: 0.into(),
             expansion_contention_events: 0.into(),
         }
     }
 
+    /// Starts a new search from the same root, reusing the arena's memory.
     pub fn reset(self) -> Self {
-        Self::new(self.root_state, self.manager, self.tree_policy.reset(), self.eval, self.table)
+        // The table points into the arena we are about to reset.
+        self.table.clear();
+        let mut arena = self.arena.into_inner();
+        arena.reset();
+        Self::with_arena(self.root_state, self.manager, self.tree_policy.reset(), self.eval, self.table, arena)
     }
 
     pub fn spec(&self) -> &Spec {
@@ -300,38 +617,135 @@ impl<Spec: MCTS> SearchTree<Spec> {
         self.num_nodes.load(Ordering::SeqCst)
     }
 
+    /// The arena that new nodes are allocated in.
+    /// If the `MCTS` has a memory limit, it is replaced (and the old one freed) whenever the tree is compacted.
     pub fn arena(&self) -> &Arena {
-        &self.arena
+        unsafe { &*self.arena.get() }
+    }
+
+    pub(crate) fn arena_generation(&self) -> usize {
+        self.arena_generation.load(Ordering::Relaxed)
+    }
+
+    /// An allocator in the current arena, which takes smaller chunks than the arena's own when
+    /// there is a memory limit, so that the chunks held by search threads don't use up the budget.
+    pub(crate) fn allocator(&self) -> ArenaAllocator<'_> {
+        self.arena().allocator_with_chunk_size(chunk_size(&self.manager))
+    }
+
+    /// Gives a new search thread its playout flag. Flags of threads that have finished are
+    /// dropped here.
+    pub(crate) fn register_playout_thread(&self) -> Arc<PlayoutFlag> {
+        let flag = Arc::new(PlayoutFlag::default());
+        let mut flags = self.playout_flags.lock().unwrap();
+        flags.retain(|flag| Arc::strong_count(flag) > 1);
+        flags.push(flag.clone());
+        flag
+    }
+
+    /// The number of bytes that count towards `MCTS::memory_limit`: everything the arena has given to
+    /// allocators, including the unused ends of their chunks, and the transposition table.
+    pub fn memory_in_use(&self) -> usize {
+        let _compaction_guard = self.compaction_lock.read().unwrap();
+        self.memory_in_use_unlocked()
+    }
+
+    fn memory_in_use_unlocked(&self) -> usize {
+        self.arena().bytes_allocated() + self.table.memory_usage()
+    }
+
+    /// Raises the playout flag of `tld`, first compacting the tree if it takes up more than `limit`
+    /// bytes, or waiting if another thread is compacting it.
+    fn enter_playout<'a>(&'a self, tld: &mut ThreadData<'a, Spec>, limit: usize) -> InPlayout {
+        let mut compacted = false;
+        loop {
+            tld.playout_flag.0.store(true, Ordering::SeqCst);
+            if !self.compacting.load(Ordering::SeqCst) {
+                // The arena can't be replaced while our flag is up. If the tree is still too big
+                // right after we have compacted it, the limit leaves too little room for the
+                // threads' chunks, and compacting again wouldn't help.
+                if compacted || self.memory_in_use_unlocked() <= limit {
+                    return InPlayout(tld.playout_flag.clone());
+                }
+                tld.playout_flag.0.store(false, Ordering::SeqCst);
+                self.compact(tld, limit);
+                compacted = true;
+            } else {
+                tld.playout_flag.0.store(false, Ordering::SeqCst);
+                // Wait for the compaction to finish.
+                drop(self.compaction_lock.read().unwrap());
+            }
+        }
+    }
+
+    fn root(&self) -> &SearchNode<Spec> {
+        unsafe { &*self.root_node.get() }
     }
 
     #[inline(never)]
     pub fn playout<'a: 'b, 'b>(&'a self, tld: &'b mut ThreadData<'a, Spec>) -> bool {
         const LARGE_DEPTH: usize = 64;
+        let _in_playout = self.manager.memory_limit().map(|limit| self.enter_playout(tld, limit));
+        let generation = self.arena_generation.load(Ordering::Relaxed);
+        if tld.arena_generation != generation {
+            tld.allocator = self.allocator();
+            tld.arena_generation = generation;
+        }
         let sentinel = IncreaseSentinel::new(&self.num_nodes);
         if sentinel.num_nodes >= self.manager.node_limit() {
             return false;
         }
-        let mut state = self.root_state.clone();
+        let information_set = self.manager.information_set();
+        let mut state = match information_set {
+            Some(information_set) => information_set.sample(&mut tld.chance_rng.rng),
+            None => self.root_state.clone(),
+        };
         let mut playout_data = Spec::PlayoutData::default();
         let mut path: SmallVec<[MoveInfoHandle<Spec>; LARGE_DEPTH]> = SmallVec::new();
         let mut node_path: SmallVec<[&SearchNode<Spec>; LARGE_DEPTH]> = SmallVec::new();
         let mut players: SmallVec<[Player<Spec>; LARGE_DEPTH]> = SmallVec::new();
         let mut did_we_create = false;
-        let mut node = &self.root_node;
+        let mut node = self.root();
         loop {
-            if node.hots().len() == 0 {
+            if node.hots().is_empty() {
                 break;
             }
             if path.len() >= self.manager.max_playout_length() {
                 break;
             }
-            let choice = match self.manager.override_policy(&playout_data, &state, node.moves()) {
-                Some(choice) => choice,
-                None => self.tree_policy.choose_child(&state, node.moves(), self.make_handle(tld, &node_path)),
-            }; 
-            self.manager.on_choice_made(&mut playout_data, &state, node.moves(), choice, self.make_handle(tld, &node_path));
+            let mask;
+            let moves = match information_set {
+                Some(information_set) if !node.is_chance_node() => {
+                    mask = available_moves(information_set, &state, node);
+                    if mask == [0; ismcts::MAX_MOVES / 64] {
+                        break;
+                    }
+                    node.moves_with_mask(&mask)
+                }
+                _ => node.moves(),
+            };
+            let choice = if node.is_chance_node() {
+                let i = tld.chance_rng.sample_index(node.probabilities());
+                node.moves().nth(i).unwrap()
+            } else {
+                match (self.manager.override_policy(&playout_data, &state, moves), self.manager.gumbel_root()) {
+                    (Some(choice), _) => choice,
+                    (None, Some(gumbel)) if path.is_empty() => gumbel.choose_child(moves),
+                    (None, _) => self.tree_policy.choose_child(&state, moves, self.make_handle(tld, &node_path)),
+                }
+            };
+            self.manager.on_choice_made(&mut playout_data, &state, moves, choice, self.make_handle(tld, &node_path));
+            // `choice` may borrow `mask`, which only lives until the next move.
+            let choice = node.move_info(choice);
             choice.hot.down(&self.manager);
-            players.push(state.current_player());
+            let player = match players.last() {
+                Some(player) if node.is_chance_node() => player.clone(),
+                _ => state.current_player(),
+            };
+            if self.track_amaf && !node.is_chance_node() {
+                self.amaf_history(&mut playout_data).record(player.clone(), &choice.cold.mov);
+            }
+            players.push(player);
             path.push(choice);
             assert!(path.len() <= self.manager.max_playout_length(),
                 "playout length exceeded maximum of {} (maybe the transposition table is creating an infinite loop?)",
@@ -349,7 +763,7 @@ impl<Spec: MCTS> SearchTree<Spec> {
                     break;
                 },
                 CycleBehaviour::UseThisEvalWhenCycleDetected(e) => if is_cycle(&node_path, node) {
-                    self.finish_playout(&path, &node_path, &players, tld, &e);
+                    self.finish_playout(&path, &node_path, &players, &mut playout_data, tld, &e);
                     return true;
                 },
             };
@@ -366,38 +780,42 @@ impl<Spec: MCTS> SearchTree<Spec> {
             Some(self.eval.evaluate_existing_state(&state, &node.evaln, self.make_handle(tld, &node_path)))
         };
         let evaln = new_evaln.as_ref().unwrap_or(&node.evaln);
-        self.finish_playout(&path, &node_path, &players, tld, evaln);
+        self.finish_playout(&path, &node_path, &players, &mut playout_data, tld, evaln);
         true
     }
 
     fn descend<'a>(&'a self, state: &Spec::State, choice: &ColdMoveInfo<Spec>,
             tld: &mut ThreadData<'a, Spec>, path: &[&'a SearchNode<Spec>])
             -> (&'a SearchNode<Spec>, bool) {
-        let child = choice.child.load(Ordering::Relaxed) as *const _;
-        if child != null() {
+        let child = choice.child.load(Ordering::Relaxed) as *const SearchNode<Spec>;
+        if !child.is_null() {
             return unsafe { (&*child, false) };
         }
         if let Some(node) = self.table.lookup(state, self.make_handle(tld, path)) {
-            let child = choice.child.compare_and_swap(
+            let result = choice.child.compare_exchange(
                 null_mut(),
                 node as *const _ as *mut _,
-                Ordering::Relaxed) as *const _;
-            if child == null() {
-                self.transposition_table_hits.fetch_add(1, Ordering::Relaxed);
-                return (node, false);
-            } else {
-                return unsafe { (&*child, false) };
+                Ordering::Relaxed,
+                Ordering::Relaxed);
+            match result {
+                Ok(_) => {
+                    self.transposition_table_hits.fetch_add(1, Ordering::Relaxed);
+                    return (node, false);
+                }
+                Err(child) => return unsafe { (&*child, false) },
             }
         }
-        let created_here = create_node(&self.eval, &self.tree_policy, state,
+        let created_here = create_node(&self.manager, &self.eval, &self.tree_policy, state,
             CreationHelper::Handle(self.make_handle(tld, path)));
+        self.node_bytes.fetch_add(node_bytes(&created_here), Ordering::Relaxed);
         let created = tld.allocator.alloc_one();
         *created = created_here;
-        let other_child = choice.child.compare_and_swap(
+        let result = choice.child.compare_exchange(
             null_mut(),
             created as *mut _,
+            Ordering::Relaxed,
             Ordering::Relaxed);
-        if other_child != null_mut() {
+        if let Err(other_child) = result {
             self.expansion_contention_events.fetch_add(1, Ordering::Relaxed);
             unsafe {
                 return (&*other_child, false);
@@ -418,21 +836,200 @@ impl<Spec: MCTS> SearchTree<Spec> {
             path: &[MoveInfoHandle<Spec>],
             node_path: &[&'a SearchNode<Spec>],
             players: &[Player<Spec>],
+            playout_data: &mut Spec::PlayoutData,
             tld: &mut ThreadData<'a, Spec>,
             evaln: &StateEvaluation<Spec>) {
+        if self.track_amaf {
+            self.update_amaf(node_path, players, playout_data, evaln);
+        }
         for ((move_info, player), node) in
                 path.iter()
                 .zip(players.iter())
                 .zip(node_path.iter())
                 .rev() {
             let evaln_value = self.eval.interpret_evaluation_for_player(evaln, player);
-            node.up(&self.manager, evaln_value);
-            move_info.hot.replace(*node);
+            node.up(&self.manager, evaln_value, self.track_sum_of_squares);
+            if node.visits() < move_info.visits() {
+                // `compact` cut the node off and it has been expanded again, so the edge has seen
+                // more playouts than the node; adding this one keeps them.
+                move_info.hot.up(&self.manager, evaln_value, self.track_sum_of_squares);
+            } else if node.is_chance_node() {
+                move_info.hot.replace_expectation(node, self.manager.graph_search());
+            } else if self.manager.graph_search() {
+                move_info.hot.replace_average(*node);
+            } else {
+                move_info.hot.replace(*node);
+            }
             self.manager.on_backpropagation(
-                &evaln,
+                evaln,
                 self.make_handle(tld, node_path));
         }
-        self.manager.on_backpropagation(&evaln, self.make_handle(tld, node_path));
+        self.manager.on_backpropagation(evaln, self.make_handle(tld, node_path));
+    }
+
+    /// Credits every move of every decision node in the playout that the same player made at that
+    /// point or later in the playout.
+    fn update_amaf(&self,
+            node_path: &[&SearchNode<Spec>],
+            players: &[Player<Spec>],
+            playout_data: &mut Spec::PlayoutData,
+            evaln: &StateEvaluation<Spec>) {
+        let history = self.amaf_history(playout_data);
+        self.manager.record_evaluation_moves(evaln, history);
+        let nodes = iter::once(self.root()).chain(node_path.iter().cloned());
+        let mut ply = 0;
+        for (node, player) in nodes.zip(players.iter()) {
+            // Only the players' moves are recorded, so chance nodes take up no ply of the history.
+            if node.is_chance_node() {
+                continue;
+            }
+            let reward = self.eval.interpret_evaluation_for_player(evaln, player).to_f64();
+            for (cold, stats) in node.colds().iter().zip(node.amaf().iter()) {
+                if history.played_since(ply, player, &cold.mov) {
+                    stats.visits.fetch_add(1, Ordering::Relaxed);
+                    stats.sum_rewards.fetch_add(reward);
+                }
+            }
+            ply += 1;
+        }
+    }
+
+    fn amaf_history<'b>(&self, playout_data: &'b mut Spec::PlayoutData) -> &'b mut dyn AmafHistory<Spec> {
+        self.manager.amaf_history(playout_data)
+            .expect("the tree policy needs AMAF statistics, so MCTS::amaf_history must return Some")
+    }
+
+    /// Copies the most-visited part of the tree into a new arena, so that its nodes use at most
+    /// about half of what `limit` leaves after the transposition table, and frees the old arena.
+    /// Children that don't fit are cut off and will be expanded again if the search comes back to
+    /// them. The edge statistics are kept, and they are backed up to incrementally until a child
+    /// expanded again has caught up with them (see `finish_playout`).
+    ///
+    /// Must be called with the playout flag of `tld` lowered.
+    fn compact<'a>(&'a self, tld: &mut ThreadData<'a, Spec>, limit: usize) {
+        let _lock = self.compaction_lock.write().unwrap();
+        if self.memory_in_use_unlocked() <= limit {
+            // Another thread has compacted the tree while we were waiting.
+            return;
+        }
+        self.compacting.store(true, Ordering::SeqCst);
+        for flag in self.playout_flags.lock().unwrap().iter() {
+            while flag.0.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+        }
+        let threshold = self.compaction_threshold(limit.saturating_sub(self.table.memory_usage()) / 2);
+        let new_arena = new_arena(&self.manager);
+        self.table.clear();
+        let mut node_bytes_in_use = 0;
+        let mut num_nodes = 1;
+        {
+            // The new arena is moved into `self.arena` below, so its memory lives as long as `self`.
+            let new_arena: &'a Arena = unsafe { &*(&*new_arena as *const Arena) };
+            let allocator = new_arena.allocator_with_chunk_size(chunk_size(&self.manager));
+            let mut copies: HashMap<*const SearchNode<Spec>, *const SearchNode<Spec>> = HashMap::new();
+            let mut stack = vec![(self.root_node.get(), self.root_state.clone())];
+            while let Some((node, state)) = stack.pop() {
+                let node = unsafe { &mut *node };
+                let num_moves = node.hots().len();
+                node_bytes_in_use += node_bytes(node);
+                let hots: &mut [HotMoveInfo<Spec>] = allocator.alloc_slice(num_moves);
+                let colds: &mut [ColdMoveInfo<Spec>] = allocator.alloc_slice(num_moves);
+                let amaf: &mut [AmafStats] = allocator.alloc_slice(node.amaf().len());
+                let probabilities: &mut [f64] = allocator.alloc_slice(node.probabilities().len());
+                probabilities.copy_from_slice(node.probabilities());
+                let availability: &mut [Availability] = allocator.alloc_slice(node.availability().len());
+                for (x, y) in availability.iter_mut().zip(node.availability()) {
+                    unsafe { ptr::write(x, ptr::read(y)); }
+                }
+                for i in 0..num_moves {
+                    unsafe {
+                        ptr::write(&mut hots[i], ptr::read(&node.hots()[i]));
+                        ptr::write(&mut colds[i], ptr::read(&node.colds()[i]));
+                    }
+                }
+                for (x, y) in amaf.iter_mut().zip(node.amaf()) {
+                    unsafe { ptr::write(x, ptr::read(y)); }
+                }
+                node.hots = hots as *const _ as *const [()];
+                node.colds = colds as *const _ as *const [()];
+                node.amaf = amaf as *const _ as *const [()];
+                node.probabilities = probabilities as *const _ as *const [()];
+                node.availability = availability as *const _ as *const [()];
+                for cold in colds.iter() {
+                    let child = cold.child.load(Ordering::Relaxed) as *const SearchNode<Spec>;
+                    if child.is_null() {
+                        continue;
+                    }
+                    if unsafe { (*child).visits() } < threshold {
+                        cold.child.store(null_mut(), Ordering::Relaxed);
+                        cold.owned.store(false, Ordering::Relaxed);
+                        continue;
+                    }
+                    if let Some(&copy) = copies.get(&child) {
+                        cold.child.store(copy as *mut _, Ordering::Relaxed);
+                        continue;
+                    }
+                    let copy: &'a mut SearchNode<Spec> = allocator.alloc_one();
+                    unsafe { ptr::write(copy, ptr::read(child)); }
+                    copies.insert(child, copy);
+                    cold.child.store(copy, Ordering::Relaxed);
+                    num_nodes += 1;
+                    // The root state is only one determinization of an information set, so the
+                    // move may not be playable in it; such trees aren't put back in the table.
+                    let mut child_state = state.clone();
+                    if self.manager.information_set().is_none() {
+                        child_state.make_move(&cold.mov);
+                        self.table.insert(&child_state, copy, self.make_handle(tld, &[]));
+                    }
+                    stack.push((copy as *mut _, child_state));
+                }
+            }
+        }
+        unsafe {
+            *self.arena.get() = new_arena;
+        }
+        self.arena_generation.fetch_add(1, Ordering::Relaxed);
+        self.node_bytes.store(node_bytes_in_use, Ordering::Relaxed);
+        self.num_nodes.store(num_nodes, Ordering::Relaxed);
+        self.compactions.fetch_add(1, Ordering::Relaxed);
+        self.compacting.store(false, Ordering::SeqCst);
+    }
+
+    /// The smallest visit count such that keeping every node with at least that many visits
+    /// fits in `budget` bytes.
+    fn compaction_threshold(&self, budget: usize) -> u64 {
+        let mut sizes = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![self.root()];
+        let mut total = node_bytes(self.root());
+        while let Some(node) = stack.pop() {
+            for cold in node.colds() {
+                let child = cold.child.load(Ordering::Relaxed) as *const SearchNode<Spec>;
+                if !child.is_null() && seen.insert(child) {
+                    let child = unsafe { &*child };
+                    sizes.push((child.visits(), node_bytes(child)));
+                    stack.push(child);
+                }
+            }
+        }
+        sizes.sort_by(|a, b| b.cmp(a));
+        let mut threshold = u64::MAX;
+        let mut i = 0;
+        while i < sizes.len() {
+            let visits = sizes[i].0;
+            let mut group_bytes = 0;
+            while i < sizes.len() && sizes[i].0 == visits {
+                group_bytes += sizes[i].1;
+                i += 1;
+            }
+            if total + group_bytes > budget {
+                break;
+            }
+            total += group_bytes;
+            threshold = visits;
+        }
+        threshold
     }
 
     fn make_handle<'a, 'b>(&'a self, tld: &'b mut ThreadData<'a, Spec>, path: &'b [&'a SearchNode<Spec>])
@@ -444,20 +1041,24 @@ impl<Spec: MCTS> SearchTree<Spec> {
     pub fn root_state(&self) -> &Spec::State {
         &self.root_state
     }
-    pub fn root_node(&self) -> NodeHandle<Spec> {
+    pub fn root_node(&self) -> NodeHandle<'_, Spec> {
         NodeHandle {
-            node: &self.root_node
+            node: self.root()
         }
     }
 
-    pub fn principal_variation(&self, num_moves: usize) -> Vec<MoveInfoHandle<Spec>> {
+    pub fn principal_variation(&self, num_moves: usize) -> Vec<MoveInfoHandle<'_, Spec>> {
         let mut result = Vec::new();
-        let mut crnt = &self.root_node;
-        while crnt.hots().len() != 0 && result.len() < num_moves {
-            let choice = self.manager.select_child_after_search(&crnt.moves().collect::<Vec<_>>());
+        let mut crnt = self.root();
+        while !crnt.hots().is_empty() && result.len() < num_moves {
+            let moves: Vec<_> = crnt.moves().collect();
+            let choice = match self.manager.gumbel_root() {
+                Some(gumbel) if result.is_empty() => gumbel.select_child_after_search(&moves),
+                _ => self.manager.select_child_after_search(&moves),
+            };
             result.push(choice);
             let child = choice.cold.child.load(Ordering::SeqCst) as *const SearchNode<Spec>;
-            if child == null() {
+            if child.is_null() {
                 break;
             } else {
                 unsafe {
@@ -471,16 +1072,101 @@ impl<Spec: MCTS> SearchTree<Spec> {
     pub fn diagnose(&self) -> String {
         let mut s = String::new();
         s.push_str(&format!("{} nodes\n", thousands_separate(self.num_nodes.load(Ordering::Relaxed))));
+        s.push_str(&format!("{} bytes used by nodes\n", thousands_separate(self.node_bytes.load(Ordering::Relaxed))));
+        s.push_str(&format!("{} bytes in use\n", thousands_separate(self.memory_in_use())));
+        s.push_str(&format!("{} compactions\n", thousands_separate(self.compactions.load(Ordering::Relaxed))));
         s.push_str(&format!("{} transposition table hits\n", thousands_separate(self.transposition_table_hits.load(Ordering::Relaxed))));
         s.push_str(&format!("{} delayed transposition table hits\n", thousands_separate(self.delayed_transposition_table_hits.load(Ordering::Relaxed))));
         s.push_str(&format!("{} expansion contention events\n", thousands_separate(self.expansion_contention_events.load(Ordering::Relaxed))));
+        s.push_str(&self.table.diagnose());
         s
     }
+
+    /// Adds the statistics of the root's edges in `other`, a separate search from the same root
+    /// state, to ours. Only what has changed since the last merge, recorded in `merged`, is added,
+    /// so this can be called repeatedly while both trees are being searched.
+    ///
+    /// Playouts copy an edge's statistics from its child node, so they are added to the child
+    /// as well. An edge whose child hasn't been created yet is left for a later merge, unless
+    /// `at_end` is true; then they are added to the edge only.
+    pub(crate) fn merge_root_statistics(&self, other: &SearchTree<Spec>, merged: &mut Vec<MergedEdge<Spec>>,
+            at_end: bool) {
+        let _compaction_guard = self.manager.memory_limit().map(|_| self.compaction_lock.read().unwrap());
+        let _other_compaction_guard = other.manager.memory_limit().map(|_| other.compaction_lock.read().unwrap());
+        let ours: Vec<_> = self.root().moves().collect();
+        let theirs: Vec<_> = other.root().moves().collect();
+        assert_eq!(ours.len(), theirs.len(), "root-parallel trees have different moves at the root");
+        merged.resize(theirs.len(), MergedEdge::default());
+        for ((our, their), merged) in ours.iter().zip(&theirs).zip(merged.iter_mut()) {
+            let child = unsafe { (our.cold.child.load(Ordering::Relaxed) as *const SearchNode<Spec>).as_ref() };
+            if child.is_none() && !at_end {
+                continue;
+            }
+            let now = MergedEdge::of(their);
+            let visits = (now.visits - merged.visits) as u32;
+            let sum_rewards = now.sum_rewards.sub(merged.sum_rewards);
+            let sum_squares = now.sum_squares - merged.sum_squares;
+            our.hot.add(visits, sum_rewards, sum_squares);
+            if let Some(child) = child {
+                child.add(visits, sum_rewards, sum_squares);
+            }
+            if let (Some(amaf), Some(_)) = (our.amaf, their.amaf) {
+                amaf.visits.fetch_add((now.amaf_visits - merged.amaf_visits) as u32, Ordering::Relaxed);
+                amaf.sum_rewards.fetch_add(now.amaf_sum_rewards - merged.amaf_sum_rewards);
+            }
+            if let (Some(availability), Some(_)) = (our.availability, their.availability) {
+                availability.count.fetch_add((now.availability - merged.availability) as u32, Ordering::Relaxed);
+            }
+            *merged = now;
+        }
+    }
+}
+
+/// The statistics of a root edge of a root-parallel tree that have been added to the main tree.
+pub(crate) struct MergedEdge<Spec: MCTS> {
+    visits: u64,
+    sum_rewards: Spec::Reward,
+    sum_squares: f64,
+    amaf_visits: u64,
+    amaf_sum_rewards: f64,
+    availability: u64,
+}
+
+impl<Spec: MCTS> MergedEdge<Spec> {
+    fn of(edge: &MoveInfoHandle<Spec>) -> Self {
+        Self {
+            visits: edge.visits(),
+            sum_rewards: edge.sum_rewards(),
+            sum_squares: edge.sum_squared_rewards(),
+            amaf_visits: edge.amaf_visits(),
+            amaf_sum_rewards: edge.amaf_sum_rewards(),
+            availability: edge.availability().unwrap_or(0),
+        }
+    }
+}
+
+impl<Spec: MCTS> Clone for MergedEdge<Spec> {
+    fn clone(&self) -> Self {
+        Self {..*self}
+    }
+}
+
+impl<Spec: MCTS> Default for MergedEdge<Spec> {
+    fn default() -> Self {
+        Self {
+            visits: 0,
+            sum_rewards: Spec::Reward::zero(),
+            sum_squares: 0.0,
+            amaf_visits: 0,
+            amaf_sum_rewards: 0.0,
+            availability: 0,
+        }
+    }
 }
 
 impl<Spec: MCTS> SearchTree<Spec> where Move<Spec>: Debug {
     pub fn debug_moves(&self) {
-        let mut moves: Vec<MoveInfoHandle<Spec>> = self.root_node.moves().collect();
+        let mut moves: Vec<MoveInfoHandle<Spec>> = self.root().moves().collect();
         moves.sort_by_key(|x| -(x.visits() as i64));
         for mov in moves {
             println!("{:?}", mov);
@@ -490,7 +1176,7 @@ impl<Spec: MCTS> SearchTree<Spec> where Move<Spec>: Debug {
 
 impl<Spec: MCTS> SearchTree<Spec> where Move<Spec>: Display {
     pub fn display_moves(&self) {
-        let mut moves: Vec<MoveInfoHandle<Spec>> = self.root_node.moves().collect();
+        let mut moves: Vec<MoveInfoHandle<Spec>> = self.root().moves().collect();
         moves.sort_by_key(|x| -(x.visits() as i64));
         for mov in moves {
             println!("{}", mov);
@@ -503,7 +1189,7 @@ pub struct NodeHandle<'a, Spec: 'a + MCTS> {
 }
 impl<'a, Spec: MCTS> Clone for NodeHandle<'a, Spec> {
     fn clone(&self) -> Self {
-        Self {node: self.node}
+        *self
     }
 }
 impl<'a, Spec: MCTS> Copy for NodeHandle<'a, Spec> {}
@@ -512,12 +1198,34 @@ impl<'a, Spec: MCTS> NodeHandle<'a, Spec> {
     pub fn data(&self) -> &'a Spec::NodeData {
         &self.node.data
     }
-    pub fn moves(&self) -> Moves<Spec> {
+    pub fn moves(&self) -> Moves<'a, Spec> {
         self.node.moves()
     }
+    /// The number of playouts that have passed through this node, from any parent.
+    pub fn visits(&self) -> u64 {
+        self.node.visits()
+    }
+    pub fn sum_rewards(&self) -> Spec::Reward {
+        Spec::Reward::load(&self.node.sum_evaluations)
+    }
+    /// Only accumulated if `MCTS::track_sum_of_squares` or `TreePolicy::needs_sum_of_squares` is true.
+    pub fn sum_squared_rewards(&self) -> f64 {
+        self.node.sum_squares.load()
+    }
+    /// Whether the state of this node is waiting for a random move (see `GameState::chance_probabilities`).
+    pub fn is_chance_node(&self) -> bool {
+        self.node.is_chance_node()
+    }
+    /// The probabilities of the moves of a chance node, or an empty slice.
+    pub fn probabilities(&self) -> &'a [f64] {
+        self.node.probabilities()
+    }
     pub fn into_raw(&self) -> *const () {
         self.node as *const _ as *const ()
     }
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` on a node of a tree that is still alive and hasn't been compacted since.
     pub unsafe fn from_raw(ptr: *const ()) -> Self {
         NodeHandle {
             node: &*(ptr as *const SearchNode<Spec>)
@@ -528,12 +1236,17 @@ impl<'a, Spec: MCTS> NodeHandle<'a, Spec> {
 pub struct Moves<'a, Spec: 'a + MCTS> {
     hots: &'a [HotMoveInfo<Spec>],
     colds: &'a [ColdMoveInfo<Spec>],
+    amaf: &'a [AmafStats],
+    availability: &'a [Availability],
+    // In an information-set search, the moves that can be played in the current playout's
+    // determinization; the others are skipped.
+    mask: Option<&'a MoveMask>,
     index: usize,
 }
 
 impl<'a, Spec: MCTS> Clone for Moves<'a, Spec> {
     fn clone(&self) -> Self {
-        Self {hots: self.hots, colds: self.colds, index: self.index}
+        *self
     }
 }
 impl<'a, Spec: MCTS> Copy for Moves<'a, Spec> {}
@@ -541,12 +1254,17 @@ impl<'a, Spec: MCTS> Copy for Moves<'a, Spec> {}
 impl<'a, Spec: 'a + MCTS> Iterator for Moves<'a, Spec> {
     type Item = MoveInfoHandle<'a, Spec>;
     fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.hots.len() && !self.mask.is_none_or(|mask| mask_contains(mask, self.index)) {
+            self.index += 1;
+        }
         if self.index == self.hots.len() {
             None
         } else {
             let handle = unsafe { MoveInfoHandle {
                 hot: self.hots.get_unchecked(self.index),
                 cold: self.colds.get_unchecked(self.index),
+                amaf: self.amaf.get(self.index),
+                availability: self.availability.get(self.index),
             }};
             self.index += 1;
             Some(handle)
@@ -560,9 +1278,7 @@ pub struct SharedSearchHandle<'a: 'b, 'b, Spec: 'a + MCTS> {
 }
 impl<'a: 'b, 'b, Spec: 'a + MCTS> Clone for SharedSearchHandle<'a, 'b, Spec> {
     fn clone(&self) -> Self {
-        let tree = self.tree;
-        let path = self.path;
-        Self {tree, path}
+        *self
     }
 }
 impl<'a: 'b, 'b, Spec: 'a + MCTS> Copy for SharedSearchHandle<'a, 'b, Spec> {}
@@ -646,3 +1362,260 @@ impl<'a> Drop for IncreaseSentinel<'a> {
         self.x.fetch_sub(1, Ordering::Relaxed);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_policy::UCTPolicy;
+    use transposition_table::ApproxTable;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct CountingGame(i64);
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Move {
+        Add, Sub
+    }
+
+    impl GameState for CountingGame {
+        type Move = Move;
+        type Player = ();
+        type MoveList = Vec<Move>;
+
+        fn current_player(&self) -> Self::Player {}
+        fn available_moves(&self) -> Vec<Move> {
+            if self.0 == 100 {
+                vec![]
+            } else {
+                vec![Move::Add, Move::Sub]
+            }
+        }
+        fn make_move(&mut self, mov: &Self::Move) {
+            match *mov {
+                Move::Add => self.0 += 1,
+                Move::Sub => self.0 -= 1,
+            }
+        }
+    }
+
+    impl transposition_table::TranspositionHash for CountingGame {
+        fn hash(&self) -> u64 {
+            self.0 as u64
+        }
+    }
+
+    struct MyEvaluator;
+
+    impl Evaluator<MyMCTS> for MyEvaluator {
+        type StateEvaluation = i64;
+
+        fn evaluate_new_state(&self, state: &CountingGame, moves: &Vec<Move>,
+            _: Option<SearchHandle<MyMCTS>>)
+            -> (Vec<()>, i64) {
+            (vec![(); moves.len()], state.0)
+        }
+        fn interpret_evaluation_for_player(&self, evaln: &i64, _player: &()) -> i64 {
+            *evaln
+        }
+        fn evaluate_existing_state(&self, _: &CountingGame, evaln: &i64, _: SearchHandle<MyMCTS>) -> i64 {
+            *evaln
+        }
+    }
+
+    const MEMORY_LIMIT: usize = 1 << 15;
+
+    #[derive(Default)]
+    struct MyMCTS;
+
+    impl MCTS for MyMCTS {
+        type State = CountingGame;
+        type Reward = i64;
+        type Eval = MyEvaluator;
+        type NodeData = ();
+        type ExtraThreadData = ();
+        type TreePolicy = UCTPolicy;
+        type TranspositionTable = ApproxTable<Self>;
+        type PlayoutData = ();
+
+        fn memory_limit(&self) -> Option<usize> {
+            Some(MEMORY_LIMIT)
+        }
+        fn cycle_behaviour(&self) -> CycleBehaviour<Self> {
+            CycleBehaviour::UseThisEvalWhenCycleDetected(0)
+        }
+    }
+
+    #[test]
+    fn search_continues_within_memory_limit() {
+        let mut mcts = MCTSManager::new(CountingGame(0), MyMCTS, MyEvaluator, UCTPolicy::new(0.5),
+            ApproxTable::new(1024));
+        // A playout may take one more chunk of the arena and finish just above the limit;
+        // the next one compacts the tree.
+        let chunk_size = chunk_size(&MyMCTS);
+        mcts.playout_n(50000);
+        assert!(mcts.tree().compactions.load(Ordering::Relaxed) > 0);
+        assert!(mcts.tree().memory_in_use() <= MEMORY_LIMIT + chunk_size);
+        mcts.playout_n_parallel(50000, 4);
+        assert!(mcts.tree().memory_in_use() <= MEMORY_LIMIT + 4 * chunk_size);
+        assert_eq!(mcts.principal_variation(5), vec![Move::Add; 5]);
+    }
+
+    #[test]
+    fn pruned_edges_keep_their_statistics() {
+        let mut mcts = MCTSManager::new(CountingGame(0), MyMCTS, MyEvaluator, UCTPolicy::new(0.5),
+            ApproxTable::new(1024));
+        mcts.playout_n(1000);
+        let visits = |mcts: &MCTSManager<MyMCTS>| -> Vec<u64> {
+            mcts.tree().root_node().moves().map(|x| x.visits()).collect()
+        };
+        let before = visits(&mcts);
+        {
+            // With no room at all, every child of the root is cut off.
+            let tree = mcts.tree();
+            let mut tld = mcts.thread_data(tree);
+            tree.compact(&mut tld, 0);
+        }
+        assert!(mcts.tree().root_node().moves().all(|x| x.child().is_none()));
+        mcts.playout_n(100);
+        assert!(mcts.tree().root_node().moves().any(|x| x.child().is_some()));
+        let after = visits(&mcts);
+        assert!(before.iter().zip(&after).all(|(x, y)| y >= x), "{:?} then {:?}", before, after);
+        assert_eq!(after.iter().sum::<u64>(), 1100);
+    }
+
+    fn tree_shape(node: NodeHandle<MyMCTS>, depth: usize, shape: &mut Vec<(u64, i64)>) {
+        for mov in node.moves() {
+            shape.push((mov.visits(), mov.sum_rewards()));
+            if let (true, Some(child)) = (depth > 0, mov.child()) {
+                tree_shape(child, depth - 1, shape);
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic_search_builds_the_same_tree() {
+        let search = |seed| {
+            let mut mcts = MCTSManager::new(CountingGame(0), MyMCTS, MyEvaluator, UCTPolicy::new(0.5),
+                ApproxTable::new(1024));
+            mcts.seed(seed).deterministic(true);
+            mcts.playout_n_parallel(20000, 4);
+            let mut shape = Vec::new();
+            tree_shape(mcts.tree().root_node(), 8, &mut shape);
+            shape
+        };
+        assert_eq!(search(1), search(1));
+        assert!(search(1) != search(2));
+    }
+
+    #[test]
+    fn root_parallel_trees_are_merged_into_the_main_tree() {
+        let new_manager = || MCTSManager::new(CountingGame(0), MyMCTS, MyEvaluator, UCTPolicy::new(0.5),
+            ApproxTable::new(1024));
+        let make_tree = || (MyMCTS, MyEvaluator, UCTPolicy::new(0.5), ApproxTable::new(1024));
+        for &merge_interval in &[None, Some(100)] {
+            let mut mcts = new_manager();
+            mcts.root_parallel(4, make_tree);
+            mcts.playout_n_root_parallel(20000, merge_interval);
+            // Every playout of every tree is counted once at the root of the main tree.
+            let visits: u64 = mcts.tree().root_node().moves().map(|x| x.visits()).sum();
+            assert_eq!(visits, 20000);
+            assert_eq!(mcts.best_move(), Some(Move::Add));
+            // Merging doesn't touch the other trees.
+            let other = &mcts.root_parallel_trees[1].tree;
+            assert_eq!(other.root_node().moves().map(|x| x.visits()).sum::<u64>(), 5000);
+        }
+    }
+
+    /// Choosing `Gamble` leads to a chance node that pays 1 with probability 0.3 and 0 otherwise,
+    /// which is worse than the 0.4 that `Safe` pays.
+    #[derive(Clone, Debug, PartialEq)]
+    enum Gamble {
+        Start, Rolling, Done(f64)
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum GambleMove {
+        Safe, Gamble, Outcome(usize)
+    }
+
+    impl GameState for Gamble {
+        type Move = GambleMove;
+        type Player = ();
+        type MoveList = Vec<GambleMove>;
+
+        fn current_player(&self) {}
+        fn available_moves(&self) -> Vec<GambleMove> {
+            match *self {
+                Gamble::Start => vec![GambleMove::Safe, GambleMove::Gamble],
+                Gamble::Rolling => vec![GambleMove::Outcome(0), GambleMove::Outcome(1)],
+                Gamble::Done(_) => vec![],
+            }
+        }
+        fn make_move(&mut self, mov: &GambleMove) {
+            *self = match *mov {
+                GambleMove::Safe => Gamble::Done(0.4),
+                GambleMove::Gamble => Gamble::Rolling,
+                GambleMove::Outcome(0) => Gamble::Done(1.0),
+                GambleMove::Outcome(_) => Gamble::Done(0.0),
+            };
+        }
+        fn chance_probabilities(&self) -> Option<Vec<f64>> {
+            match *self {
+                Gamble::Rolling => Some(vec![0.3, 0.7]),
+                _ => None,
+            }
+        }
+    }
+
+    struct GambleEvaluator;
+
+    impl Evaluator<GambleMCTS> for GambleEvaluator {
+        type StateEvaluation = f64;
+
+        fn evaluate_new_state(&self, state: &Gamble, moves: &Vec<GambleMove>,
+            _: Option<SearchHandle<GambleMCTS>>)
+            -> (Vec<()>, f64) {
+            let evaln = match *state {
+                Gamble::Done(x) => x,
+                // A misleading evaluation, which the statistics of the outcomes should replace.
+                _ => 0.9,
+            };
+            (vec![(); moves.len()], evaln)
+        }
+        fn interpret_evaluation_for_player(&self, evaln: &f64, _player: &()) -> f64 {
+            *evaln
+        }
+        fn evaluate_existing_state(&self, _: &Gamble, evaln: &f64, _: SearchHandle<GambleMCTS>) -> f64 {
+            *evaln
+        }
+    }
+
+    #[derive(Default)]
+    struct GambleMCTS;
+
+    impl MCTS for GambleMCTS {
+        type State = Gamble;
+        type Reward = f64;
+        type Eval = GambleEvaluator;
+        type NodeData = ();
+        type ExtraThreadData = ();
+        type TreePolicy = UCTPolicy;
+        type TranspositionTable = ();
+        type PlayoutData = ();
+    }
+
+    #[test]
+    fn chance_nodes_back_up_expectations() {
+        let mut mcts = MCTSManager::new(Gamble::Start, GambleMCTS, GambleEvaluator, UCTPolicy::new(2.0), ());
+        mcts.playout_n(2000);
+        assert_eq!(mcts.best_move(), Some(GambleMove::Safe));
+        let gamble = mcts.tree().root_node().moves().nth(1).unwrap();
+        assert!((gamble.average_reward().unwrap() - 0.3).abs() < 1e-6);
+        let rolling = gamble.child().unwrap();
+        assert!(rolling.is_chance_node());
+        let outcomes: Vec<_> = rolling.moves().map(|x| x.visits()).collect();
+        let frequency = outcomes[0] as f64 / (outcomes[0] + outcomes[1]) as f64;
+        assert!(outcomes[0] + outcomes[1] >= 100);
+        assert!((frequency - 0.3).abs() < 0.1, "first outcome sampled {:?} of the time", frequency);
+    }
+}
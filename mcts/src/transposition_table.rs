@@ -1,7 +1,11 @@
 use super::*;
 use search_tree::*;
 use atomics::*;
+use std::sync::Mutex;
 
+/// # Safety
+///
+/// Implementations must follow the rules documented on `insert`; the search relies on them for memory safety.
 pub unsafe trait TranspositionTable<Spec: MCTS>: Sync + Sized {
     /// **If this function inserts a value, it must return `None`.** Failure to follow
     /// this rule will lead to memory safety violation.
@@ -31,6 +35,27 @@ pub unsafe trait TranspositionTable<Spec: MCTS>: Sync + Sized {
     /// to the associated value.
     fn lookup<'a>(&'a self, key: &Spec::State, handle: SearchHandle<Spec>)
             -> Option<&'a SearchNode<Spec>>;
+
+    /// Removes every entry. Called when the nodes the table points to are about to be freed,
+    /// so after this returns, `lookup` **must not** return any previously inserted value.
+    ///
+    /// The search frees nodes when the tree is compacted (see `MCTS::memory_limit`) and when the
+    /// manager is reset. The default implementation panics, so a table that doesn't override it
+    /// can't be used with a memory limit; `MCTSManager::new` checks this up front.
+    fn clear(&self) {
+        panic!("compaction unsupported: the transposition table doesn't implement `clear`");
+    }
+
+    /// The number of bytes the table takes up, which counts towards `MCTS::memory_limit`.
+    /// Defaults to 0.
+    fn memory_usage(&self) -> usize {
+        0
+    }
+
+    /// Statistics appended to `SearchTree::diagnose`. Defaults to an empty string.
+    fn diagnose(&self) -> String {
+        String::new()
+    }
 }
 
 unsafe impl<Spec: MCTS<TranspositionTable=Self>> TranspositionTable<Spec> for () {
@@ -43,10 +68,18 @@ unsafe impl<Spec: MCTS<TranspositionTable=Self>> TranspositionTable<Spec> for ()
             -> Option<&'a SearchNode<Spec>> {
         None
     }
+
+    fn clear(&self) {}
 }
 
 pub trait TranspositionHash {
     fn hash(&self) -> u64;
+
+    /// A second hash, independent of `hash`, which `ReplacementTable` stores alongside each entry
+    /// to tell apart states whose `hash` collides. Defaults to 0, which disables the check.
+    fn verification_key(&self) -> u64 {
+        0
+    }
 }
 
 pub struct ApproxQuadraticProbingHashTable<K: TranspositionHash, V> {
@@ -105,15 +138,19 @@ pub type ApproxTable<Spec> =
          ApproxQuadraticProbingHashTable<<Spec as MCTS>::State, SearchNode<Spec>>;
 
 fn get_or_write<'a, V>(ptr: &AtomicPtr<V>, v: &'a V) -> Option<&'a V> {
-    let result = ptr.compare_and_swap(
+    let result = ptr.compare_exchange(
         std::ptr::null_mut(),
         v as *const _ as *mut _,
+        Ordering::Relaxed,
         Ordering::Relaxed);
-    convert(result)
+    match result {
+        Ok(_) => None,
+        Err(existing) => convert(existing),
+    }
 }
 
 fn convert<'a, V>(ptr: *const V) -> Option<&'a V> {
-    if ptr == std::ptr::null() {
+    if ptr.is_null() {
         None
     } else {
         unsafe { Some(&*ptr) }
@@ -137,18 +174,20 @@ unsafe impl<Spec> TranspositionTable<Spec> for ApproxTable<Spec>
         let mut posn = my_hash as usize & self.mask;
         for inc in 1..(PROBE_LIMIT + 1) {
             let entry = unsafe { self.arr.get_unchecked(posn) };
-            let key_here = entry.k.load(Ordering::Relaxed) as u64;
+            let key_here = entry.k.load(Ordering::Relaxed);
             if key_here == my_hash {
                 let value_here = entry.v.load(Ordering::Relaxed);
-                if value_here != std::ptr::null_mut() {
+                if !value_here.is_null() {
                     return unsafe { Some(&*value_here) };
                 }
                 return get_or_write(&entry.v, value);
             }
             if key_here == 0 {
-                let key_here = entry.k.compare_and_swap(0, my_hash as FakeU64, Ordering::Relaxed);
+                let key_here = match entry.k.compare_exchange(0, my_hash, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(old) | Err(old) => old,
+                };
                 self.size.fetch_add(1, Ordering::Relaxed);
-                if key_here == 0 || key_here == my_hash as FakeU64 {
+                if key_here == 0 || key_here == my_hash {
                     return get_or_write(&entry.v, value);
                 }
             }
@@ -163,7 +202,7 @@ unsafe impl<Spec> TranspositionTable<Spec> for ApproxTable<Spec>
         let mut posn = my_hash as usize & self.mask;
         for inc in 1..(PROBE_LIMIT + 1) {
             let entry = unsafe { self.arr.get_unchecked(posn) };
-            let key_here = entry.k.load(Ordering::Relaxed) as u64;
+            let key_here = entry.k.load(Ordering::Relaxed);
             if key_here == my_hash {
                 return convert(entry.v.load(Ordering::Relaxed));
             }
@@ -175,4 +214,292 @@ unsafe impl<Spec> TranspositionTable<Spec> for ApproxTable<Spec>
         }
         None
     }
+    fn clear(&self) {
+        for entry in self.arr.iter() {
+            entry.v.store(std::ptr::null_mut(), Ordering::Relaxed);
+            entry.k.store(0, Ordering::Relaxed);
+        }
+        self.size.store(0, Ordering::Relaxed);
+    }
+    fn memory_usage(&self) -> usize {
+        self.arr.len() * std::mem::size_of::<Entry16<Spec::State, SearchNode<Spec>>>()
+    }
+}
+
+const BUCKET_SIZE: usize = 4;
+
+struct Slot<V> {
+    key: u64,
+    verification_key: u64,
+    value: *const V,
+}
+
+impl<V> Clone for Slot<V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<V> Copy for Slot<V> {}
+
+impl<V> Default for Slot<V> {
+    fn default() -> Self {
+        Self {key: 0, verification_key: 0, value: std::ptr::null()}
+    }
+}
+
+/// A transposition table made of small buckets. Unlike `ApproxQuadraticProbingHashTable`, it
+/// checks `TranspositionHash::verification_key` before returning an entry, accepts a hash of 0,
+/// and keeps inserting once full by evicting the least-visited node in the bucket.
+///
+/// Evicted nodes stay in the search tree; they just can't be found through the table any more.
+pub struct ReplacementHashTable<K: TranspositionHash, V> {
+    buckets: Box<[Mutex<[Slot<V>; BUCKET_SIZE]>]>,
+    mask: usize,
+    occupied: AtomicUsize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    verification_failures: AtomicUsize,
+    replacements: AtomicUsize,
+    _marker: std::marker::PhantomData<K>,
+}
+
+impl<K: TranspositionHash, V> ReplacementHashTable<K, V> {
+    /// `capacity` is the number of entries, and must be a power of 2 and at least `BUCKET_SIZE`.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity.count_ones() == 1, "the capacity must be a power of 2");
+        assert!(capacity >= BUCKET_SIZE, "the capacity must be at least {}", BUCKET_SIZE);
+        let num_buckets = capacity / BUCKET_SIZE;
+        let buckets = (0..num_buckets)
+            .map(|_| Mutex::new([Slot::default(); BUCKET_SIZE]))
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+        Self {
+            buckets,
+            mask: num_buckets - 1,
+            occupied: AtomicUsize::default(),
+            hits: AtomicUsize::default(),
+            misses: AtomicUsize::default(),
+            verification_failures: AtomicUsize::default(),
+            replacements: AtomicUsize::default(),
+            _marker: Default::default(),
+        }
+    }
+    pub fn enough_to_hold(num: usize) -> Self {
+        let mut capacity = BUCKET_SIZE;
+        while capacity < num {
+            capacity <<= 1;
+        }
+        Self::new(capacity)
+    }
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * BUCKET_SIZE
+    }
+    /// Finds the slot holding `(key, verification_key)`, counting any entry that only matches
+    /// `key` as a verification failure.
+    fn find(&self, slots: &[Slot<V>; BUCKET_SIZE], key: u64, verification_key: u64) -> Option<*const V> {
+        for slot in slots.iter() {
+            if slot.value.is_null() || slot.key != key {
+                continue;
+            }
+            if slot.verification_key == verification_key {
+                return Some(slot.value);
+            }
+            self.verification_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        None
+    }
+}
+
+unsafe impl<K: TranspositionHash, V> Sync for ReplacementHashTable<K, V> {}
+unsafe impl<K: TranspositionHash, V> Send for ReplacementHashTable<K, V> {}
+
+pub type ReplacementTable<Spec> =
+         ReplacementHashTable<<Spec as MCTS>::State, SearchNode<Spec>>;
+
+unsafe impl<Spec> TranspositionTable<Spec> for ReplacementTable<Spec>
+    where Spec::State: TranspositionHash, Spec: MCTS
+{
+    fn insert<'a>(&'a self, key: &Spec::State, value: &'a SearchNode<Spec>,
+            _: SearchHandle<Spec>) -> Option<&'a SearchNode<Spec>> {
+        let my_hash = key.hash();
+        let my_verification_key = key.verification_key();
+        let bucket = unsafe { self.buckets.get_unchecked(my_hash as usize & self.mask) };
+        let mut slots = bucket.lock().unwrap();
+        if let Some(existing) = self.find(&slots, my_hash, my_verification_key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return convert(existing);
+        }
+        let victim = {
+            let mut victim = 0;
+            let mut victim_visits = u64::MAX;
+            for (i, slot) in slots.iter().enumerate() {
+                if slot.value.is_null() {
+                    victim = i;
+                    break;
+                }
+                let visits = unsafe { (*slot.value).visits() };
+                if visits < victim_visits {
+                    victim = i;
+                    victim_visits = visits;
+                }
+            }
+            victim
+        };
+        if slots[victim].value.is_null() {
+            self.occupied.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.replacements.fetch_add(1, Ordering::Relaxed);
+        }
+        slots[victim] = Slot {
+            key: my_hash,
+            verification_key: my_verification_key,
+            value,
+        };
+        None
+    }
+    fn lookup<'a>(&'a self, key: &Spec::State, _: SearchHandle<Spec>)
+            -> Option<&'a SearchNode<Spec>> {
+        let my_hash = key.hash();
+        let bucket = unsafe { self.buckets.get_unchecked(my_hash as usize & self.mask) };
+        let slots = bucket.lock().unwrap();
+        match self.find(&slots, my_hash, key.verification_key()) {
+            Some(existing) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                convert(existing)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+    fn clear(&self) {
+        for bucket in self.buckets.iter() {
+            *bucket.lock().unwrap() = [Slot::default(); BUCKET_SIZE];
+        }
+        self.occupied.store(0, Ordering::Relaxed);
+    }
+    fn memory_usage(&self) -> usize {
+        self.buckets.len() * std::mem::size_of::<Mutex<[Slot<SearchNode<Spec>>; BUCKET_SIZE]>>()
+    }
+    fn diagnose(&self) -> String {
+        let occupied = self.occupied.load(Ordering::Relaxed);
+        let mut s = String::new();
+        s.push_str(&format!("{} table entries ({:.1}% full)\n",
+            thousands_separate(occupied),
+            100.0 * occupied as f64 / self.capacity() as f64));
+        s.push_str(&format!("{} table hits\n", thousands_separate(self.hits.load(Ordering::Relaxed))));
+        s.push_str(&format!("{} table misses\n", thousands_separate(self.misses.load(Ordering::Relaxed))));
+        s.push_str(&format!("{} table verification failures\n", thousands_separate(self.verification_failures.load(Ordering::Relaxed))));
+        s.push_str(&format!("{} table replacements\n", thousands_separate(self.replacements.load(Ordering::Relaxed))));
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_policy::UCTPolicy;
+
+    // The counting game from the crate documentation, but with a hash that collides constantly.
+    #[derive(Clone, Debug, PartialEq)]
+    struct CountingGame(i64);
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Move {
+        Add, Sub
+    }
+
+    impl GameState for CountingGame {
+        type Move = Move;
+        type Player = ();
+        type MoveList = Vec<Move>;
+
+        fn current_player(&self) -> Self::Player {}
+        fn available_moves(&self) -> Vec<Move> {
+            if self.0 == 100 {
+                vec![]
+            } else {
+                vec![Move::Add, Move::Sub]
+            }
+        }
+        fn make_move(&mut self, mov: &Self::Move) {
+            match *mov {
+                Move::Add => self.0 += 1,
+                Move::Sub => self.0 -= 1,
+            }
+        }
+    }
+
+    impl TranspositionHash for CountingGame {
+        fn hash(&self) -> u64 {
+            (self.0 & 3) as u64
+        }
+        fn verification_key(&self) -> u64 {
+            self.0 as u64
+        }
+    }
+
+    struct MyEvaluator;
+
+    impl Evaluator<MyMCTS> for MyEvaluator {
+        type StateEvaluation = i64;
+
+        fn evaluate_new_state(&self, state: &CountingGame, moves: &Vec<Move>,
+            _: Option<SearchHandle<MyMCTS>>)
+            -> (Vec<()>, i64) {
+            (vec![(); moves.len()], state.0)
+        }
+        fn interpret_evaluation_for_player(&self, evaln: &i64, _player: &()) -> i64 {
+            *evaln
+        }
+        fn evaluate_existing_state(&self, _: &CountingGame, evaln: &i64, _: SearchHandle<MyMCTS>) -> i64 {
+            *evaln
+        }
+    }
+
+    #[derive(Default)]
+    struct MyMCTS;
+
+    impl MCTS for MyMCTS {
+        type State = CountingGame;
+        type Reward = i64;
+        type Eval = MyEvaluator;
+        type NodeData = ();
+        type ExtraThreadData = ();
+        type TreePolicy = UCTPolicy;
+        type TranspositionTable = ReplacementTable<Self>;
+        type PlayoutData = ();
+
+        fn cycle_behaviour(&self) -> CycleBehaviour<Self> {
+            CycleBehaviour::UseThisEvalWhenCycleDetected(0)
+        }
+    }
+
+    fn search(capacity: usize) -> MCTSManager<MyMCTS> {
+        let mut mcts = MCTSManager::new(CountingGame(0), MyMCTS, MyEvaluator, UCTPolicy::new(0.5),
+            ReplacementTable::new(capacity));
+        mcts.playout_n(10000);
+        mcts
+    }
+
+    #[test]
+    fn verification_key_rejects_collisions() {
+        let mcts = search(1024);
+        assert_eq!(mcts.principal_variation(20), vec![Move::Add; 20]);
+        let states = mcts.principal_variation_states(20);
+        assert_eq!(states.last(), Some(&CountingGame(20)));
+        let diagnosis = mcts.tree().diagnose();
+        assert!(!diagnosis.contains("\n0 table verification failures"), "{}", diagnosis);
+        assert!(!diagnosis.contains("\n0 table hits"), "{}", diagnosis);
+    }
+
+    #[test]
+    fn full_table_replaces_entries() {
+        let mcts = search(4);
+        assert_eq!(mcts.principal_variation(20), vec![Move::Add; 20]);
+        let diagnosis = mcts.tree().diagnose();
+        assert!(diagnosis.contains("4 table entries (100.0% full)"), "{}", diagnosis);
+        assert!(!diagnosis.contains("\n0 table replacements"), "{}", diagnosis);
+    }
 }
@@ -1,7 +1,6 @@
 extern crate rand;
 use self::rand::{XorShiftRng, Rng, SeedableRng};
 
-use std;
 use super::*;
 use search_tree::*;
 
@@ -15,11 +14,16 @@ impl From<f32> for Fraction {
 
 pub trait TreePolicy<Spec: MCTS<TreePolicy=Self>>: Sync + Sized {
     type MoveEvaluation: Sync + Send;
-    type ThreadLocalData: Default;
+    type ThreadLocalData: Default + SeedableThreadData;
 
     fn choose_child<'a>(&self, state: &Spec::State, moves: Moves<'a, Spec>, handle: SearchHandle<Spec>)
         -> MoveInfoHandle<'a, Spec>;
     fn validate_evaluations(&self, _evalns: &[Self::MoveEvaluation]) {}
+    /// Whether the policy reads `sum_squared_rewards`, so the tree has to accumulate it.
+    fn needs_sum_of_squares(&self) -> bool { false }
+    /// Whether the policy reads `amaf_visits` and `amaf_sum_rewards`, so the tree has to record them.
+    /// If so, `MCTS::amaf_history` must return `Some`.
+    fn needs_amaf_statistics(&self) -> bool { false }
     fn reset(self) -> Self { self } // TODO put this on everything
 }
 
@@ -43,16 +47,259 @@ impl UCTPolicy {
 
 #[derive(Clone, Debug)]
 pub struct AlphaGoPolicy {
-    exploration_constant: f32,
+    root: PUCTParameters,
+    non_root: PUCTParameters,
+}
+
+/// The parameters of `AlphaGoPolicy` at one kind of node. `PUCTParameters::new(c)` gives
+/// the original AlphaGo formula: a constant `c`, and a value of 0 for unvisited children.
+#[derive(Clone, Debug)]
+pub struct PUCTParameters {
+    pub exploration_constant: f32,
+    /// The exploration constant grows to
+    /// `exploration_constant + cpuct_factor * ln((N + cpuct_base) / cpuct_base)` after `N` visits.
+    pub cpuct_factor: f32,
+    pub cpuct_base: f32,
+    /// If `Some(r)`, unvisited children get the value of their parent minus
+    /// `r * sqrt(sum of the move evaluations of visited children)` (first-play urgency reduction),
+    /// instead of 0.
+    pub fpu_reduction: Option<f32>,
+}
+
+impl PUCTParameters {
+    pub fn new(exploration_constant: f32) -> Self {
+        Self {
+            exploration_constant,
+            cpuct_factor: 0.0,
+            cpuct_base: 19652.0,
+            fpu_reduction: None,
+        }
+    }
+
+    fn cpuct(&self, visits: u64) -> f32 {
+        if self.cpuct_factor == 0.0 {
+            self.exploration_constant
+        } else {
+            let growth = ((visits as f32 + self.cpuct_base) / self.cpuct_base).ln();
+            self.exploration_constant + self.cpuct_factor * growth
+        }
+    }
 }
 
 impl AlphaGoPolicy {
     pub fn new(exploration_constant: f32) -> Self {
-        Self {exploration_constant}
+        let parameters = PUCTParameters::new(exploration_constant);
+        Self::with_parameters(parameters.clone(), parameters)
+    }
+    pub fn with_parameters(root: PUCTParameters, non_root: PUCTParameters) -> Self {
+        Self {root, non_root}
+    }
+    /// The exploration constant at non-root nodes.
+    pub fn exploration_constant(&self) -> f32 {
+        self.non_root.exploration_constant
     }
+    pub fn root_parameters(&self) -> &PUCTParameters {
+        &self.root
+    }
+    pub fn non_root_parameters(&self) -> &PUCTParameters {
+        &self.non_root
+    }
+}
+
+/// UCB1-Tuned (Auer et al. 2002): like UCT, but the exploration term of each child is scaled by
+/// an upper bound on the variance of its rewards, so children with consistent rewards are explored less.
+#[derive(Clone, Debug)]
+pub struct UCB1TunedPolicy {
+    exploration_constant: f32,
+    reward_range: f32,
+}
+
+impl UCB1TunedPolicy {
+    /// `reward_range` is the difference between the largest and smallest possible reward
+    /// (1 for rewards in [0, 1]). With an exploration constant of 1 this is the textbook formula.
+    pub fn new(exploration_constant: f32, reward_range: f32) -> Self {
+        assert!(exploration_constant > 0.0,
+            "exploration constant is {} (must be positive)",
+            exploration_constant);
+        assert!(reward_range > 0.0,
+            "reward range is {} (must be positive)",
+            reward_range);
+        Self {exploration_constant, reward_range}
+    }
+
     pub fn exploration_constant(&self) -> f32 {
         self.exploration_constant
     }
+
+    pub fn reward_range(&self) -> f32 {
+        self.reward_range
+    }
+
+    /// The selection rule, usable from any `MCTS` whose tree tracks sums of squares.
+    pub fn select_child<'a, Spec: MCTS>(&self, moves: Moves<'a, Spec>, rng: &mut PolicyRng)
+        -> MoveInfoHandle<'a, Spec>
+    {
+        let total_visits = moves.map(|x| x.visits()).sum::<u64>();
+        let ln_adjusted_total = ((total_visits + 1) as f64).ln();
+        let range = self.reward_range as f64;
+        rng.select_by_key(moves, |mov| {
+            let child_visits = mov.visits();
+            if child_visits == 0 {
+                return f32::INFINITY.into();
+            }
+            let n = child_visits as f64;
+            let mean = mov.sum_rewards().to_f64() / n;
+            let variance = reward_variance(mov) / (range * range);
+            let variance_bound = (variance + (2.0 * ln_adjusted_total / n).sqrt()).min(0.25);
+            let explore_term = range * (ln_adjusted_total / n * variance_bound).sqrt();
+            ((mean + self.exploration_constant as f64 * explore_term) as f32).into()
+        }).unwrap()
+    }
+}
+
+/// A version of `AlphaGoPolicy` where the exploration term of each child is multiplied by
+/// the ratio of its reward standard deviation to `sqrt(prior_variance)`. The variance estimate
+/// starts at `prior_variance` and moves towards the observed variance as the child is visited,
+/// so with a well-chosen prior the exploration constant means the same as in `AlphaGoPolicy`.
+#[derive(Clone, Debug)]
+pub struct VariancePUCTPolicy {
+    exploration_constant: f32,
+    prior_variance: f32,
+}
+
+impl VariancePUCTPolicy {
+    pub fn new(exploration_constant: f32, prior_variance: f32) -> Self {
+        assert!(prior_variance > 0.0,
+            "prior variance is {} (must be positive)",
+            prior_variance);
+        Self {exploration_constant, prior_variance}
+    }
+
+    pub fn exploration_constant(&self) -> f32 {
+        self.exploration_constant
+    }
+
+    pub fn prior_variance(&self) -> f32 {
+        self.prior_variance
+    }
+
+    /// The selection rule, usable from any `MCTS` with `f32` move evaluations whose tree tracks sums of squares.
+    pub fn select_child<'a, Spec: MCTS>(&self, moves: Moves<'a, Spec>, rng: &mut PolicyRng)
+        -> MoveInfoHandle<'a, Spec>
+        where Spec::TreePolicy: TreePolicy<Spec, MoveEvaluation=f32>
+    {
+        let total_visits = moves.map(|x| x.visits()).sum::<u64>() + 1;
+        let sqrt_total_visits = (total_visits as f32).sqrt();
+        let explore_coef = self.exploration_constant * sqrt_total_visits;
+        let prior_variance = self.prior_variance as f64;
+        rng.select_by_key(moves, |mov| {
+            let sum_rewards = mov.sum_rewards().to_f64() as f32;
+            let child_visits = mov.visits();
+            let policy_evaln = *mov.move_evaluation();
+            let n = child_visits as f64;
+            let variance = (prior_variance + n * reward_variance(mov)) / (n + 1.0);
+            let deviation_ratio = (variance / prior_variance).sqrt() as f32;
+            Fraction(
+                sum_rewards + explore_coef * policy_evaln * deviation_ratio,
+                (child_visits + 1) as f32)
+        }).unwrap()
+    }
+}
+
+/// UCT with RAVE (Gelly and Silver 2007), for games without a prior. The value of each child
+/// blends its average reward with its all-moves-as-first average, weighting the latter by
+/// `beta = sqrt(k / (3n + k))` after `n` visits, where `k` is the equivalence parameter.
+/// Needs AMAF statistics (see the `rave` module).
+#[derive(Clone, Debug)]
+pub struct RAVEPolicy {
+    exploration_constant: f32,
+    equivalence: f32,
+}
+
+impl RAVEPolicy {
+    pub fn new(exploration_constant: f32, equivalence: f32) -> Self {
+        assert!(exploration_constant >= 0.0,
+            "exploration constant is {} (must be non-negative)",
+            exploration_constant);
+        assert!(equivalence > 0.0,
+            "equivalence parameter is {} (must be positive)",
+            equivalence);
+        Self {exploration_constant, equivalence}
+    }
+
+    pub fn exploration_constant(&self) -> f32 {
+        self.exploration_constant
+    }
+
+    pub fn equivalence(&self) -> f32 {
+        self.equivalence
+    }
+}
+
+impl<Spec: MCTS<TreePolicy=Self>> TreePolicy<Spec> for RAVEPolicy
+{
+    type ThreadLocalData = PolicyRng;
+    type MoveEvaluation = ();
+
+    fn choose_child<'a>(&self, _: &Spec::State, moves: Moves<'a, Spec>, mut handle: SearchHandle<Spec>)
+        -> MoveInfoHandle<'a, Spec>
+    {
+        let total_visits = moves.map(|x| x.visits()).sum::<u64>();
+        let ln_adjusted_total = ((total_visits + 1) as f64).ln();
+        let k = self.equivalence as f64;
+        handle.thread_data().policy_data.select_by_key(moves, |mov| {
+            let child_visits = mov.visits();
+            let amaf_visits = mov.amaf_visits();
+            if child_visits == 0 && amaf_visits == 0 {
+                return f32::INFINITY.into();
+            }
+            let n = child_visits as f64;
+            let (mean, beta) = if child_visits == 0 {
+                (0.0, 1.0)
+            } else {
+                (mov.sum_rewards().to_f64() / n, (k / (3.0 * n + k)).sqrt())
+            };
+            let amaf_mean = if amaf_visits == 0 {
+                mean
+            } else {
+                mov.amaf_sum_rewards() / amaf_visits as f64
+            };
+            let value = (1.0 - beta) * mean + beta * amaf_mean;
+            let explore_term = (ln_adjusted_total / (n + 1.0)).sqrt();
+            ((value + self.exploration_constant as f64 * explore_term) as f32).into()
+        }).unwrap()
+    }
+
+    fn needs_amaf_statistics(&self) -> bool {
+        true
+    }
+}
+
+/// The observed variance of a child's rewards, or 0 if it hasn't been visited.
+fn reward_variance<Spec: MCTS>(mov: &MoveInfoHandle<Spec>) -> f64 {
+    let visits = mov.visits();
+    if visits == 0 {
+        return 0.0;
+    }
+    let n = visits as f64;
+    let mean = mov.sum_rewards().to_f64() / n;
+    // Virtual loss can make this slightly negative while other threads are in the subtree.
+    (mov.sum_squared_rewards() / n - mean * mean).max(0.0)
+}
+
+/// Checks that move evaluations form a probability distribution, as `AlphaGoPolicy` expects.
+pub fn validate_probabilities(evalns: &[f32]) {
+    for &x in evalns {
+        assert!(x >= -1e-6,
+            "Move evaluation is {} (must be non-negative)",
+            x);
+    }
+    if !evalns.is_empty() {
+        let evaln_sum: f32 = evalns.iter().sum();
+        assert!((evaln_sum - 1.0).abs() < 0.1,
+            "Sum of evaluations is {} (should sum to 1)",
+            evaln_sum);
+    }
 }
 
 impl<Spec: MCTS<TreePolicy=Self>> TreePolicy<Spec> for UCTPolicy
@@ -66,54 +313,126 @@ impl<Spec: MCTS<TreePolicy=Self>> TreePolicy<Spec> for UCTPolicy
         let total_visits = moves.map(|x| x.visits()).sum::<u64>();
         let adjusted_total = (total_visits + 1) as f32;
         let ln_adjusted_total = adjusted_total.ln();
+        let graph_search = handle.mcts().graph_search();
         handle.thread_data().policy_data.select_by_key(moves, |mov| {
-            let sum_rewards = mov.sum_rewards();
+            let sum_rewards = mov.sum_rewards().to_f64() as f32;
             let child_visits = mov.visits();
+            // In an information-set search, the move wasn't available in every playout through the parent.
+            let ln_total = match mov.availability() {
+                Some(availability) => (availability as f32).ln(),
+                None => ln_adjusted_total,
+            };
             // http://mcts.ai/pubs/mcts-survey-master.pdf
             let explore_term = if child_visits == 0 {
-                std::f32::INFINITY
+                f32::INFINITY
             } else {
-                2.0 * (ln_adjusted_total / child_visits as f32).sqrt()
+                2.0 * (ln_total / child_visits as f32).sqrt()
+            };
+            // With graph backups an edge's visits can outgrow its parent's, so only the edge's own
+            // mean stays in range.
+            let mean_action_value = if graph_search && child_visits != 0 {
+                sum_rewards / child_visits as f32
+            } else {
+                sum_rewards / adjusted_total
             };
-            let mean_action_value = sum_rewards as f32 / adjusted_total;
             (self.exploration_constant * explore_term + mean_action_value).into()
         }).unwrap()
     }
 }
 
-impl<Spec: MCTS<TreePolicy=Self>> TreePolicy<Spec> for AlphaGoPolicy
-{
-    type ThreadLocalData = PolicyRng;
-    type MoveEvaluation = f32;
-
-    fn choose_child<'a>(&self, _: &Spec::State, moves: Moves<'a, Spec>, mut handle: SearchHandle<Spec>)
+impl AlphaGoPolicy {
+    /// The selection rule, usable from any `MCTS` with `f32` move evaluations.
+    pub fn select_child<'a, Spec: MCTS>(&self, moves: Moves<'a, Spec>, rng: &mut PolicyRng, is_root: bool)
         -> MoveInfoHandle<'a, Spec>
+        where Spec::TreePolicy: TreePolicy<Spec, MoveEvaluation=f32>
     {
-        let total_visits = moves.map(|x| x.visits()).sum::<u64>() + 1;
+        let params = if is_root { &self.root } else { &self.non_root };
+        let child_visits_total = moves.map(|x| x.visits()).sum::<u64>();
+        let total_visits = child_visits_total + 1;
         let sqrt_total_visits = (total_visits as f32).sqrt();
-        let explore_coef = self.exploration_constant * sqrt_total_visits;
-        handle.thread_data().policy_data.select_by_key(moves, |mov| {
-            let sum_rewards = mov.sum_rewards() as f32;
+        let explore_coef = params.cpuct(child_visits_total) * sqrt_total_visits;
+        let first_play_value = params.fpu_reduction.map(|reduction| first_play_value(moves, reduction));
+        rng.select_by_key(moves, |mov| {
             let child_visits = mov.visits();
-            let policy_evaln = *mov.move_evaluation() as f32;
+            let sum_rewards = match first_play_value {
+                Some(x) if child_visits == 0 => x,
+                _ => mov.sum_rewards().to_f64() as f32,
+            };
+            let policy_evaln = *mov.move_evaluation();
             Fraction(
                 sum_rewards + explore_coef * policy_evaln,
                 (child_visits + 1) as f32)
         }).unwrap()
     }
+}
+
+/// The parent's average reward over its visited children, minus `reduction` times
+/// the square root of the total move evaluation of those children.
+fn first_play_value<'a, Spec: MCTS>(moves: Moves<'a, Spec>, reduction: f32) -> f32
+    where Spec::TreePolicy: TreePolicy<Spec, MoveEvaluation=f32>
+{
+    let mut sum_rewards = 0.0;
+    let mut visits = 0;
+    let mut visited_policy = 0.0;
+    for mov in moves.filter(|x| x.visits() > 0) {
+        sum_rewards += mov.sum_rewards().to_f64();
+        visits += mov.visits();
+        visited_policy += *mov.move_evaluation();
+    }
+    let parent_value = if visits == 0 { 0.0 } else { (sum_rewards / visits as f64) as f32 };
+    parent_value - reduction * visited_policy.sqrt()
+}
+
+impl<Spec: MCTS<TreePolicy=Self>> TreePolicy<Spec> for AlphaGoPolicy
+{
+    type ThreadLocalData = PolicyRng;
+    type MoveEvaluation = f32;
+
+    fn choose_child<'a>(&self, _: &Spec::State, moves: Moves<'a, Spec>, mut handle: SearchHandle<Spec>)
+        -> MoveInfoHandle<'a, Spec>
+    {
+        let is_root = handle.depth() == 0;
+        self.select_child(moves, &mut handle.thread_data().policy_data, is_root)
+    }
 
     fn validate_evaluations(&self, evalns: &[f32]) {
-        for &x in evalns {
-            assert!(x >= -1e-6,
-                "Move evaluation is {} (must be non-negative)",
-                x);
-        }
-        if evalns.len() >= 1 {
-            let evaln_sum: f32 = evalns.iter().sum();
-            assert!((evaln_sum - 1.0).abs() < 0.1,
-                "Sum of evaluations is {} (should sum to 1)",
-                evaln_sum);
-        }
+        validate_probabilities(evalns);
+    }
+}
+
+impl<Spec: MCTS<TreePolicy=Self>> TreePolicy<Spec> for UCB1TunedPolicy
+{
+    type ThreadLocalData = PolicyRng;
+    type MoveEvaluation = ();
+
+    fn choose_child<'a>(&self, _: &Spec::State, moves: Moves<'a, Spec>, mut handle: SearchHandle<Spec>)
+        -> MoveInfoHandle<'a, Spec>
+    {
+        self.select_child(moves, &mut handle.thread_data().policy_data)
+    }
+
+    fn needs_sum_of_squares(&self) -> bool {
+        true
+    }
+}
+
+impl<Spec: MCTS<TreePolicy=Self>> TreePolicy<Spec> for VariancePUCTPolicy
+{
+    type ThreadLocalData = PolicyRng;
+    type MoveEvaluation = f32;
+
+    fn choose_child<'a>(&self, _: &Spec::State, moves: Moves<'a, Spec>, mut handle: SearchHandle<Spec>)
+        -> MoveInfoHandle<'a, Spec>
+    {
+        self.select_child(moves, &mut handle.thread_data().policy_data)
+    }
+
+    fn validate_evaluations(&self, evalns: &[f32]) {
+        validate_probabilities(evalns);
+    }
+
+    fn needs_sum_of_squares(&self) -> bool {
+        true
     }
 }
 
@@ -128,12 +447,45 @@ impl PolicyRng {
         Self {rng}
     }
 
+    /// A generator whose sequence is determined by `seed`. Nearby seeds give unrelated sequences.
+    pub fn with_seed(seed: u64) -> Self {
+        // SplitMix64 spreads the seed over the 128 bits of XorShift's state.
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        let (a, b) = (next(), next());
+        let words = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32];
+        if words == [0; 4] {
+            // XorShift can't start from an all-zero state.
+            return Self::new();
+        }
+        Self {rng: SeedableRng::from_seed(words)}
+    }
+
+    /// Samples an index with the given probabilities, which should add up to 1.
+    pub fn sample_index(&mut self, probabilities: &[f64]) -> usize {
+        let mut x = self.rng.gen::<f64>();
+        for (i, &p) in probabilities.iter().enumerate() {
+            if x < p {
+                return i;
+            }
+            x -= p;
+        }
+        // Rounding can leave a little of `x` over.
+        probabilities.iter().rposition(|&p| p > 0.0).unwrap()
+    }
+
     pub fn select_by_key<T, Iter, KeyFn>(&mut self, elts: Iter, mut key_fn: KeyFn) -> Option<T>
         where Iter: Iterator<Item=T>, KeyFn: FnMut(&T) -> Fraction
     {
         let mut choice = None;
         let mut num_optimal: u32 = 0;
-        let mut best_so_far: Fraction = std::f32::NEG_INFINITY.into();
+        let mut best_so_far: Fraction = f32::NEG_INFINITY.into();
         for elt in elts {
             let score = key_fn(&elt);
             let a = score.0 * best_so_far.1;
@@ -158,3 +510,264 @@ impl Default for PolicyRng {
         Self::new()
     }
 }
+
+/// Thread-local data of a tree policy that can be seeded, so that different search threads
+/// (or the trees of a root-parallel search) make different random choices.
+pub trait SeedableThreadData {
+    fn seed(&mut self, seed: u64);
+}
+
+impl SeedableThreadData for () {
+    fn seed(&mut self, _: u64) {}
+}
+
+impl SeedableThreadData for PolicyRng {
+    fn seed(&mut self, seed: u64) {
+        *self = Self::with_seed(seed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // A multi-armed bandit: the only decision is which arm to pull,
+    // and arm i pays 1 with probability ARMS[i] and 0 otherwise.
+    const ARMS: [f64; 5] = [0.3, 0.5, 0.55, 0.6, 0.4];
+    const BEST_ARM: usize = 3;
+
+    #[derive(Clone)]
+    struct Bandit(Option<usize>);
+
+    impl GameState for Bandit {
+        type Move = usize;
+        type Player = ();
+        type MoveList = Vec<usize>;
+
+        fn current_player(&self) -> Self::Player {}
+        fn available_moves(&self) -> Vec<usize> {
+            match self.0 {
+                None => (0..ARMS.len()).collect(),
+                Some(_) => vec![],
+            }
+        }
+        fn make_move(&mut self, mov: &usize) {
+            self.0 = Some(*mov);
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct CountingGame(i64);
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Move {
+        Add, Sub
+    }
+
+    impl GameState for CountingGame {
+        type Move = Move;
+        type Player = ();
+        type MoveList = Vec<Move>;
+
+        fn current_player(&self) -> Self::Player {}
+        fn available_moves(&self) -> Vec<Move> {
+            if self.0 == 100 {
+                vec![]
+            } else {
+                vec![Move::Add, Move::Sub]
+            }
+        }
+        fn make_move(&mut self, mov: &Self::Move) {
+            match *mov {
+                Move::Add => self.0 += 1,
+                Move::Sub => self.0 -= 1,
+            }
+        }
+    }
+
+    trait UniformPrior {
+        fn uniform(num_moves: usize) -> Vec<Self> where Self: Sized;
+    }
+    impl UniformPrior for () {
+        fn uniform(num_moves: usize) -> Vec<()> {
+            vec![(); num_moves]
+        }
+    }
+    impl UniformPrior for f32 {
+        fn uniform(num_moves: usize) -> Vec<f32> {
+            vec![1.0 / num_moves as f32; num_moves]
+        }
+    }
+
+    struct BanditEvaluator {
+        rng: Mutex<XorShiftRng>,
+    }
+
+    impl BanditEvaluator {
+        fn new() -> Self {
+            Self {rng: Mutex::new(SeedableRng::from_seed([5, 6, 7, 8]))}
+        }
+        fn evaluate(&self, state: &Bandit) -> f64 {
+            match state.0 {
+                Some(arm) if self.rng.lock().unwrap().gen::<f64>() < ARMS[arm] => 1.0,
+                _ => 0.0,
+            }
+        }
+        fn reevaluate(&self, state: &Bandit, _: f64) -> f64 {
+            self.evaluate(state)
+        }
+    }
+
+    struct CountingEvaluator;
+
+    impl CountingEvaluator {
+        fn new() -> Self {
+            CountingEvaluator
+        }
+        fn evaluate(&self, state: &CountingGame) -> f64 {
+            state.0 as f64
+        }
+        fn reevaluate(&self, _: &CountingGame, evaln: f64) -> f64 {
+            evaln
+        }
+    }
+
+    macro_rules! test_mcts {
+        ($name:ident, $game:ty, $eval:ty, $policy:ty) => {
+            #[derive(Default)]
+            struct $name;
+
+            impl MCTS for $name {
+                type State = $game;
+                type Reward = f64;
+                type Eval = $eval;
+                type NodeData = ();
+                type ExtraThreadData = ();
+                type TreePolicy = $policy;
+                type TranspositionTable = ();
+                type PlayoutData = ();
+            }
+
+            impl Evaluator<$name> for $eval {
+                type StateEvaluation = f64;
+
+                fn evaluate_new_state(&self, state: &$game, moves: &MoveList<$name>,
+                    _: Option<SearchHandle<$name>>)
+                    -> (Vec<MoveEvaluation<$name>>, f64) {
+                    (UniformPrior::uniform(moves.len()), self.evaluate(state))
+                }
+                fn interpret_evaluation_for_player(&self, evaln: &f64, _player: &()) -> f64 {
+                    *evaln
+                }
+                fn evaluate_existing_state(&self, state: &$game, evaln: &f64, _: SearchHandle<$name>) -> f64 {
+                    self.reevaluate(state, *evaln)
+                }
+            }
+        }
+    }
+
+    /// `AlphaGoPolicy` as it was before `PUCTParameters`: a constant exploration constant,
+    /// and a value of 0 for unvisited children.
+    struct BaselineAlphaGoPolicy(f32);
+
+    impl<Spec: MCTS<TreePolicy=Self>> TreePolicy<Spec> for BaselineAlphaGoPolicy {
+        type ThreadLocalData = PolicyRng;
+        type MoveEvaluation = f32;
+
+        fn choose_child<'a>(&self, _: &Spec::State, moves: Moves<'a, Spec>, mut handle: SearchHandle<Spec>)
+            -> MoveInfoHandle<'a, Spec>
+        {
+            let total_visits = moves.map(|x| x.visits()).sum::<u64>() + 1;
+            let sqrt_total_visits = (total_visits as f32).sqrt();
+            let explore_coef = self.0 * sqrt_total_visits;
+            handle.thread_data().policy_data.select_by_key(moves, |mov| {
+                let sum_rewards = mov.sum_rewards().to_f64() as f32;
+                let child_visits = mov.visits();
+                let policy_evaln = *mov.move_evaluation();
+                Fraction(
+                    sum_rewards + explore_coef * policy_evaln,
+                    (child_visits + 1) as f32)
+            }).unwrap()
+        }
+    }
+
+    test_mcts!(BanditUCT, Bandit, BanditEvaluator, UCTPolicy);
+    test_mcts!(BanditBaselineAlphaGo, Bandit, BanditEvaluator, BaselineAlphaGoPolicy);
+    test_mcts!(BanditTuned, Bandit, BanditEvaluator, UCB1TunedPolicy);
+    test_mcts!(BanditAlphaGo, Bandit, BanditEvaluator, AlphaGoPolicy);
+    test_mcts!(BanditVariancePUCT, Bandit, BanditEvaluator, VariancePUCTPolicy);
+    test_mcts!(CountingUCT, CountingGame, CountingEvaluator, UCTPolicy);
+    test_mcts!(CountingTuned, CountingGame, CountingEvaluator, UCB1TunedPolicy);
+    test_mcts!(CountingAlphaGo, CountingGame, CountingEvaluator, AlphaGoPolicy);
+    test_mcts!(CountingVariancePUCT, CountingGame, CountingEvaluator, VariancePUCTPolicy);
+
+    /// The expected reward lost by not always pulling the best arm.
+    fn regret<Spec>(policy: Spec::TreePolicy) -> f64
+        where Spec: MCTS<State=Bandit, Eval=BanditEvaluator, TranspositionTable=(), ExtraThreadData=()> + Default,
+            BanditEvaluator: Evaluator<Spec>, TreePolicyThreadData<Spec>: Default
+    {
+        let mut mcts = MCTSManager::new(Bandit(None), Spec::default(), BanditEvaluator::new(), policy, ());
+        mcts.playout_n(10000);
+        assert_eq!(mcts.best_move(), Some(BEST_ARM));
+        mcts.tree().root_node().moves()
+            .map(|mov| mov.visits() as f64 * (ARMS[BEST_ARM] - ARMS[*mov.get_move()]))
+            .sum()
+    }
+
+    /// The visits of each arm after a short search.
+    fn arm_visits<Spec>(policy: Spec::TreePolicy) -> Vec<u64>
+        where Spec: MCTS<State=Bandit, Eval=BanditEvaluator, TranspositionTable=(), ExtraThreadData=()> + Default,
+            BanditEvaluator: Evaluator<Spec>, TreePolicyThreadData<Spec>: Default
+    {
+        let mut mcts = MCTSManager::new(Bandit(None), Spec::default(), BanditEvaluator::new(), policy, ());
+        mcts.playout_n(2000);
+        mcts.tree().root_node().moves().map(|mov| mov.visits()).collect()
+    }
+
+    fn assert_counts_up<Spec>(policy: Spec::TreePolicy)
+        where Spec: MCTS<State=CountingGame, Eval=CountingEvaluator, TranspositionTable=(), ExtraThreadData=()> + Default,
+            CountingEvaluator: Evaluator<Spec>, TreePolicyThreadData<Spec>: Default
+    {
+        let mut mcts = MCTSManager::new(CountingGame(0), Spec::default(), CountingEvaluator::new(), policy, ());
+        mcts.playout_n(10000);
+        assert_eq!(mcts.principal_variation(5), vec![Move::Add; 5]);
+    }
+
+    #[test]
+    fn counting_game() {
+        assert_counts_up::<CountingUCT>(UCTPolicy::new(0.5));
+        assert_counts_up::<CountingTuned>(UCB1TunedPolicy::new(1.0, 10.0));
+        assert_counts_up::<CountingAlphaGo>(AlphaGoPolicy::new(5.0));
+        let params = PUCTParameters {cpuct_factor: 2.0, fpu_reduction: Some(0.5), ..PUCTParameters::new(5.0)};
+        assert_counts_up::<CountingAlphaGo>(AlphaGoPolicy::with_parameters(PUCTParameters::new(5.0), params));
+        assert_counts_up::<CountingVariancePUCT>(VariancePUCTPolicy::new(5.0, 1.0));
+    }
+
+    #[test]
+    fn variance_aware_policies_have_less_regret() {
+        let uct = regret::<BanditUCT>(UCTPolicy::new(1.0));
+        let tuned = regret::<BanditTuned>(UCB1TunedPolicy::new(1.0, 1.0));
+        let alphago = regret::<BanditAlphaGo>(AlphaGoPolicy::new(1.0));
+        let variance_puct = regret::<BanditVariancePUCT>(VariancePUCTPolicy::new(1.0, 0.25));
+        let regrets = format!("regret: UCT {} UCB1-Tuned {} AlphaGo {} variance PUCT {}",
+            uct, tuned, alphago, variance_puct);
+        assert!(tuned < uct, "{}", regrets);
+        assert!(variance_puct < alphago, "{}", regrets);
+    }
+
+    #[test]
+    fn default_puct_parameters_are_the_alphago_formula() {
+        let baseline = arm_visits::<BanditBaselineAlphaGo>(BaselineAlphaGoPolicy(1.0));
+        let with_parameters = |params: PUCTParameters| {
+            arm_visits::<BanditAlphaGo>(AlphaGoPolicy::with_parameters(params.clone(), params))
+        };
+        assert_eq!(arm_visits::<BanditAlphaGo>(AlphaGoPolicy::new(1.0)), baseline);
+        assert_eq!(with_parameters(PUCTParameters::new(1.0)), baseline);
+        let fpu = with_parameters(PUCTParameters {fpu_reduction: Some(0.2), ..PUCTParameters::new(1.0)});
+        let dynamic = with_parameters(PUCTParameters {cpuct_factor: 2.0, cpuct_base: 100.0, ..PUCTParameters::new(1.0)});
+        assert!(fpu != baseline, "{:?}", fpu);
+        // An exploration constant that grows with the visits spreads them over more arms.
+        assert!(dynamic[BEST_ARM] < baseline[BEST_ARM], "{:?} {:?}", dynamic, baseline);
+    }
+}